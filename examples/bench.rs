@@ -0,0 +1,154 @@
+//! Load generator for comparing server builds, in the spirit of
+//! redis-benchmark: `cargo run --release --example bench -- --clients 50`.
+
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[derive(Debug, Parser)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(long, default_value_t = 6379)]
+    port: u16,
+
+    /// Concurrent connections.
+    #[arg(long, default_value_t = 50)]
+    clients: usize,
+
+    /// Requests sent by each connection, alternating SET and GET.
+    #[arg(long, default_value_t = 20_000)]
+    requests: usize,
+
+    /// Requests written before waiting for their replies.
+    #[arg(long, default_value_t = 1)]
+    pipeline: usize,
+
+    /// Distinct keys the requests are spread over.
+    #[arg(long, default_value_t = 10_000)]
+    keyspace: usize,
+}
+
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+    }
+    out
+}
+
+/// Counts complete replies at the start of `buf`, returning how many there
+/// are and how many bytes they take. Only handles the reply types SET and
+/// GET produce.
+fn count_replies(buf: &[u8]) -> (usize, usize) {
+    let mut pos = 0;
+    let mut count = 0;
+
+    while let Some(end) = buf[pos..].windows(2).position(|w| w == b"\r\n") {
+        let line = &buf[pos..pos + end];
+        let mut next = pos + end + 2;
+
+        if line.first() == Some(&b'$') {
+            let len: i64 = std::str::from_utf8(&line[1..]).unwrap().parse().unwrap();
+            if len >= 0 {
+                next += len as usize + 2;
+                if next > buf.len() {
+                    break;
+                }
+            }
+        }
+
+        pos = next;
+        count += 1;
+    }
+
+    (count, pos)
+}
+
+async fn client(args: &Args, id: usize) -> anyhow::Result<Vec<Duration>> {
+    let mut stream = TcpStream::connect((args.host.as_str(), args.port)).await?;
+    stream.set_nodelay(true)?;
+
+    let mut latencies = Vec::with_capacity(args.requests / args.pipeline + 1);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut sent = 0;
+
+    while sent < args.requests {
+        let batch = args.pipeline.min(args.requests - sent);
+        let mut out = vec![];
+        for i in sent..sent + batch {
+            let key = format!("key:{}", (id * args.requests + i) % args.keyspace);
+            if i % 2 == 0 {
+                out.extend(encode(&["SET", &key, "value"]));
+            } else {
+                out.extend(encode(&["GET", &key]));
+            }
+        }
+
+        let start = Instant::now();
+        stream.write_all(&out).await?;
+
+        let (mut received, mut filled) = (0, 0);
+        while received < batch {
+            let n = stream.read(&mut buf[filled..]).await?;
+            anyhow::ensure!(n > 0, "Server closed the connection");
+            filled += n;
+
+            let (count, consumed) = count_replies(&buf[..filled]);
+            received += count;
+            buf.copy_within(consumed..filled, 0);
+            filled -= consumed;
+            if filled == buf.len() {
+                buf.resize(buf.len() * 2, 0);
+            }
+        }
+
+        latencies.push(start.elapsed());
+        sent += batch;
+    }
+
+    Ok(latencies)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args: &'static Args = Box::leak(Box::new(Args::parse()));
+
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..args.clients)
+        .map(|id| tokio::spawn(client(args, id)))
+        .collect();
+
+    let mut latencies = vec![];
+    for task in tasks {
+        latencies.extend(task.await??);
+    }
+    let elapsed = start.elapsed();
+
+    latencies.sort();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    let total = args.clients * args.requests;
+
+    println!(
+        "{} requests, {} clients, pipeline {}",
+        total, args.clients, args.pipeline
+    );
+    println!(
+        "{:.0} requests/s in {:.2?}",
+        total as f64 / elapsed.as_secs_f64(),
+        elapsed
+    );
+    println!(
+        "batch latency p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(0.5),
+        percentile(0.99),
+        percentile(1.0)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,291 @@
+use std::{
+    time::{Instant, SystemTime, UNIX_EPOCH},
+    vec,
+};
+
+use anyhow::Context;
+
+use super::{client, table::COMMANDS, Call};
+use crate::{
+    handler::{ClientType, Session},
+    store::{LogEntry, User, CATEGORIES},
+    Command, RedisError, Resp, Store,
+};
+
+#[derive(Debug)]
+pub(crate) enum Op {
+    SetUser { name: String, rules: Vec<String> },
+    GetUser(String),
+    DelUser(Vec<String>),
+    List,
+    Users,
+    WhoAmI,
+    Cat(Option<String>),
+    DryRun { name: String, args: Vec<String> },
+    Log(Option<usize>),
+    LogReset,
+    Load,
+    Save,
+}
+
+#[derive(Debug)]
+pub(crate) struct Acl {
+    op: Op,
+}
+
+impl Command for Acl {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let sub = args.next().context("Missing subcommand for ACL command")?;
+
+        let op = match sub.to_lowercase().as_str() {
+            "setuser" => Op::SetUser {
+                name: args
+                    .next()
+                    .context("Missing argument 'username' for ACL SETUSER command")?,
+                rules: args.collect(),
+            },
+            "getuser" => Op::GetUser(
+                args.next()
+                    .context("Missing argument 'username' for ACL GETUSER command")?,
+            ),
+            "deluser" => Op::DelUser(args.collect()),
+            "list" => Op::List,
+            "users" => Op::Users,
+            "whoami" => Op::WhoAmI,
+            "cat" => Op::Cat(args.next()),
+            "dryrun" => Op::DryRun {
+                name: args
+                    .next()
+                    .context("Missing argument 'username' for ACL DRYRUN command")?,
+                args: args.collect(),
+            },
+            "log" => match args.next() {
+                None => Op::Log(None),
+                Some(arg) if arg.eq_ignore_ascii_case("reset") => Op::LogReset,
+                Some(count) => Op::Log(Some(count.parse().map_err(|_| RedisError::NotInteger)?)),
+            },
+            "load" => Op::Load,
+            "save" => Op::Save,
+            _ => anyhow::bail!("unknown subcommand '{}'. Try ACL HELP.", sub),
+        };
+
+        Ok(Acl { op })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Acl { op } = *self;
+
+        Ok(invoke(store, session, op)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(store: &mut Store, session: &mut Session, op: Op) -> anyhow::Result<Resp> {
+    let reply = match op {
+        Op::SetUser { name, rules } => {
+            store.acl.set_user(&name, &rules)?;
+            Resp::ok()
+        }
+        Op::GetUser(name) => match store.acl.user(&name) {
+            Some(user) => describe(user),
+            None => Resp::null(),
+        },
+        Op::DelUser(names) => {
+            let mut deleted = 0;
+            for name in names {
+                if store.acl.delete_user(&name)? {
+                    disconnect(store, session, &name);
+                    deleted += 1;
+                }
+            }
+            Resp::integer(deleted)
+        }
+        Op::List => Resp::array(store.acl.users().map(User::describe).collect()),
+        Op::Users => Resp::array(store.acl.users().map(|user| user.name.clone()).collect()),
+        Op::WhoAmI => Resp::bulk(&session.user),
+        Op::Cat(None) => Resp::array(CATEGORIES.iter().map(|c| c.to_string()).collect()),
+        Op::Cat(Some(category)) => {
+            let category = category.to_lowercase();
+            if !CATEGORIES.contains(&category.as_str()) {
+                anyhow::bail!("Unknown category '{}'", category);
+            }
+            let category = format!("@{}", category);
+            Resp::array(
+                COMMANDS
+                    .iter()
+                    .flat_map(|spec| std::iter::once(spec).chain(spec.subcommands.iter()))
+                    .filter(|spec| spec.categories.contains(&category.as_str()))
+                    .map(|spec| spec.name.to_string())
+                    .collect(),
+            )
+        }
+        Op::DryRun { name, args } => {
+            let user = store
+                .acl
+                .user(&name)
+                .with_context(|| format!("User '{}' not found", name))?;
+            let command = args.first().map_or("", String::as_str);
+            if super::lookup(command).is_none() {
+                anyhow::bail!("Command '{}' not found", command);
+            }
+            let call = Call::parse(args)?;
+            match user.check(&call) {
+                Ok(()) => Resp::ok(),
+                Err(denied) => Resp::bulk(denied.describe(&name)),
+            }
+        }
+        Op::Log(count) => Resp::Array(
+            store
+                .acl
+                .log_entries()
+                .take(count.unwrap_or(usize::MAX))
+                .map(log_entry)
+                .collect(),
+        ),
+        Op::LogReset => {
+            store.acl.reset_log();
+            Resp::ok()
+        }
+        Op::Load => {
+            let path = acl_file(store)?;
+            store.acl.load(&path)?;
+            // Clients of users that no longer exist can't stay logged in
+            let removed: Vec<String> = std::iter::once(&*session)
+                .chain(store.clients.values())
+                .filter(|client| store.acl.user(&client.user).is_none())
+                .map(|client| client.user.clone())
+                .collect();
+            for name in removed {
+                disconnect(store, session, &name);
+            }
+            Resp::ok()
+        }
+        Op::Save => {
+            let path = acl_file(store)?;
+            store.acl.save(&path)?;
+            Resp::ok()
+        }
+    };
+
+    Ok(reply)
+}
+
+/// Checks the session's user may run `call`, logging the denial if not.
+pub(crate) fn check(store: &mut Store, session: &Session, call: &Call) -> anyhow::Result<()> {
+    let context = match session.transaction {
+        Some(_) => "multi",
+        None => "toplevel",
+    };
+    check_in(store, session, call, context)
+}
+
+/// Checks a call queued by MULTI again as EXEC runs it, since the user's
+/// permissions may have changed in between.
+pub(crate) fn check_queued(
+    store: &mut Store,
+    session: &Session,
+    call: &Call,
+) -> anyhow::Result<()> {
+    check_in(store, session, call, "multi")
+}
+
+fn check_in(
+    store: &mut Store,
+    session: &Session,
+    call: &Call,
+    context: &'static str,
+) -> anyhow::Result<()> {
+    // The master link replays whatever the master ran
+    if session.role == ClientType::Master {
+        return Ok(());
+    }
+
+    let denied = match store.acl.user(&session.user) {
+        Some(user) => match user.check(call) {
+            Ok(()) => return Ok(()),
+            Err(denied) => denied,
+        },
+        None => return Err(RedisError::NoAuth.into()),
+    };
+
+    store.acl.log(
+        denied.reason(),
+        context,
+        denied.object(),
+        &session.user,
+        client::describe(session, Instant::now()),
+    );
+
+    Err(denied.error(&session.user).into())
+}
+
+fn acl_file(store: &Store) -> anyhow::Result<String> {
+    store.config.aclfile().map(str::to_string).context(
+        "This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.",
+    )
+}
+
+/// Closes the connections logged in as `name`.
+fn disconnect(store: &Store, session: &Session, name: &str) {
+    for client in store.clients.values().filter(|client| client.user == name) {
+        client.killed.notify_one();
+    }
+    // The caller's connection closes once this reply is written
+    if session.user == name {
+        session.killed.notify_one();
+    }
+}
+
+/// Reply for ACL GETUSER.
+fn describe(user: &User) -> Resp {
+    Resp::Map(vec![
+        (
+            Resp::bulk("flags"),
+            Resp::array(user.flags().into_iter().map(str::to_string).collect()),
+        ),
+        (
+            Resp::bulk("passwords"),
+            Resp::array(user.passwords().to_vec()),
+        ),
+        (Resp::bulk("commands"), Resp::bulk(user.command_rules())),
+        (Resp::bulk("keys"), Resp::bulk(user.key_rules())),
+        (Resp::bulk("channels"), Resp::bulk(user.channel_rules())),
+        (Resp::bulk("selectors"), Resp::Array(vec![])),
+    ])
+}
+
+/// Reply entry for ACL LOG.
+fn log_entry(entry: &LogEntry) -> Resp {
+    let millis = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64)
+    };
+    let age = SystemTime::now()
+        .duration_since(entry.created)
+        .unwrap_or_default();
+
+    Resp::Map(vec![
+        (Resp::bulk("count"), Resp::integer(entry.count)),
+        (Resp::bulk("reason"), Resp::bulk(entry.reason)),
+        (Resp::bulk("context"), Resp::bulk(entry.context)),
+        (Resp::bulk("object"), Resp::bulk(&entry.object)),
+        (Resp::bulk("username"), Resp::bulk(&entry.username)),
+        (Resp::bulk("age-seconds"), Resp::Double(age.as_secs_f64())),
+        (Resp::bulk("client-info"), Resp::bulk(&entry.client_info)),
+        (Resp::bulk("entry-id"), Resp::Integer(entry.entry_id as i64)),
+        (
+            Resp::bulk("timestamp-created"),
+            Resp::Integer(millis(entry.created)),
+        ),
+        (
+            Resp::bulk("timestamp-last-updated"),
+            Resp::Integer(millis(entry.updated)),
+        ),
+    ])
+}
@@ -0,0 +1,89 @@
+use std::{time::Instant, vec};
+
+use anyhow::Context;
+
+use super::client;
+use crate::{handler::Session, store::User, Command, RedisError, Resp, Store};
+
+/// User connections start out as; `requirepass` sets its password.
+pub(crate) const DEFAULT_USER: &str = "default";
+
+#[derive(Debug)]
+pub(crate) struct Auth {
+    username: Option<String>,
+    password: String,
+}
+
+impl Command for Auth {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let first = args
+            .next()
+            .context("Missing argument 'password' for AUTH command")?;
+
+        let (username, password) = match args.next() {
+            Some(password) => (Some(first), password),
+            None => (None, first),
+        };
+        if args.next().is_some() {
+            return Err(RedisError::Syntax.into());
+        }
+
+        Ok(Auth { username, password })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Auth { username, password } = *self;
+
+        Ok(invoke(store, session, username, &password)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(
+    store: &mut Store,
+    session: &mut Session,
+    username: Option<String>,
+    password: &str,
+) -> anyhow::Result<Resp> {
+    let default_nopass = store.acl.user(DEFAULT_USER).is_some_and(User::is_nopass);
+    if username.is_none() && default_nopass {
+        anyhow::bail!("AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?");
+    }
+
+    authenticate(
+        store,
+        session,
+        username.as_deref().unwrap_or(DEFAULT_USER),
+        password,
+    )?;
+
+    Ok(Resp::ok())
+}
+
+/// Authenticates the session, as done by AUTH and HELLO AUTH. Failed
+/// attempts are recorded in the ACL LOG.
+pub(crate) fn authenticate(
+    store: &mut Store,
+    session: &mut Session,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<()> {
+    if !store.acl.authenticate(username, password) {
+        let client_info = client::describe(session, Instant::now());
+        store
+            .acl
+            .log("auth", "toplevel", "AUTH", username, client_info);
+        return Err(RedisError::WrongPass.into());
+    }
+
+    session.user = username.to_string();
+    session.authenticated = true;
+
+    Ok(())
+}
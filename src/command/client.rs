@@ -0,0 +1,507 @@
+use std::{
+    time::{Duration, Instant},
+    vec,
+};
+
+use anyhow::Context;
+
+use crate::{
+    handler::{ClientType, Session},
+    store::PauseMode,
+    Command, RedisError, Resp, Store,
+};
+
+#[derive(Debug)]
+pub(crate) enum Op {
+    Id,
+    SetName(String),
+    GetName,
+    List {
+        kind: Option<ClientType>,
+        ids: Vec<u64>,
+    },
+    Info,
+    Kill {
+        filters: Vec<Filter>,
+        /// `CLIENT KILL addr:port`, which replies OK rather than a count.
+        legacy: bool,
+    },
+    Pause {
+        timeout: u64,
+        mode: PauseMode,
+    },
+    Unpause,
+    NoEvict(bool),
+    Reply(ReplyMode),
+}
+
+#[derive(Debug)]
+pub(crate) enum Filter {
+    Id(u64),
+    Addr(String),
+    Laddr(String),
+    User(String),
+    Type(ClientType),
+    SkipMe(bool),
+    MaxAge(u64),
+}
+
+#[derive(Debug)]
+pub(crate) enum ReplyMode {
+    On,
+    Off,
+    Skip,
+}
+
+#[derive(Debug)]
+pub(crate) struct Client {
+    op: Op,
+}
+
+impl Command for Client {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let sub = args
+            .next()
+            .context("Missing subcommand for CLIENT command")?;
+
+        let op = match sub.to_lowercase().as_str() {
+            "id" => Op::Id,
+            "setname" => Op::SetName(
+                args.next()
+                    .context("Missing argument 'name' for CLIENT SETNAME command")?,
+            ),
+            "getname" => Op::GetName,
+            "list" => parse_list(args)?,
+            "info" => Op::Info,
+            "kill" => parse_kill(args)?,
+            "pause" => {
+                let timeout = args
+                    .next()
+                    .context("Missing argument 'timeout' for CLIENT PAUSE command")?
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("timeout is not an integer or out of range"))?;
+                let mode = match args.next().map(|mode| mode.to_lowercase()) {
+                    None => PauseMode::All,
+                    Some(mode) if mode == "all" => PauseMode::All,
+                    Some(mode) if mode == "write" => PauseMode::Write,
+                    Some(_) => return Err(RedisError::Syntax.into()),
+                };
+                Op::Pause { timeout, mode }
+            }
+            "unpause" => Op::Unpause,
+            "no-evict" => match parse_switch(args)?.as_str() {
+                "on" => Op::NoEvict(true),
+                "off" => Op::NoEvict(false),
+                _ => return Err(RedisError::Syntax.into()),
+            },
+            "reply" => match parse_switch(args)?.as_str() {
+                "on" => Op::Reply(ReplyMode::On),
+                "off" => Op::Reply(ReplyMode::Off),
+                "skip" => Op::Reply(ReplyMode::Skip),
+                _ => return Err(RedisError::Syntax.into()),
+            },
+            _ => anyhow::bail!("unknown subcommand '{}'. Try CLIENT HELP.", sub),
+        };
+
+        Ok(Client { op })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Client { op } = *self;
+
+        Ok(invoke(store, session, op)?
+            .map(|reply| reply.encode_with(session.protocol).into_bytes())
+            .unwrap_or_default())
+    }
+}
+
+fn parse_switch(args: &mut impl Iterator<Item = String>) -> anyhow::Result<String> {
+    let value = args.next().context("Missing argument for CLIENT command")?;
+
+    Ok(value.to_lowercase())
+}
+
+fn parse_list(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Op> {
+    let mut kind = None;
+    let mut ids = vec![];
+
+    while let Some(option) = args.next() {
+        match option.to_lowercase().as_str() {
+            "type" => {
+                let name = args.next().ok_or(RedisError::Syntax)?;
+                kind = Some(
+                    ClientType::parse(&name)
+                        .with_context(|| format!("Unknown client type '{}'", name))?,
+                );
+            }
+            "id" => {
+                for id in args.by_ref() {
+                    ids.push(
+                        id.parse()
+                            .map_err(|_| anyhow::anyhow!("Invalid client ID"))?,
+                    );
+                }
+            }
+            _ => return Err(RedisError::Syntax.into()),
+        }
+    }
+
+    Ok(Op::List { kind, ids })
+}
+
+fn parse_kill(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Op> {
+    let args: Vec<String> = args.collect();
+    if let [addr] = args.as_slice() {
+        return Ok(Op::Kill {
+            filters: vec![Filter::Addr(addr.to_string())],
+            legacy: true,
+        });
+    }
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(RedisError::Syntax.into());
+    }
+
+    let mut filters = vec![];
+    for pair in args.chunks(2) {
+        let value = pair[1].to_string();
+        let filter = match pair[0].to_lowercase().as_str() {
+            "id" => Filter::Id(
+                value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("client-id should be greater than 0"))?,
+            ),
+            "addr" => Filter::Addr(value),
+            "laddr" => Filter::Laddr(value),
+            "user" => Filter::User(value),
+            "type" => Filter::Type(
+                ClientType::parse(&value)
+                    .with_context(|| format!("Unknown client type '{}'", value))?,
+            ),
+            "skipme" => match value.to_lowercase().as_str() {
+                "yes" => Filter::SkipMe(true),
+                "no" => Filter::SkipMe(false),
+                _ => return Err(RedisError::Syntax.into()),
+            },
+            "maxage" => Filter::MaxAge(value.parse().map_err(|_| RedisError::NotInteger)?),
+            _ => return Err(RedisError::Syntax.into()),
+        };
+        filters.push(filter);
+    }
+
+    Ok(Op::Kill {
+        filters,
+        legacy: false,
+    })
+}
+
+/// Client names are shown in CLIENT LIST, so they can't contain spaces.
+pub(crate) fn validate_name(name: &str) -> anyhow::Result<()> {
+    if name.chars().any(|c| !('!'..='~').contains(&c)) {
+        anyhow::bail!("Client names cannot contain spaces, newlines or special characters.");
+    }
+
+    Ok(())
+}
+
+/// Runs a CLIENT subcommand, returning `None` where nothing is replied.
+pub(crate) fn invoke(
+    store: &mut Store,
+    session: &mut Session,
+    op: Op,
+) -> anyhow::Result<Option<Resp>> {
+    let reply = match op {
+        Op::Id => Resp::Integer(session.id as i64),
+        Op::SetName(name) => {
+            validate_name(&name)?;
+            // An empty name clears it
+            session.name = (!name.is_empty()).then_some(name);
+            Resp::ok()
+        }
+        Op::GetName => match &session.name {
+            Some(name) => Resp::bulk(name),
+            None => Resp::null(),
+        },
+        Op::List { kind, ids } => {
+            let mut clients: Vec<&Session> = std::iter::once(&*session)
+                .chain(store.clients.values())
+                .filter(|client| kind.is_none_or(|kind| client.client_type() == kind))
+                .filter(|client| ids.is_empty() || ids.contains(&client.id))
+                .collect();
+            clients.sort_by_key(|client| client.id);
+
+            let now = Instant::now();
+            Resp::VerbatimString {
+                format: "txt".to_string(),
+                text: clients
+                    .into_iter()
+                    .map(|client| describe(client, now) + "\n")
+                    .collect(),
+            }
+        }
+        Op::Info => Resp::VerbatimString {
+            format: "txt".to_string(),
+            text: describe(session, Instant::now()) + "\n",
+        },
+        Op::Kill { filters, legacy } => {
+            // SKIPME defaults to yes, except in the legacy form
+            let skip_me = !legacy
+                && filters
+                    .iter()
+                    .rev()
+                    .find_map(|filter| match filter {
+                        Filter::SkipMe(skip) => Some(*skip),
+                        _ => None,
+                    })
+                    .unwrap_or(true);
+            let killed = kill(store, session, &filters, skip_me);
+            if !legacy {
+                Resp::integer(killed)
+            } else if killed > 0 {
+                Resp::ok()
+            } else {
+                anyhow::bail!("No such client");
+            }
+        }
+        Op::Pause { timeout, mode } => {
+            // Like Redis, the timeout has to fit in a signed millisecond count
+            let until = i64::try_from(timeout)
+                .ok()
+                .and_then(|_| {
+                    tokio::time::Instant::now().checked_add(Duration::from_millis(timeout))
+                })
+                .ok_or_else(|| anyhow::anyhow!("timeout is out of range"))?;
+            store.pause.pause(mode, until);
+            Resp::ok()
+        }
+        Op::Unpause => {
+            store.pause.unpause();
+            Resp::ok()
+        }
+        Op::NoEvict(enabled) => {
+            session.no_evict = enabled;
+            Resp::ok()
+        }
+        Op::Reply(ReplyMode::On) => {
+            session.reply_off = false;
+            Resp::ok()
+        }
+        Op::Reply(ReplyMode::Off) => {
+            session.reply_off = true;
+            return Ok(None);
+        }
+        Op::Reply(ReplyMode::Skip) => {
+            session.skip_reply = true;
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(reply))
+}
+
+/// Closes every client matching all of `filters`, returning how many.
+fn kill(store: &Store, session: &Session, filters: &[Filter], skip_me: bool) -> usize {
+    let now = Instant::now();
+    let matches = |client: &Session| {
+        filters.iter().all(|filter| match filter {
+            Filter::Id(id) => client.id == *id,
+            Filter::Addr(addr) => client.addr == *addr,
+            Filter::Laddr(laddr) => client.laddr == *laddr,
+            Filter::User(user) => client.user == *user,
+            Filter::Type(kind) => client.client_type() == *kind,
+            Filter::SkipMe(_) => true,
+            Filter::MaxAge(age) => now.duration_since(client.created).as_secs() >= *age,
+        })
+    };
+
+    let mut killed = 0;
+    for client in store.clients.values().filter(|client| matches(client)) {
+        client.killed.notify_one();
+        killed += 1;
+    }
+    // The caller's connection closes once this reply is written
+    if !skip_me && matches(session) {
+        session.killed.notify_one();
+        killed += 1;
+    }
+
+    killed
+}
+
+/// One line of CLIENT LIST.
+pub(crate) fn describe(client: &Session, now: Instant) -> String {
+    let mut flags = String::new();
+    match client.client_type() {
+        ClientType::Master => flags.push('M'),
+        ClientType::Replica => flags.push('S'),
+        ClientType::Pubsub => flags.push('P'),
+        ClientType::Normal => {}
+    }
+    if client.transaction.is_some() {
+        flags.push('x');
+    }
+    if client.no_evict {
+        flags.push('e');
+    }
+    if flags.is_empty() {
+        flags.push('N');
+    }
+
+    format!(
+        "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub={} psub={} ssub={} multi={} qbuf={} omem={} cmd={} user={} resp={}",
+        client.id,
+        client.addr,
+        client.laddr,
+        client.name.as_deref().unwrap_or(""),
+        now.duration_since(client.created).as_secs(),
+        now.duration_since(client.last_interaction).as_secs(),
+        flags,
+        client.db,
+        client.channels.len(),
+        client.patterns.len(),
+        client.shard_channels.len(),
+        client
+            .transaction
+            .as_ref()
+            .map_or(-1, |transaction| transaction.commands.len() as i64),
+        client.qbuf,
+        client.omem,
+        client.last_command,
+        client.user,
+        client.protocol.version(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::store::load_config;
+
+    async fn store() -> Store {
+        Store::init(load_config(&[]).unwrap()).await.unwrap()
+    }
+
+    fn session(addr: &str) -> Session {
+        let (sender, _) = mpsc::unbounded_channel();
+        Session::new(sender, addr.to_string(), "127.0.0.1:6379".to_string())
+    }
+
+    fn client(args: &str) -> Op {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        Client::parse(&mut args.into_iter()).unwrap().op
+    }
+
+    /// Takes the notification CLIENT KILL left for `session`, if any.
+    async fn killed(session: &Session) -> bool {
+        let notified = session.killed.notified();
+        tokio::time::timeout(Duration::ZERO, notified).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_kill_filters() {
+        let mut store = store().await;
+        let mut me = session("10.0.0.1:1000");
+        let other = session("10.0.0.1:1000");
+        let mut subscriber = session("10.0.0.2:2000");
+        subscriber.channels.insert("news".to_string());
+        let (other_id, subscriber_id) = (other.id, subscriber.id);
+        store.clients.insert(other.id, other);
+        store.clients.insert(subscriber.id, subscriber);
+
+        // SKIPME defaults to yes
+        let reply = invoke(&mut store, &mut me, client("KILL ADDR 10.0.0.1:1000"));
+        assert_eq!(reply.unwrap(), Some(Resp::integer(1)));
+        assert!(killed(&store.clients[&other_id]).await);
+        assert!(!killed(&me).await);
+
+        let reply = invoke(
+            &mut store,
+            &mut me,
+            client("KILL ADDR 10.0.0.1:1000 SKIPME no"),
+        );
+        assert_eq!(reply.unwrap(), Some(Resp::integer(2)));
+        assert!(killed(&store.clients[&other_id]).await);
+        assert!(killed(&me).await);
+
+        let reply = invoke(&mut store, &mut me, client("KILL TYPE pubsub"));
+        assert_eq!(reply.unwrap(), Some(Resp::integer(1)));
+        assert!(killed(&store.clients[&subscriber_id]).await);
+        assert!(!killed(&store.clients[&other_id]).await);
+
+        // The legacy form doesn't skip the caller and replies OK
+        let reply = invoke(&mut store, &mut me, client("KILL 10.0.0.1:1000"));
+        assert_eq!(reply.unwrap(), Some(Resp::ok()));
+        assert!(killed(&me).await);
+        let reply = invoke(&mut store, &mut me, client("KILL 10.0.0.9:1"));
+        assert_eq!(reply.unwrap_err().to_string(), "No such client");
+    }
+
+    #[tokio::test]
+    async fn test_list_and_reply_modes() {
+        let mut store = store().await;
+        let mut me = session("10.0.0.1:1000");
+        let mut subscriber = session("10.0.0.2:2000");
+        subscriber.channels.insert("news".to_string());
+        let subscriber_id = subscriber.id;
+        store.clients.insert(subscriber.id, subscriber);
+
+        let lines = |reply: Option<Resp>| match reply {
+            Some(Resp::VerbatimString { text, .. }) => text.lines().count(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        let list = invoke(&mut store, &mut me, client("LIST")).unwrap();
+        assert_eq!(lines(list), 2);
+        let list = invoke(&mut store, &mut me, client("LIST TYPE pubsub")).unwrap();
+        assert_eq!(lines(list), 1);
+        let ids = format!("LIST ID {} {}", me.id, subscriber_id + 100);
+        let list = invoke(&mut store, &mut me, client(&ids)).unwrap();
+        assert_eq!(lines(list), 1);
+
+        assert_eq!(
+            invoke(&mut store, &mut me, client("REPLY SKIP")).unwrap(),
+            None
+        );
+        assert!(me.skip_reply);
+        assert_eq!(
+            invoke(&mut store, &mut me, client("REPLY OFF")).unwrap(),
+            None
+        );
+        assert!(me.reply_off);
+        assert_eq!(
+            invoke(&mut store, &mut me, client("REPLY ON")).unwrap(),
+            Some(Resp::ok())
+        );
+        assert!(!me.reply_off);
+
+        let err = invoke(&mut store, &mut me, client("PAUSE 18446744073709551615"));
+        assert_eq!(err.unwrap_err().to_string(), "timeout is out of range");
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut client = session("10.0.0.1:1000");
+        client.name = Some("worker".to_string());
+        client.patterns.insert("news.*".to_string());
+        client.transaction = Some(Default::default());
+        client.no_evict = true;
+        client.db = 2;
+
+        let line = describe(&client, client.created);
+        assert_eq!(
+            line,
+            format!(
+                "id={} addr=10.0.0.1:1000 laddr=127.0.0.1:6379 name=worker age=0 idle=0 flags=Pxe db=2 sub=0 psub=1 ssub=0 multi=0 qbuf=0 omem=0 cmd=NULL user=default resp=2",
+                client.id
+            )
+        );
+
+        let plain = session("10.0.0.2:2000");
+        assert!(describe(&plain, plain.created).contains(" flags=N "));
+    }
+}
@@ -0,0 +1,108 @@
+use std::vec;
+
+use anyhow::Context;
+
+use super::table::{self, COMMANDS};
+use crate::{handler::Session, Command, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) enum Query {
+    All,
+    Count,
+    Info { names: Vec<String> },
+    Docs { names: Vec<String> },
+    GetKeys { args: Vec<String> },
+}
+
+#[derive(Debug)]
+pub(crate) struct Introspect {
+    query: Query,
+}
+
+impl Command for Introspect {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let query = match args.next() {
+            None => Query::All,
+            Some(sub) => match sub.to_lowercase().as_str() {
+                "count" => Query::Count,
+                "info" => Query::Info {
+                    names: args.collect(),
+                },
+                "docs" => Query::Docs {
+                    names: args.collect(),
+                },
+                "getkeys" => Query::GetKeys {
+                    args: args.collect(),
+                },
+                _ => anyhow::bail!("unknown subcommand '{}'. Try COMMAND HELP.", sub),
+            },
+        };
+
+        Ok(Introspect { query })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        _store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Introspect { query } = *self;
+
+        Ok(invoke(query)?.encode_with(session.protocol).into_bytes())
+    }
+}
+
+pub(crate) fn invoke(query: Query) -> anyhow::Result<Resp> {
+    let reply = match query {
+        Query::All => Resp::Array(COMMANDS.iter().map(|spec| spec.info()).collect()),
+        Query::Count => Resp::integer(COMMANDS.len()),
+        Query::Info { names } if names.is_empty() => {
+            Resp::Array(COMMANDS.iter().map(|spec| spec.info()).collect())
+        }
+        Query::Info { names } => Resp::Array(
+            names
+                .iter()
+                .map(|name| match table::lookup(name) {
+                    Some(spec) => spec.info(),
+                    None => Resp::NullArray,
+                })
+                .collect(),
+        ),
+        Query::Docs { names } => {
+            let specs: Vec<_> = if names.is_empty() {
+                COMMANDS.iter().collect()
+            } else {
+                // Unknown names are left out rather than replied as nulls
+                names
+                    .iter()
+                    .filter_map(|name| table::lookup(name))
+                    .collect()
+            };
+            Resp::Map(
+                specs
+                    .into_iter()
+                    .map(|spec| (Resp::bulk(spec.name), spec.docs()))
+                    .collect(),
+            )
+        }
+        Query::GetKeys { args } => {
+            let spec = table::resolve(&args).context("Invalid command specified")?;
+            table::check_arity(&args).map_err(|_| {
+                anyhow::anyhow!("Invalid number of arguments specified for command")
+            })?;
+
+            let keys = spec.keys(&args);
+            if keys.is_empty() {
+                anyhow::bail!("The command has no key arguments");
+            }
+            Resp::Array(
+                keys.into_iter()
+                    .map(|key| Resp::bulk(key.as_str()))
+                    .collect(),
+            )
+        }
+    };
+
+    Ok(reply)
+}
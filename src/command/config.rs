@@ -1,51 +1,184 @@
-use crate::{Command, Resp, Store};
+use std::{collections::HashSet, vec};
+
 use anyhow::Context;
+use glob::Pattern;
+
+use crate::{
+    handler::Session,
+    store::{self, lookup_param, Param, Stats, PARAMS},
+    Command, RedisError, Resp, Store,
+};
 
 #[derive(Debug)]
 pub(crate) enum Op {
-    Get,
-    Set,
+    Get(Vec<String>),
+    Set(Vec<(String, String)>),
+    ResetStat,
+    Rewrite,
 }
 
 #[derive(Debug)]
-pub(crate) enum Name {
-    Dir,
-    DbFileName,
+pub(crate) struct Config {
+    op: Op,
 }
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let op = args
-        .next()
-        .context("Missing argument 'GET' for CONFIG command")?;
-    let name = args
-        .next()
-        .context("Missing argument 'name' for CONFIG command")?;
-
-    let op = match op.as_str() {
-        "GET" => Op::Get,
-        "SET" => Op::Set,
-        _ => anyhow::bail!(format!("Invalid arguemnt '{}' for CONFIG command", op)),
-    };
-    let name = match name.as_str() {
-        "dir" => Name::Dir,
-        "dbfilename" => Name::DbFileName,
-        _ => anyhow::bail!(format!("Invalid argument '{}' in CONFIG command", name)),
-    };
+impl Command for Config {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let sub = args
+            .next()
+            .context("Missing subcommand for CONFIG command")?;
+
+        let op = match sub.to_lowercase().as_str() {
+            "get" => {
+                let patterns: Vec<String> = args.collect();
+                if patterns.is_empty() {
+                    return Err(RedisError::WrongArity("config|get".to_string()).into());
+                }
+                Op::Get(patterns)
+            }
+            "set" => {
+                let args: Vec<String> = args.collect();
+                if args.is_empty() || !args.len().is_multiple_of(2) {
+                    return Err(RedisError::WrongArity("config|set".to_string()).into());
+                }
+                Op::Set(
+                    args.chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect(),
+                )
+            }
+            "resetstat" => Op::ResetStat,
+            "rewrite" => Op::Rewrite,
+            _ => anyhow::bail!("unknown subcommand '{}'. Try CONFIG HELP.", sub),
+        };
+
+        Ok(Config { op })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Config { op } = *self;
+
+        Ok(invoke(store, op)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(store: &mut Store, op: Op) -> anyhow::Result<Resp> {
+    match op {
+        Op::Get(patterns) => Ok(get(store, &patterns)),
+        Op::Set(pairs) => {
+            set(store, &pairs)?;
+            Ok(Resp::ok())
+        }
+        Op::ResetStat => {
+            store.stats = Stats::default();
+            Ok(Resp::ok())
+        }
+        Op::Rewrite => {
+            store::rewrite_config(&store.config)?;
+            Ok(Resp::ok())
+        }
+    }
+}
+
+/// Values of the parameters matching any of `patterns`. A name without
+/// wildcards may also be an alias, and is replied under that name.
+fn get(store: &Store, patterns: &[String]) -> Resp {
+    let mut seen = HashSet::new();
+    let mut values = vec![];
+
+    for pattern in patterns {
+        let pattern = pattern.to_lowercase();
+        let matched: Vec<(&Param, String)> = if pattern.contains(['*', '?', '[']) {
+            let Ok(glob) = Pattern::new(&pattern) else {
+                continue;
+            };
+            PARAMS
+                .iter()
+                .filter(|param| glob.matches(param.name))
+                .map(|param| (param, param.name.to_string()))
+                .collect()
+        } else {
+            lookup_param(&pattern)
+                .map(|param| (param, pattern.clone()))
+                .into_iter()
+                .collect()
+        };
 
-    Ok(Command::Config { op, name })
+        for (param, name) in matched {
+            if seen.insert(param.name) {
+                values.push((Resp::bulk(name), Resp::bulk(param.get(&store.config))));
+            }
+        }
+    }
+
+    Resp::Map(values)
 }
 
-pub(crate) fn invoke(store: &Store, op: Op, name: Name) -> anyhow::Result<Resp> {
-    let (key, val) = match name {
-        Name::Dir => (String::from("dir"), store.config.dir().to_string()),
-        Name::DbFileName => (
-            String::from("dbfilename"),
-            store.config.db_file_name().to_string(),
-        ),
+/// Sets every parameter or none: if any value is rejected, or applying it
+/// fails, the ones already set are restored.
+fn set(store: &mut Store, pairs: &[(String, String)]) -> anyhow::Result<()> {
+    let failed = |name: &str, reason: &dyn std::fmt::Display| {
+        anyhow::anyhow!(
+            "CONFIG SET failed (possibly related to argument '{}') - {}",
+            name,
+            reason
+        )
     };
 
-    match op {
-        Op::Get => Ok(Resp::array(vec![key, val])),
-        _ => Ok(Resp::null()),
+    let mut params: Vec<(&Param, &str)> = vec![];
+    for (name, value) in pairs {
+        let param = lookup_param(name).with_context(|| {
+            format!(
+                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            )
+        })?;
+        if !param.mutable {
+            return Err(failed(name, &"can't set immutable config"));
+        }
+        if params.iter().any(|(seen, _)| seen.name == param.name) {
+            return Err(failed(name, &"duplicate parameter"));
+        }
+        params.push((param, value));
     }
+
+    let previous: Vec<String> = params
+        .iter()
+        .map(|(param, _)| param.get(&store.config))
+        .collect();
+    let restore = |store: &mut Store| {
+        for ((param, _), value) in params.iter().zip(&previous) {
+            // The old values were valid, so setting them back can't fail
+            let _ = param.set(&mut store.config, value);
+        }
+        for (param, _) in &params {
+            if let Some(hook) = param.hook {
+                let _ = hook(store);
+            }
+        }
+    };
+
+    for (param, value) in &params {
+        if let Err(e) = param.set(&mut store.config, value) {
+            restore(store);
+            return Err(failed(param.name, &e));
+        }
+    }
+    for (param, _) in &params {
+        if let Some(hook) = param.hook {
+            if let Err(e) = hook(store) {
+                restore(store);
+                return Err(failed(param.name, &e));
+            }
+        }
+    }
+
+    Ok(())
 }
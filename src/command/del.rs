@@ -0,0 +1,43 @@
+use std::vec;
+
+use crate::{handler::Session, store::notify, Command, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct Del {
+    keys: Vec<String>,
+}
+
+impl Command for Del {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let keys: Vec<String> = args.collect();
+
+        Ok(Del { keys })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Del { keys } = *self;
+
+        Ok(invoke(store, session, &keys)
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(store: &mut Store, session: &Session, keys: &[String]) -> Resp {
+    let mut deleted = 0;
+    for key in keys {
+        store.expire_if_needed(session.db, key);
+        if store.db_mut(session.db).remove(key) {
+            store.notify_keyspace_event(notify::GENERIC, "del", key, session.db);
+            store.dirty += 1;
+            deleted += 1;
+        }
+    }
+
+    Resp::integer(deleted)
+}
@@ -0,0 +1,103 @@
+use std::vec;
+
+use crate::{handler::Session, store::Db, Command, RedisError, Resp, Store};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FlushMode {
+    Sync,
+    Async,
+}
+
+fn parse_mode(args: &mut impl Iterator<Item = String>) -> anyhow::Result<FlushMode> {
+    match args.next() {
+        Some(mode) => match mode.to_uppercase().as_str() {
+            "SYNC" => Ok(FlushMode::Sync),
+            "ASYNC" => Ok(FlushMode::Async),
+            _ => Err(RedisError::Syntax.into()),
+        },
+        None => Ok(FlushMode::Sync),
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FlushDb {
+    mode: FlushMode,
+}
+
+impl Command for FlushDb {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(FlushDb {
+            mode: parse_mode(args)?,
+        })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let FlushDb { mode } = *self;
+
+        Ok(invoke_flushdb(store, session, mode)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FlushAll {
+    mode: FlushMode,
+}
+
+impl Command for FlushAll {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(FlushAll {
+            mode: parse_mode(args)?,
+        })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let FlushAll { mode } = *self;
+
+        Ok(invoke_flushall(store, mode)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke_flushdb(
+    store: &mut Store,
+    session: &Session,
+    mode: FlushMode,
+) -> anyhow::Result<Resp> {
+    let old = store.db_mut(session.db).flush();
+    store.dirty += old.len() as u64;
+    release(vec![old], mode);
+
+    Ok(Resp::ok())
+}
+
+pub(crate) fn invoke_flushall(store: &mut Store, mode: FlushMode) -> anyhow::Result<Resp> {
+    let old: Vec<Db> = store.dbs.iter_mut().map(Db::flush).collect();
+    store.dirty += old.iter().map(|db| db.len() as u64).sum::<u64>();
+    release(old, mode);
+
+    Ok(Resp::ok())
+}
+
+/// Drops the flushed databases, off the connection task for ASYNC so large
+/// keyspaces don't stall the reply.
+fn release(dbs: Vec<Db>, mode: FlushMode) {
+    match mode {
+        FlushMode::Sync => drop(dbs),
+        FlushMode::Async => {
+            tokio::task::spawn_blocking(move || drop(dbs));
+        }
+    }
+}
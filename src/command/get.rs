@@ -1,18 +1,57 @@
-use crate::{store, Command, Resp, Store};
+use std::vec;
+
+use crate::{
+    handler::Session,
+    store::{self, notify},
+    Command, RedisError, Resp, Store,
+};
 use anyhow::Context;
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let key = args
-        .next()
-        .context("Missing argument 'key' for GET command")?;
+#[derive(Debug)]
+pub(crate) struct Get {
+    key: String,
+}
+
+impl Command for Get {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let key = args
+            .next()
+            .context("Missing argument 'key' for GET command")?;
+
+        Ok(Get { key })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Get { key } = *self;
 
-    Ok(Command::Get { key })
+        Ok(invoke(store, session, &key)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
 }
 
-pub(crate) fn invoke(store: &mut Store, key: &str) -> anyhow::Result<Resp> {
-    match store.db.get(key) {
-        Some(store::RedisValue::String(value)) => Ok(Resp::bulk(value)),
-        Some(store::RedisValue::Stream(..)) => Ok(Resp::error("INVALID TYPE")),
-        _ => Ok(Resp::null()),
+pub(crate) fn invoke(store: &mut Store, session: &Session, key: &str) -> anyhow::Result<Resp> {
+    store.expire_if_needed(session.db, key);
+
+    match store.db(session.db).get(key) {
+        Some(store::RedisValue::String(value)) => {
+            let reply = Resp::bulk(value);
+            store.stats.keyspace_hits += 1;
+            Ok(reply)
+        }
+        Some(store::RedisValue::Stream(..)) => {
+            store.stats.keyspace_hits += 1;
+            Err(RedisError::WrongType.into())
+        }
+        None => {
+            store.stats.keyspace_misses += 1;
+            store.notify_keyspace_event(notify::KEY_MISS, "keymiss", key, session.db);
+            Ok(Resp::null())
+        }
     }
 }
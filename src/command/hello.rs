@@ -0,0 +1,124 @@
+use std::vec;
+
+use anyhow::Context;
+
+use super::{auth, client};
+use crate::{handler::Session, resp::Protocol, Command, RedisError, Resp, Store};
+
+/// Redis version this server reports compatibility with.
+pub(crate) const SERVER_VERSION: &str = "7.2.0";
+
+#[derive(Debug)]
+pub(crate) struct Hello {
+    protover: Option<u8>,
+    auth: Option<(String, String)>,
+    setname: Option<String>,
+}
+
+impl Command for Hello {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let protover = match args.next() {
+            Some(protover) => Some(
+                protover
+                    .parse()
+                    .context("Protocol version is not an integer or out of range")?,
+            ),
+            None => None,
+        };
+
+        let mut auth = None;
+        let mut setname = None;
+        while let Some(option) = args.next() {
+            match option.to_uppercase().as_str() {
+                "AUTH" => {
+                    let username = args
+                        .next()
+                        .context("Missing argument 'username' for HELLO AUTH")?;
+                    let password = args
+                        .next()
+                        .context("Missing argument 'password' for HELLO AUTH")?;
+                    auth = Some((username, password));
+                }
+                "SETNAME" => {
+                    let name = args
+                        .next()
+                        .context("Missing argument 'clientname' for HELLO SETNAME")?;
+                    setname = Some(name);
+                }
+                _ => anyhow::bail!("Syntax error in HELLO option '{}'", option),
+            }
+        }
+
+        Ok(Hello {
+            protover,
+            auth,
+            setname,
+        })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Hello {
+            protover,
+            auth,
+            setname,
+        } = *self;
+
+        Ok(invoke(store, session, protover, auth, setname)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(
+    store: &mut Store,
+    session: &mut Session,
+    protover: Option<u8>,
+    auth: Option<(String, String)>,
+    setname: Option<String>,
+) -> anyhow::Result<Resp> {
+    let protocol = match protover {
+        Some(2) => Protocol::Resp2,
+        Some(3) => Protocol::Resp3,
+        Some(_) => return Err(RedisError::NoProto.into()),
+        None => session.protocol,
+    };
+
+    match auth {
+        Some((username, password)) => auth::authenticate(store, session, &username, &password)?,
+        None if !session.authenticated => return Err(RedisError::HelloNoAuth.into()),
+        None => {}
+    }
+
+    if let Some(name) = setname.as_deref() {
+        client::validate_name(name)?;
+    }
+
+    session.protocol = protocol;
+    if setname.is_some() {
+        session.name = setname;
+    }
+
+    let role = if store.config.is_master() {
+        "master"
+    } else {
+        "replica"
+    };
+
+    Ok(Resp::Map(vec![
+        (Resp::bulk("server"), Resp::bulk("redis")),
+        (Resp::bulk("version"), Resp::bulk(SERVER_VERSION)),
+        (
+            Resp::bulk("proto"),
+            Resp::integer(session.protocol.version()),
+        ),
+        (Resp::bulk("id"), Resp::Integer(session.id as i64)),
+        (Resp::bulk("mode"), Resp::bulk("standalone")),
+        (Resp::bulk("role"), Resp::bulk(role)),
+        (Resp::bulk("modules"), Resp::Array(vec![])),
+    ]))
+}
@@ -1,43 +1,551 @@
-use crate::{Command, Resp, Store};
+use std::{
+    ffi::CStr,
+    fs,
+    sync::atomic::Ordering,
+    time::{SystemTime, UNIX_EPOCH},
+    vec,
+};
+
+use super::hello::SERVER_VERSION;
+use crate::{
+    handler::{ClientType, Session},
+    store::{unix_time, CommandStats, BACKLOG_SIZE},
+    Command, Resp, Store,
+};
+
+/// Sections in the order INFO lists them, each with its title and whether
+/// it is one of the `default` sections.
+const SECTIONS: &[(&str, &str, bool)] = &[
+    ("server", "Server", true),
+    ("clients", "Clients", true),
+    ("memory", "Memory", true),
+    ("persistence", "Persistence", true),
+    ("stats", "Stats", true),
+    ("replication", "Replication", true),
+    ("cpu", "CPU", true),
+    ("modules", "Modules", true),
+    ("commandstats", "Commandstats", false),
+    ("errorstats", "Errorstats", true),
+    ("latencystats", "Latencystats", false),
+    ("cluster", "Cluster", true),
+    ("keyspace", "Keyspace", true),
+];
+
+/// Percentiles latencystats reports, as `latency-tracking-info-percentiles`
+/// defaults to.
+const LATENCY_PERCENTILES: &[f64] = &[50.0, 99.0, 99.9];
+
+/// Active expiry and other periodic work run this many times a second.
+const HZ: u64 = 10;
 
 #[derive(Debug)]
-pub(crate) enum InfoKind {
-    All,
-    Replication,
+pub(crate) struct Info {
+    sections: Vec<String>,
 }
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let section = args.next();
+impl Command for Info {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let mut sections: Vec<String> = args.map(|arg| arg.to_lowercase()).collect();
+        if sections.is_empty() {
+            sections.push("default".to_string());
+        }
+
+        Ok(Info { sections })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Info { sections } = *self;
+
+        Ok(invoke(store, session, &sections)
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
 
-    let kind = match section {
-        Some(kind) => match kind.as_str() {
-            "replication" => InfoKind::Replication,
-            _ => return Err(anyhow::anyhow!("Invalid section for INFO command")),
-        },
-        None => InfoKind::All,
+pub(crate) fn invoke(store: &Store, session: &Session, sections: &[String]) -> Resp {
+    let wanted = |name: &str, default: bool| {
+        sections.iter().any(|section| match section.as_str() {
+            "all" | "everything" => true,
+            "default" => default,
+            section => section == name,
+        })
     };
 
-    Ok(Command::Info { kind })
+    let mut text = String::new();
+    for (name, title, default) in SECTIONS {
+        if !wanted(name, *default) {
+            continue;
+        }
+
+        if !text.is_empty() {
+            text.push_str("\r\n");
+        }
+        text.push_str(&format!("# {}\r\n", title));
+        for (field, value) in section(name, store, session) {
+            text.push_str(&format!("{}:{}\r\n", field, value));
+        }
+    }
+
+    Resp::VerbatimString {
+        format: "txt".to_string(),
+        text,
+    }
 }
 
-pub(crate) fn invoke(store: &mut Store, kind: InfoKind) -> anyhow::Result<Resp> {
-    let replication_info = match kind {
-        InfoKind::Replication => store.config.get_repl_info(),
-        // TODO: for InfoKind::All, need to parse all values
-        InfoKind::All => store.config.get_repl_info(),
+fn section(name: &str, store: &Store, session: &Session) -> Vec<(String, String)> {
+    let fields: Vec<(&str, String)> = match name {
+        "server" => server(store),
+        "clients" => clients(store, session),
+        "memory" => memory(),
+        "persistence" => persistence(store),
+        "stats" => stats(store),
+        "replication" => return replication(store),
+        "cpu" => cpu(),
+        "commandstats" => {
+            return store
+                .stats
+                .commands
+                .iter()
+                .map(|(name, stats)| (format!("cmdstat_{}", name), command_stats(stats)))
+                .collect();
+        }
+        "errorstats" => {
+            return store
+                .stats
+                .errors
+                .iter()
+                .map(|(code, count)| (format!("errorstat_{}", code), format!("count={}", count)))
+                .collect();
+        }
+        "latencystats" => {
+            return store
+                .stats
+                .commands
+                .iter()
+                .filter(|(_, stats)| stats.calls > 0)
+                .map(|(name, stats)| {
+                    let percentiles: Vec<String> = LATENCY_PERCENTILES
+                        .iter()
+                        .map(|p| {
+                            let usec = stats.latency.percentile(*p) as f64 / 1000.0;
+                            format!("p{}={:.3}", p, usec)
+                        })
+                        .collect();
+                    (
+                        format!("latency_percentiles_usec_{}", name),
+                        percentiles.join(","),
+                    )
+                })
+                .collect();
+        }
+        "cluster" => vec![("cluster_enabled", "0".to_string())],
+        "keyspace" => {
+            return store
+                .dbs
+                .iter()
+                .enumerate()
+                .map(|(index, db)| (index, db.keyspace_info()))
+                .filter(|(_, (keys, _, _))| *keys > 0)
+                .map(|(index, (keys, expires, avg_ttl))| {
+                    (
+                        format!("db{}", index),
+                        format!("keys={},expires={},avg_ttl={}", keys, expires, avg_ttl),
+                    )
+                })
+                .collect();
+        }
+        _ => vec![],
+    };
+
+    fields
+        .into_iter()
+        .map(|(field, value)| (field.to_string(), value))
+        .collect()
+}
+
+fn server(store: &Store) -> Vec<(&'static str, String)> {
+    let config = &store.config;
+    let uptime = store.started.elapsed().as_secs();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let multiplexing_api = if cfg!(target_os = "linux") {
+        "epoll"
+    } else {
+        "kqueue"
     };
 
-    let mut result = vec![];
-    result.push(format!(
-        "role:{}",
-        if store.config.is_master() {
-            "master"
+    let mut fields = vec![
+        ("redis_version", SERVER_VERSION.to_string()),
+        ("redis_git_sha1", "00000000".to_string()),
+        ("redis_git_dirty", "0".to_string()),
+        ("redis_mode", "standalone".to_string()),
+        ("os", os_name()),
+        ("arch_bits", usize::BITS.to_string()),
+        ("multiplexing_api", multiplexing_api.to_string()),
+        ("process_id", std::process::id().to_string()),
+        ("run_id", store.run_id.clone()),
+        ("tcp_port", config.port().to_string()),
+        ("server_time_usec", now.as_micros().to_string()),
+        ("uptime_in_seconds", uptime.to_string()),
+        ("uptime_in_days", (uptime / 86400).to_string()),
+        ("hz", HZ.to_string()),
+        ("configured_hz", HZ.to_string()),
+        (
+            "executable",
+            std::env::current_exe()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        ),
+        (
+            "config_file",
+            config.config_file().unwrap_or_default().to_string(),
+        ),
+    ];
+
+    let bind: Vec<String> = config
+        .bind()
+        .iter()
+        .map(|addr| format!("bind={}", addr))
+        .collect();
+    let mut listeners = vec![];
+    if config.port() != 0 {
+        listeners.push(format!(
+            "name=tcp,{},port={}",
+            bind.join(","),
+            config.port()
+        ));
+    }
+    if let Some(path) = config.unixsocket() {
+        listeners.push(format!("name=unix,bind={}", path));
+    }
+    if config.tls().port != 0 {
+        listeners.push(format!(
+            "name=tls,{},port={}",
+            bind.join(","),
+            config.tls().port
+        ));
+    }
+    const LISTENERS: [&str; 3] = ["listener0", "listener1", "listener2"];
+    fields.extend(LISTENERS.into_iter().zip(listeners));
+
+    fields
+}
+
+fn clients(store: &Store, session: &Session) -> Vec<(&'static str, String)> {
+    // The caller's session is checked out of the store while it runs
+    let clients: Vec<&Session> = std::iter::once(session)
+        .chain(store.clients.values())
+        .filter(|client| client.role != ClientType::Replica)
+        .collect();
+    let count = |f: fn(&Session) -> bool| clients.iter().filter(|c| f(c)).count();
+
+    vec![
+        ("connected_clients", clients.len().to_string()),
+        (
+            "client_recent_max_input_buffer",
+            clients
+                .iter()
+                .map(|c| c.qbuf)
+                .max()
+                .unwrap_or(0)
+                .to_string(),
+        ),
+        (
+            "client_recent_max_output_buffer",
+            clients
+                .iter()
+                .map(|c| c.omem)
+                .max()
+                .unwrap_or(0)
+                .to_string(),
+        ),
+        ("pubsub_clients", count(Session::is_subscribed).to_string()),
+        (
+            "watching_clients",
+            count(|c| !c.watched.is_empty()).to_string(),
+        ),
+        (
+            "total_watched_keys",
+            clients
+                .iter()
+                .map(|c| c.watched.len())
+                .sum::<usize>()
+                .to_string(),
+        ),
+    ]
+}
+
+fn memory() -> Vec<(&'static str, String)> {
+    // Allocations aren't tracked, so the resident set stands in for them
+    let rss = resident_set_size();
+    let peak = rusage(libc::RUSAGE_SELF).map_or(0, |usage| {
+        // Linux reports the peak in kilobytes, macOS in bytes
+        let max_rss = usage.ru_maxrss as u64;
+        if cfg!(target_os = "macos") {
+            max_rss
         } else {
-            "slave"
+            max_rss * 1024
         }
+    });
+
+    vec![
+        ("used_memory", rss.to_string()),
+        ("used_memory_human", bytes_to_human(rss)),
+        ("used_memory_rss", rss.to_string()),
+        ("used_memory_rss_human", bytes_to_human(rss)),
+        ("used_memory_peak", peak.to_string()),
+        ("used_memory_peak_human", bytes_to_human(peak)),
+        ("maxmemory", "0".to_string()),
+        ("maxmemory_human", "0B".to_string()),
+        ("maxmemory_policy", "noeviction".to_string()),
+        ("mem_allocator", "libc".to_string()),
+    ]
+}
+
+fn persistence(store: &Store) -> Vec<(&'static str, String)> {
+    // Nothing is saved, so the last save is when the server started
+    let started = SystemTime::now() - store.started.elapsed();
+    let last_save = started
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    vec![
+        ("loading", "0".to_string()),
+        ("async_loading", "0".to_string()),
+        ("rdb_changes_since_last_save", store.dirty.to_string()),
+        ("rdb_bgsave_in_progress", "0".to_string()),
+        ("rdb_last_save_time", last_save.to_string()),
+        ("rdb_last_bgsave_status", "ok".to_string()),
+        ("rdb_last_bgsave_time_sec", "-1".to_string()),
+        ("rdb_current_bgsave_time_sec", "-1".to_string()),
+        ("aof_enabled", "0".to_string()),
+        ("aof_rewrite_in_progress", "0".to_string()),
+        ("aof_rewrite_scheduled", "0".to_string()),
+        ("aof_last_rewrite_time_sec", "-1".to_string()),
+        ("aof_current_rewrite_time_sec", "-1".to_string()),
+        ("aof_last_bgrewrite_status", "ok".to_string()),
+        ("aof_last_write_status", "ok".to_string()),
+    ]
+}
+
+fn stats(store: &Store) -> Vec<(&'static str, String)> {
+    let stats = &store.stats;
+
+    vec![
+        (
+            "total_connections_received",
+            stats.connections_received.to_string(),
+        ),
+        (
+            "total_commands_processed",
+            stats.commands_processed.to_string(),
+        ),
+        (
+            "rejected_connections",
+            stats.rejected_connections.to_string(),
+        ),
+        ("sync_full", stats.sync_full.to_string()),
+        ("expired_keys", stats.expired_keys.to_string()),
+        ("evicted_keys", "0".to_string()),
+        ("keyspace_hits", stats.keyspace_hits.to_string()),
+        ("keyspace_misses", stats.keyspace_misses.to_string()),
+        (
+            "pubsub_channels",
+            store.pubsub.channels(None).len().to_string(),
+        ),
+        ("pubsub_patterns", store.pubsub.numpat().to_string()),
+        (
+            "pubsubshard_channels",
+            store.pubsub.shard_channels(None).len().to_string(),
+        ),
+        ("total_error_replies", stats.error_replies.to_string()),
+    ]
+}
+
+fn replication(store: &Store) -> Vec<(String, String)> {
+    let replication = &store.replication;
+    let mut fields = vec![];
+
+    if store.config.is_master() {
+        fields.push(("role".to_string(), "master".to_string()));
+    } else {
+        let (host, port) = store
+            .config
+            .master_addr()
+            .rsplit_once(':')
+            .unwrap_or_default();
+        // The master's link is a client of its own until it drops
+        let link = store
+            .clients
+            .values()
+            .find(|client| client.role == ClientType::Master);
+        let last_io = link.map_or(-1, |link| link.last_interaction.elapsed().as_secs() as i64);
+
+        fields.extend([
+            ("role".to_string(), "slave".to_string()),
+            ("master_host".to_string(), host.to_string()),
+            ("master_port".to_string(), port.to_string()),
+            (
+                "master_link_status".to_string(),
+                if link.is_some() { "up" } else { "down" }.to_string(),
+            ),
+            (
+                "master_last_io_seconds_ago".to_string(),
+                last_io.to_string(),
+            ),
+            ("master_sync_in_progress".to_string(), "0".to_string()),
+            (
+                "slave_read_repl_offset".to_string(),
+                replication.offset.to_string(),
+            ),
+            (
+                "slave_repl_offset".to_string(),
+                replication.offset.to_string(),
+            ),
+            ("slave_priority".to_string(), "100".to_string()),
+            ("slave_read_only".to_string(), "1".to_string()),
+            ("replica_announced".to_string(), "1".to_string()),
+        ]);
+    }
+
+    fields.push((
+        "connected_slaves".to_string(),
+        store.replicas.len().to_string(),
     ));
-    result.push(format!("master_replid:{}", replication_info.id));
-    result.push(format!("master_repl_offset:{}", replication_info.offset));
+    let now = unix_time();
+    for (index, replica) in store.replicas.iter().enumerate() {
+        let offset = replica.ack_offset.load(Ordering::SeqCst);
+        let lag = now.saturating_sub(replica.ack_time.load(Ordering::SeqCst));
+        fields.push((
+            format!("slave{}", index),
+            format!(
+                "ip={},port={},state=online,offset={},lag={}",
+                replica.ip, replica.port, offset, lag
+            ),
+        ));
+    }
+
+    let (backlog_active, first_byte, histlen) = match replication.backlog() {
+        Some((first_byte, histlen)) => (1, first_byte, histlen),
+        None => (0, 0, 0),
+    };
+    fields.extend([
+        (
+            "master_failover_state".to_string(),
+            "no-failover".to_string(),
+        ),
+        ("master_replid".to_string(), replication.id.clone()),
+        ("master_replid2".to_string(), replication.id2.clone()),
+        (
+            "master_repl_offset".to_string(),
+            replication.offset.to_string(),
+        ),
+        (
+            "second_repl_offset".to_string(),
+            replication.second_offset.to_string(),
+        ),
+        (
+            "repl_backlog_active".to_string(),
+            backlog_active.to_string(),
+        ),
+        ("repl_backlog_size".to_string(), BACKLOG_SIZE.to_string()),
+        (
+            "repl_backlog_first_byte_offset".to_string(),
+            first_byte.to_string(),
+        ),
+        ("repl_backlog_histlen".to_string(), histlen.to_string()),
+    ]);
+
+    fields
+}
+
+fn cpu() -> Vec<(&'static str, String)> {
+    let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1e6;
+    let (sys, user) = rusage(libc::RUSAGE_SELF).map_or((0.0, 0.0), |usage| {
+        (seconds(usage.ru_stime), seconds(usage.ru_utime))
+    });
+    let (sys_children, user_children) = rusage(libc::RUSAGE_CHILDREN).map_or((0.0, 0.0), |usage| {
+        (seconds(usage.ru_stime), seconds(usage.ru_utime))
+    });
+
+    vec![
+        ("used_cpu_sys", format!("{:.6}", sys)),
+        ("used_cpu_user", format!("{:.6}", user)),
+        ("used_cpu_sys_children", format!("{:.6}", sys_children)),
+        ("used_cpu_user_children", format!("{:.6}", user_children)),
+    ]
+}
+
+fn command_stats(stats: &CommandStats) -> String {
+    let per_call = match stats.calls {
+        0 => 0.0,
+        calls => stats.usec as f64 / calls as f64,
+    };
+
+    format!(
+        "calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+        stats.calls, stats.usec, per_call, stats.rejected_calls, stats.failed_calls
+    )
+}
+
+fn rusage(who: libc::c_int) -> Option<libc::rusage> {
+    // SAFETY: getrusage only writes to the struct it's given
+    unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        (libc::getrusage(who, &mut usage) == 0).then_some(usage)
+    }
+}
+
+/// Kernel name, release and machine, as `uname -srm` prints them.
+fn os_name() -> String {
+    // SAFETY: uname fills in the struct it's given with NUL-terminated
+    // strings
+    unsafe {
+        let mut name: libc::utsname = std::mem::zeroed();
+        if libc::uname(&mut name) != 0 {
+            return std::env::consts::OS.to_string();
+        }
+        let field = |field: &[libc::c_char]| {
+            CStr::from_ptr(field.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        };
+        format!(
+            "{} {} {}",
+            field(&name.sysname),
+            field(&name.release),
+            field(&name.machine)
+        )
+    }
+}
+
+/// Resident memory of the process in bytes, where /proc provides it.
+fn resident_set_size() -> u64 {
+    let pages = fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+        .unwrap_or(0);
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+    pages * page_size.max(0) as u64
+}
+
+/// Formats a byte count the way redis-server's `*_human` fields do.
+fn bytes_to_human(bytes: u64) -> String {
+    const UNITS: [(&str, u64); 3] = [("G", 1024 * 1024 * 1024), ("M", 1024 * 1024), ("K", 1024)];
 
-    Ok(Resp::bulk(result.join("\r\n")))
+    UNITS
+        .iter()
+        .find(|(_, size)| bytes >= *size)
+        .map_or(format!("{}B", bytes), |(unit, size)| {
+            format!("{:.2}{}", bytes as f64 / *size as f64, unit)
+        })
 }
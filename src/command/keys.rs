@@ -1,15 +1,39 @@
+use std::vec;
+
 use anyhow::Context;
 
-use crate::{Command, Resp, Store};
+use crate::{handler::Session, Command, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct Keys {
+    pattern: String,
+}
+
+impl Command for Keys {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let pattern = args
+            .next()
+            .context("Missing argument 'pattern for KEYS command")?;
+
+        Ok(Keys { pattern })
+    }
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let pattern = args.next().context("Missing argument 'pattern for KEYS command")?;
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Keys { pattern } = *self;
 
-    Ok(Command::Keys { pattern })
+        Ok(invoke(store, session, &pattern)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
 }
 
-pub(crate) fn invoke(store: &mut Store, pattern: &str) -> anyhow::Result<Resp> {
-    let matching_keys = store.db.keys(pattern);
+pub(crate) fn invoke(store: &mut Store, session: &Session, pattern: &str) -> anyhow::Result<Resp> {
+    let matching_keys = store.db(session.db).keys(pattern);
 
     Ok(Resp::array(matching_keys))
 }
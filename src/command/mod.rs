@@ -1,145 +1,155 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::{any::Any, fmt, time::Instant, vec};
 
 use anyhow::{Context, Ok};
-use tokio::sync::Mutex;
 
-use crate::{resp::Resp, store::Store};
+use crate::{error, handler::Session, store::Store, RedisError};
 
+mod acl;
+mod auth;
+mod client;
+mod command_cmd;
 mod config;
+mod del;
+mod flush;
 mod get;
+mod hello;
 mod info;
 mod keys;
+mod move_cmd;
+mod multi;
 mod psync;
+mod pubsub;
 mod repl_conf;
+mod reset;
+mod select;
 mod set;
+mod swapdb;
+mod table;
 mod type_cmd;
 mod wait;
+mod watch;
 mod xadd;
 
-#[derive(Debug)]
-pub(crate) enum Command {
-    Ping,
-    Echo {
-        name: String,
-    },
-    Get {
-        key: String,
-    },
-    Set {
-        key: String,
-        value: String,
-        expiry: Option<Duration>,
-    },
-    Config {
-        op: config::Op,
-        name: config::Name,
-    },
-    Keys {
-        pattern: String,
-    },
-    Info {
-        kind: info::InfoKind,
-    },
-    ReplConf {
-        key: repl_conf::Kind,
-        value: String,
-    },
-    Psync {
-        repl_id: String,
-        offset: String,
-    },
-    Wait {
-        numreplicas: u8,
-        timeout: u16,
-    },
-    Type {
-        key: String,
-    },
-    Xadd {
-        key: String,
-        id: String,
-        fields: Vec<(String, String)>,
-    },
-}
+pub(crate) use acl::check as check_acl;
+pub(crate) use auth::DEFAULT_USER;
+pub(crate) use psync::Psync;
+pub(crate) use pubsub::unsubscribe_all;
+pub(crate) use repl_conf::ReplConf;
+pub(crate) use table::{lookup, Behavior, CommandSpec, COMMANDS};
+pub(crate) use wait::{invoke as wait, Wait};
+pub(crate) use watch::unwatch_all;
 
-impl Command {
-    pub(crate) fn parse(args: Vec<String>) -> anyhow::Result<Command> {
-        let mut args = args.into_iter();
-        let command = args.next().context("Invalid command")?;
+/// A parsed command, ready to run. Each command table entry registers the
+/// type that implements its command, so whatever it parses is also what
+/// runs.
+pub(crate) trait Command: Any + fmt::Debug + Send {
+    /// Builds the command from the arguments following its name.
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self>
+    where
+        Self: Sized;
 
-        match command.to_lowercase().as_str() {
-            "ping" => Ok(Command::Ping),
-            "echo" => {
-                let name = args
-                    .next()
-                    .context("Missing argument 'name' for ECHO command")?;
+    /// Runs the command, returning the encoded reply. `args` are what
+    /// replicas are sent, which commands rewrite where running them again
+    /// could turn out differently, such as a relative expiry or a generated
+    /// stream ID.
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>>;
 
-                Ok(Command::Echo { name })
-            }
-            "get" => get::parse(&mut args),
-            "set" => set::parse(&mut args),
-            "config" => config::parse(&mut args),
-            "keys" => keys::parse(&mut args),
-            "info" => info::parse(&mut args),
-            "replconf" => repl_conf::parse(&mut args),
-            "psync" => psync::parse(&mut args),
-            "wait" => wait::parse(&mut args),
-            "type" => type_cmd::parse(&mut args),
-            "xadd" => xadd::parse(&mut args),
-            _ => anyhow::bail!("Unknown command encountered: {}", command),
-        }
+    /// Channels the command publishes or subscribes to, each paired with
+    /// whether it is a PSUBSCRIBE pattern rather than a channel name.
+    fn channels(&self) -> Vec<(&String, bool)> {
+        vec![]
     }
+}
 
-    pub(crate) async fn execute(self, store: Arc<Mutex<Store>>) -> anyhow::Result<Vec<u8>> {
-        let result: Vec<u8> = match self {
-            Command::Ping => Resp::SimpleString("PONG".to_string()).encode().into_bytes(),
-            Command::Echo { name } => Resp::bulk(name).encode().into_bytes(),
-            Command::Get { key } => {
-                let mut s = store.lock().await;
-                get::invoke(&mut s, &key)?.encode().into_bytes()
-            }
-            Command::Set { key, value, expiry } => {
-                let mut s = store.lock().await;
-                set::invoke(&mut s, key, value, expiry)?
-                    .encode()
-                    .into_bytes()
-            }
-            Command::Config { op, name } => {
-                let s = store.lock().await;
-                config::invoke(&s, op, name)?.encode().into_bytes()
-            }
-            Command::Keys { pattern } => {
-                let mut s = store.lock().await;
-                keys::invoke(&mut s, &pattern)?.encode().into_bytes()
-            }
-            Command::Info { kind } => {
-                let mut s = store.lock().await;
-                info::invoke(&mut s, kind)?.encode().into_bytes()
-            }
-            Command::ReplConf { key, value } => {
-                let mut s = store.lock().await;
-                repl_conf::invoke(&mut s, key, &value)?
-                    .encode()
-                    .into_bytes()
-            }
-            Command::Psync { repl_id, offset } => {
-                let mut s = store.lock().await;
-                psync::invoke(&mut s, &repl_id, &offset)?
-            }
-            Command::Wait {
-                numreplicas,
-                timeout,
-            } => wait::invoke(store, numreplicas, timeout).await?,
-            Command::Type { key } => {
-                let mut s = store.lock().await;
-                type_cmd::invoke(&mut s, &key).await?.encode().into_bytes()
-            }
-            Command::Xadd { key, id, fields } => {
-                let mut s = store.lock().await;
-                xadd::invoke(&mut s, key, id, fields)?.encode().into_bytes()
+/// A parsed command together with its entry in the command table.
+#[derive(Debug)]
+pub(crate) struct Call {
+    pub(crate) spec: &'static CommandSpec,
+    /// Full name, including the subcommand for containers like CONFIG GET.
+    pub(crate) name: &'static str,
+    command: Box<dyn Command>,
+    /// Arguments as received, including the command name.
+    args: Vec<String>,
+}
+
+impl Call {
+    pub(crate) fn parse(args: Vec<String>) -> anyhow::Result<Call> {
+        let name = args.first().context("Invalid command")?;
+        let spec = match table::lookup(name) {
+            Some(spec) => spec,
+            None => {
+                let mut args = args.into_iter();
+                let name = args.next().unwrap_or_default();
+                return Err(RedisError::unknown_command(&name, args).into());
             }
         };
+        let resolved = spec.resolve(&args);
+        if !resolved.accepts(args.len()) {
+            return Err(RedisError::WrongArity(resolved.name.to_string()).into());
+        }
+
+        let mut rest = args.clone().into_iter();
+        rest.next();
+        let command = spec.parse(&mut rest)?;
+
+        Ok(Call {
+            spec,
+            name: resolved.name,
+            command,
+            args,
+        })
+    }
+
+    pub(crate) fn keys(&self) -> Vec<&String> {
+        self.spec.keys(&self.args)
+    }
+
+    /// Channels the command publishes or subscribes to, each paired with
+    /// whether it is a PSUBSCRIBE pattern rather than a channel name.
+    pub(crate) fn channels(&self) -> Vec<(&String, bool)> {
+        self.command.channels()
+    }
+
+    /// Whether the parsed command is a `C`.
+    pub(crate) fn is<C: Command>(&self) -> bool {
+        (self.command.as_ref() as &dyn Any).is::<C>()
+    }
+
+    /// The parsed command, if it is a `C`.
+    pub(crate) fn downcast_ref<C: Command>(&self) -> Option<&C> {
+        (self.command.as_ref() as &dyn Any).downcast_ref::<C>()
+    }
+
+    /// Runs the command on the executor. Writes that changed the dataset
+    /// are recorded in the session for propagation.
+    pub(crate) fn apply(
+        mut self,
+        store: &mut Store,
+        session: &mut Session,
+    ) -> anyhow::Result<Vec<u8>> {
+        let dirty = store.dirty;
+        let started = Instant::now();
+        let result = self.command.execute(store, session, &mut self.args);
+        store
+            .stats
+            .record_call(self.name, started.elapsed(), result.is_err());
+        if let Err(err) = &result {
+            store.stats.record_error(&error::code(err));
+        }
+        let result = result?;
+
+        // Writes count their changes in `dirty`, so one that changed nothing
+        // isn't replicated. Published messages change nothing yet still
+        // reach replicas.
+        let changed = self.spec.has_flag("write") && store.dirty > dirty;
+        if changed || self.spec.has_flag("may_replicate") {
+            session.propagate.push((session.db, self.args));
+        }
 
         Ok(result)
     }
@@ -0,0 +1,74 @@
+use std::vec;
+
+use anyhow::Context;
+
+use crate::{handler::Session, store::notify, Command, RedisError, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct Move {
+    key: String,
+    db: usize,
+}
+
+impl Command for Move {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let key = args
+            .next()
+            .context("Missing argument 'key' for MOVE command")?;
+        let db = args
+            .next()
+            .context("Missing argument 'db' for MOVE command")?
+            .parse()
+            .map_err(|_| RedisError::NotInteger)?;
+
+        Ok(Move { key, db })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Move { key, db } = *self;
+
+        Ok(invoke(store, session, &key, db)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(
+    store: &mut Store,
+    session: &Session,
+    key: &str,
+    db: usize,
+) -> anyhow::Result<Resp> {
+    if db >= store.config.databases() {
+        anyhow::bail!("DB index is out of range");
+    }
+    if db == session.db {
+        anyhow::bail!("source and destination objects are the same");
+    }
+
+    store.expire_if_needed(session.db, key);
+    store.expire_if_needed(db, key);
+
+    // Nothing moves when the key is missing or already present in the target
+    if !store.db(session.db).contains_key(key) || store.db(db).contains_key(key) {
+        return Ok(Resp::integer(0));
+    }
+
+    match store.db_mut(session.db).take_entry(key) {
+        Some(entry) => {
+            store.db_mut(db).insert_entry(key.to_string(), entry);
+            store.dirty += 1;
+
+            store.notify_keyspace_event(notify::GENERIC, "move_from", key, session.db);
+            store.notify_keyspace_event(notify::GENERIC, "move_to", key, db);
+
+            Ok(Resp::integer(1))
+        }
+        None => Ok(Resp::integer(0)),
+    }
+}
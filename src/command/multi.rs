@@ -0,0 +1,128 @@
+use std::vec;
+
+use super::{acl, watch};
+use crate::{
+    error,
+    handler::{Session, Transaction},
+    Command, RedisError, Resp, Store,
+};
+
+#[derive(Debug)]
+pub(crate) struct Multi;
+
+impl Command for Multi {
+    fn parse(_args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Multi)
+    }
+
+    fn execute(
+        self: Box<Self>,
+        _store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(invoke_multi(session)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke_multi(session: &mut Session) -> anyhow::Result<Resp> {
+    if session.transaction.is_some() {
+        anyhow::bail!("MULTI calls can not be nested");
+    }
+
+    session.transaction = Some(Transaction::default());
+
+    Ok(Resp::ok())
+}
+
+#[derive(Debug)]
+pub(crate) struct Discard;
+
+impl Command for Discard {
+    fn parse(_args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Discard)
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(invoke_discard(store, session)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke_discard(store: &mut Store, session: &mut Session) -> anyhow::Result<Resp> {
+    if session.transaction.take().is_none() {
+        anyhow::bail!("DISCARD without MULTI");
+    }
+    watch::unwatch_all(store, session);
+
+    Ok(Resp::ok())
+}
+
+#[derive(Debug)]
+pub(crate) struct Exec;
+
+impl Command for Exec {
+    fn parse(_args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Exec)
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        invoke_exec(store, session)
+    }
+}
+
+pub(crate) fn invoke_exec(store: &mut Store, session: &mut Session) -> anyhow::Result<Vec<u8>> {
+    let transaction = match session.transaction.take() {
+        Some(transaction) => transaction,
+        None => anyhow::bail!("EXEC without MULTI"),
+    };
+
+    if transaction.dirty {
+        watch::unwatch_all(store, session);
+        return Err(RedisError::ExecAbort.into());
+    }
+
+    // Optimistic locking: a watched key changed, so nothing runs
+    let conflict = watch::is_dirty(store, session);
+    watch::unwatch_all(store, session);
+    if conflict {
+        return Ok(Resp::NullArray.encode_with(session.protocol).into_bytes());
+    }
+
+    let propagate_start = session.propagate.len();
+
+    let mut result = format!("*{}\r\n", transaction.commands.len()).into_bytes();
+    for call in transaction.commands {
+        match acl::check_queued(store, session, &call).and_then(|()| call.apply(store, session)) {
+            Ok(reply) => result.extend_from_slice(&reply),
+            Err(err) => result
+                .extend_from_slice(error::reply(&err).encode_with(session.protocol).as_bytes()),
+        }
+    }
+
+    // Replicas must apply the transaction's writes atomically as well
+    if session.propagate.len() > propagate_start {
+        let first_db = session.propagate[propagate_start].0;
+        session
+            .propagate
+            .insert(propagate_start, (first_db, vec!["MULTI".to_string()]));
+        session
+            .propagate
+            .push((session.db, vec!["EXEC".to_string()]));
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,515 @@
+use std::vec;
+
+use anyhow::Context;
+
+use crate::{handler::Session, Command, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) enum Query {
+    Channels { pattern: Option<String> },
+    NumSub { channels: Vec<String> },
+    NumPat,
+    ShardChannels { pattern: Option<String> },
+    ShardNumSub { channels: Vec<String> },
+}
+
+#[derive(Debug)]
+pub(crate) struct Subscribe {
+    channels: Vec<String>,
+}
+
+impl Command for Subscribe {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let channel = args
+            .next()
+            .context("Missing argument 'channel' for SUBSCRIBE command")?;
+        let channels = std::iter::once(channel).chain(args).collect();
+
+        Ok(Subscribe { channels })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Subscribe { channels } = *self;
+
+        invoke_subscribe(store, session, channels)
+    }
+
+    fn channels(&self) -> Vec<(&String, bool)> {
+        self.channels
+            .iter()
+            .map(|channel| (channel, false))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Unsubscribe {
+    channels: Vec<String>,
+}
+
+impl Command for Unsubscribe {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Unsubscribe {
+            channels: args.collect(),
+        })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Unsubscribe { channels } = *self;
+
+        invoke_unsubscribe(store, session, channels)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Psubscribe {
+    patterns: Vec<String>,
+}
+
+impl Command for Psubscribe {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let pattern = args
+            .next()
+            .context("Missing argument 'pattern' for PSUBSCRIBE command")?;
+        let patterns = std::iter::once(pattern).chain(args).collect();
+
+        Ok(Psubscribe { patterns })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Psubscribe { patterns } = *self;
+
+        invoke_psubscribe(store, session, patterns)
+    }
+
+    fn channels(&self) -> Vec<(&String, bool)> {
+        self.patterns
+            .iter()
+            .map(|pattern| (pattern, true))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Punsubscribe {
+    patterns: Vec<String>,
+}
+
+impl Command for Punsubscribe {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Punsubscribe {
+            patterns: args.collect(),
+        })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Punsubscribe { patterns } = *self;
+
+        invoke_punsubscribe(store, session, patterns)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Publish {
+    channel: String,
+    message: String,
+}
+
+impl Command for Publish {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let channel = args
+            .next()
+            .context("Missing argument 'channel' for PUBLISH command")?;
+        let message = args
+            .next()
+            .context("Missing argument 'message' for PUBLISH command")?;
+
+        Ok(Publish { channel, message })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Publish { channel, message } = *self;
+
+        Ok(invoke_publish(store, &channel, &message)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+
+    fn channels(&self) -> Vec<(&String, bool)> {
+        vec![(&self.channel, false)]
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Ssubscribe {
+    channels: Vec<String>,
+}
+
+impl Command for Ssubscribe {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let channel = args
+            .next()
+            .context("Missing argument 'shardchannel' for SSUBSCRIBE command")?;
+        let channels = std::iter::once(channel).chain(args).collect();
+
+        Ok(Ssubscribe { channels })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Ssubscribe { channels } = *self;
+
+        invoke_ssubscribe(store, session, channels)
+    }
+
+    fn channels(&self) -> Vec<(&String, bool)> {
+        self.channels
+            .iter()
+            .map(|channel| (channel, false))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Sunsubscribe {
+    channels: Vec<String>,
+}
+
+impl Command for Sunsubscribe {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Sunsubscribe {
+            channels: args.collect(),
+        })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Sunsubscribe { channels } = *self;
+
+        invoke_sunsubscribe(store, session, channels)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Spublish {
+    channel: String,
+    message: String,
+}
+
+impl Command for Spublish {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let channel = args
+            .next()
+            .context("Missing argument 'shardchannel' for SPUBLISH command")?;
+        let message = args
+            .next()
+            .context("Missing argument 'message' for SPUBLISH command")?;
+
+        Ok(Spublish { channel, message })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Spublish { channel, message } = *self;
+
+        Ok(invoke_spublish(store, &channel, &message)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+
+    fn channels(&self) -> Vec<(&String, bool)> {
+        vec![(&self.channel, false)]
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PubSub {
+    query: Query,
+}
+
+impl Command for PubSub {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let sub = args
+            .next()
+            .context("Missing subcommand for PUBSUB command")?;
+
+        let query = match sub.to_lowercase().as_str() {
+            "channels" => Query::Channels {
+                pattern: args.next(),
+            },
+            "numsub" => Query::NumSub {
+                channels: args.collect(),
+            },
+            "numpat" => Query::NumPat,
+            "shardchannels" => Query::ShardChannels {
+                pattern: args.next(),
+            },
+            "shardnumsub" => Query::ShardNumSub {
+                channels: args.collect(),
+            },
+            _ => anyhow::bail!("unknown subcommand '{}'. Try PUBSUB HELP.", sub),
+        };
+
+        Ok(PubSub { query })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let PubSub { query } = *self;
+
+        Ok(invoke_pubsub(store, query)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+/// Reply confirming a (un)subscription, carrying the session's remaining
+/// subscription count.
+fn confirmation(kind: &str, name: Option<String>, count: usize) -> Resp {
+    Resp::Push(vec![
+        Resp::bulk(kind),
+        Resp::BulkString(name),
+        Resp::integer(count),
+    ])
+}
+
+pub(crate) fn invoke_subscribe(
+    store: &mut Store,
+    session: &mut Session,
+    channels: Vec<String>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut result = vec![];
+
+    for channel in channels {
+        store
+            .pubsub
+            .subscribe(&channel, session.id, &session.sender);
+        session.channels.insert(channel.clone());
+
+        let reply = confirmation("subscribe", Some(channel), session.subscription_count());
+        result.extend_from_slice(reply.encode_with(session.protocol).as_bytes());
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn invoke_unsubscribe(
+    store: &mut Store,
+    session: &mut Session,
+    channels: Vec<String>,
+) -> anyhow::Result<Vec<u8>> {
+    // Without arguments every channel is dropped
+    let channels = if channels.is_empty() {
+        session.channels.iter().cloned().collect()
+    } else {
+        channels
+    };
+
+    if channels.is_empty() {
+        let reply = confirmation("unsubscribe", None, session.subscription_count());
+        return Ok(reply.encode_with(session.protocol).into_bytes());
+    }
+
+    let mut result = vec![];
+    for channel in channels {
+        store.pubsub.unsubscribe(&channel, session.id);
+        session.channels.remove(&channel);
+
+        let reply = confirmation("unsubscribe", Some(channel), session.subscription_count());
+        result.extend_from_slice(reply.encode_with(session.protocol).as_bytes());
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn invoke_psubscribe(
+    store: &mut Store,
+    session: &mut Session,
+    patterns: Vec<String>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut result = vec![];
+
+    for pattern in patterns {
+        store
+            .pubsub
+            .psubscribe(&pattern, session.id, &session.sender);
+        session.patterns.insert(pattern.clone());
+
+        let reply = confirmation("psubscribe", Some(pattern), session.subscription_count());
+        result.extend_from_slice(reply.encode_with(session.protocol).as_bytes());
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn invoke_punsubscribe(
+    store: &mut Store,
+    session: &mut Session,
+    patterns: Vec<String>,
+) -> anyhow::Result<Vec<u8>> {
+    let patterns = if patterns.is_empty() {
+        session.patterns.iter().cloned().collect()
+    } else {
+        patterns
+    };
+
+    if patterns.is_empty() {
+        let reply = confirmation("punsubscribe", None, session.subscription_count());
+        return Ok(reply.encode_with(session.protocol).into_bytes());
+    }
+
+    let mut result = vec![];
+    for pattern in patterns {
+        store.pubsub.punsubscribe(&pattern, session.id);
+        session.patterns.remove(&pattern);
+
+        let reply = confirmation("punsubscribe", Some(pattern), session.subscription_count());
+        result.extend_from_slice(reply.encode_with(session.protocol).as_bytes());
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn invoke_ssubscribe(
+    store: &mut Store,
+    session: &mut Session,
+    channels: Vec<String>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut result = vec![];
+
+    for channel in channels {
+        store
+            .pubsub
+            .ssubscribe(&channel, session.id, &session.sender);
+        session.shard_channels.insert(channel.clone());
+
+        let reply = confirmation("ssubscribe", Some(channel), session.shard_channels.len());
+        result.extend_from_slice(reply.encode_with(session.protocol).as_bytes());
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn invoke_sunsubscribe(
+    store: &mut Store,
+    session: &mut Session,
+    channels: Vec<String>,
+) -> anyhow::Result<Vec<u8>> {
+    let channels = if channels.is_empty() {
+        session.shard_channels.iter().cloned().collect()
+    } else {
+        channels
+    };
+
+    if channels.is_empty() {
+        let reply = confirmation("sunsubscribe", None, session.shard_channels.len());
+        return Ok(reply.encode_with(session.protocol).into_bytes());
+    }
+
+    let mut result = vec![];
+    for channel in channels {
+        store.pubsub.sunsubscribe(&channel, session.id);
+        session.shard_channels.remove(&channel);
+
+        let reply = confirmation("sunsubscribe", Some(channel), session.shard_channels.len());
+        result.extend_from_slice(reply.encode_with(session.protocol).as_bytes());
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn invoke_publish(store: &Store, channel: &str, message: &str) -> anyhow::Result<Resp> {
+    let receivers = store.pubsub.publish(channel, message);
+
+    Ok(Resp::integer(receivers))
+}
+
+pub(crate) fn invoke_spublish(store: &Store, channel: &str, message: &str) -> anyhow::Result<Resp> {
+    let receivers = store.pubsub.spublish(channel, message);
+
+    Ok(Resp::integer(receivers))
+}
+
+pub(crate) fn invoke_pubsub(store: &Store, query: Query) -> anyhow::Result<Resp> {
+    let result = match query {
+        Query::Channels { pattern } => Resp::array(store.pubsub.channels(pattern.as_deref())),
+        Query::NumSub { channels } => Resp::Array(
+            channels
+                .into_iter()
+                .flat_map(|channel| {
+                    let count = store.pubsub.numsub(&channel);
+                    [Resp::bulk(channel), Resp::integer(count)]
+                })
+                .collect(),
+        ),
+        Query::NumPat => Resp::integer(store.pubsub.numpat()),
+        Query::ShardChannels { pattern } => {
+            Resp::array(store.pubsub.shard_channels(pattern.as_deref()))
+        }
+        Query::ShardNumSub { channels } => Resp::Array(
+            channels
+                .into_iter()
+                .flat_map(|channel| {
+                    let count = store.pubsub.shard_numsub(&channel);
+                    [Resp::bulk(channel), Resp::integer(count)]
+                })
+                .collect(),
+        ),
+    };
+
+    Ok(result)
+}
+
+/// Removes every subscription held by the session from the registry.
+pub(crate) fn unsubscribe_all(store: &mut Store, session: &mut Session) {
+    for channel in session.channels.drain() {
+        store.pubsub.unsubscribe(&channel, session.id);
+    }
+    for pattern in session.patterns.drain() {
+        store.pubsub.punsubscribe(&pattern, session.id);
+    }
+    for channel in session.shard_channels.drain() {
+        store.pubsub.sunsubscribe(&channel, session.id);
+    }
+}
@@ -1,6 +1,6 @@
-use std::str::FromStr;
+use std::{str::FromStr, vec};
 
-use crate::{Command, Resp, Store};
+use crate::{handler::Session, Command, Resp, Store};
 use anyhow::Context;
 
 #[derive(Debug)]
@@ -25,24 +25,56 @@ impl FromStr for Kind {
     }
 }
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let key: Kind = args
-        .next()
-        .context("Failed to parse 'KEY' for REPLCONF")?
-        .parse()?;
-    let value = args
-        .next()
-        .context("Failed to parse 'VALUE' for REPLCONF")?;
+#[derive(Debug)]
+pub(crate) struct ReplConf {
+    key: Kind,
+    value: String,
+}
+
+impl Command for ReplConf {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let key: Kind = args
+            .next()
+            .context("Failed to parse 'KEY' for REPLCONF")?
+            .parse()?;
+        let value = args
+            .next()
+            .context("Failed to parse 'VALUE' for REPLCONF")?;
+
+        Ok(ReplConf { key, value })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let ReplConf { key, value } = *self;
 
-    Ok(Command::ReplConf { key, value })
+        Ok(invoke(store, session, key, &value)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
 }
 
-pub(crate) fn invoke(store: &mut Store, key: Kind, _value: &str) -> anyhow::Result<Resp> {
+pub(crate) fn invoke(
+    store: &mut Store,
+    session: &mut Session,
+    key: Kind,
+    value: &str,
+) -> anyhow::Result<Resp> {
     let result = match key {
+        Kind::ListeningPort => {
+            session.listening_port = value
+                .parse()
+                .with_context(|| format!("Invalid listening port '{}'", value))?;
+            Resp::ok()
+        }
         Kind::GetAck => Resp::array(vec![
             "REPLCONF".to_string(),
             "ACK".to_string(),
-            store.get_offset().to_string(),
+            store.replication.offset.to_string(),
         ]),
         _ => Resp::ok(),
     };
@@ -0,0 +1,44 @@
+use std::vec;
+
+use super::{pubsub, watch, DEFAULT_USER};
+use crate::{handler::Session, resp::Protocol, Command, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct Reset;
+
+impl Command for Reset {
+    fn parse(_args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Reset)
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(invoke(store, session)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+/// Puts the connection back in the state it connected in: no transaction,
+/// WATCHed keys or subscriptions, RESP2, database 0, no name, replies on,
+/// and logged in as the default user if it has no password.
+pub(crate) fn invoke(store: &mut Store, session: &mut Session) -> anyhow::Result<Resp> {
+    session.transaction = None;
+    watch::unwatch_all(store, session);
+    pubsub::unsubscribe_all(store, session);
+
+    session.protocol = Protocol::Resp2;
+    session.db = 0;
+    session.name = None;
+    session.no_evict = false;
+    session.reply_off = false;
+    session.skip_reply = false;
+    session.user = DEFAULT_USER.to_string();
+    session.authenticated = store.acl.auto_login();
+
+    Ok(Resp::SimpleString("RESET".to_string()))
+}
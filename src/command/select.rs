@@ -0,0 +1,45 @@
+use std::vec;
+
+use anyhow::Context;
+
+use crate::{handler::Session, Command, RedisError, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct Select {
+    index: usize,
+}
+
+impl Command for Select {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let index = args
+            .next()
+            .context("Missing argument 'index' for SELECT command")?
+            .parse()
+            .map_err(|_| RedisError::NotInteger)?;
+
+        Ok(Select { index })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Select { index } = *self;
+
+        Ok(invoke(store, session, index)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(store: &Store, session: &mut Session, index: usize) -> anyhow::Result<Resp> {
+    if index >= store.config.databases() {
+        anyhow::bail!("DB index is out of range");
+    }
+
+    session.db = index;
+
+    Ok(Resp::ok())
+}
@@ -1,56 +1,159 @@
-use crate::{store::IntoSystemTime, Command, Resp, Store};
+use crate::{handler::Session, store::notify, Command, RedisError, Resp, Store};
 use anyhow::Context;
-use std::time::Duration;
+use std::{
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    vec,
+};
 
-enum ExpiryUnit {
-    PX,
-    EX,
+/// When a key set with an expiry option times out.
+#[derive(Debug)]
+pub(crate) enum Expiry {
+    /// EX and PX, counted from when the command runs.
+    After(Duration),
+    /// EXAT and PXAT.
+    At(SystemTime),
 }
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let key = args
-        .next()
-        .context("Missing argument 'key' for SET command")?;
-    let value = args
-        .next()
-        .context("Missing argument 'value' for SET command")?;
-    let unit = match args.next() {
-        Some(unit) => match unit.as_str() {
-            "PX" => Some(ExpiryUnit::PX),
-            "EX" => Some(ExpiryUnit::EX),
+#[derive(Debug)]
+pub(crate) struct Set {
+    key: String,
+    value: String,
+    expiry: Option<Expiry>,
+}
+
+impl Command for Set {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let key = args
+            .next()
+            .context("Missing argument 'key' for SET command")?;
+        let value = args
+            .next()
+            .context("Missing argument 'value' for SET command")?;
+        let unit = args.next().map(|unit| unit.to_uppercase());
+        let expiry = match (unit, args.next()) {
+            (Some(unit), Some(expiry_time)) => parse_expiry(&unit, &expiry_time)?,
             _ => None,
-        },
-        _ => None,
+        };
+
+        Ok(Set { key, value, expiry })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Set { key, value, expiry } = *self;
+
+        Ok(invoke(store, session, key, value, expiry, args)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+/// Reads the time given to EX, PX, EXAT or PXAT. Like redis-server, it must
+/// be positive, and the moment it expires must fit in milliseconds.
+fn parse_expiry(unit: &str, expiry_time: &str) -> anyhow::Result<Option<Expiry>> {
+    let expiry_time: i64 = expiry_time.parse().map_err(|_| RedisError::NotInteger)?;
+    let invalid = || RedisError::InvalidExpire("set".to_string());
+
+    let millis = match unit {
+        "PX" | "PXAT" => expiry_time,
+        "EX" | "EXAT" => expiry_time.checked_mul(1000).ok_or_else(invalid)?,
+        _ => return Ok(None),
     };
-    let expiry = match args.next() {
-        Some(expiry_time) => {
-            let expiry_time = expiry_time
-                .parse::<u64>()
-                .context("Failed to parse expiry")?;
-
-            match unit {
-                Some(ExpiryUnit::PX) => Some(Duration::from_millis(expiry_time)),
-                Some(ExpiryUnit::EX) => Some(Duration::from_secs(expiry_time)),
-                _ => None,
-            }
-        }
-        None => None,
+    if millis <= 0 {
+        return Err(invalid().into());
+    }
+    let duration = Duration::from_millis(millis as u64);
+
+    let expiry = if unit.ends_with("AT") {
+        Expiry::At(UNIX_EPOCH + duration)
+    } else {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        now.checked_add(millis).ok_or_else(invalid)?;
+        Expiry::After(duration)
     };
 
-    Ok(Command::Set { key, value, expiry })
+    Ok(Some(expiry))
 }
 
 pub(crate) fn invoke(
     store: &mut Store,
+    session: &Session,
     key: String,
     value: String,
-    expiry: Option<Duration>,
+    expiry: Option<Expiry>,
+    args: &mut Vec<String>,
 ) -> anyhow::Result<Resp> {
-    let expiry = expiry.into_system_time();
+    let expiry = expiry.map(|expiry| match expiry {
+        Expiry::After(duration) => SystemTime::now() + duration,
+        Expiry::At(time) => time,
+    });
+    // Replicas are sent the absolute time, so the key expires at the same
+    // moment on every one of them
+    if let Some(expiry) = expiry {
+        let millis = expiry.duration_since(UNIX_EPOCH).unwrap_or_default();
+        args.truncate(3);
+        args.extend(["PXAT".to_string(), millis.as_millis().to_string()]);
+    }
+
+    store.expire_if_needed(session.db, &key);
+    let is_new = !store.db(session.db).contains_key(&key);
+
     store
-        .db
-        .set(key, value, expiry)
+        .db_mut(session.db)
+        .set(key.clone(), value, expiry)
         .context("Failed to write data to store")?;
+    store.dirty += 1;
+
+    if is_new {
+        store.notify_keyspace_event(notify::NEW, "new", &key, session.db);
+    }
+    store.notify_keyspace_event(notify::STRING, "set", &key, session.db);
+    if expiry.is_some() {
+        store.notify_keyspace_event(notify::GENERIC, "expire", &key, session.db);
+    }
 
     Ok(Resp::ok())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expiry() {
+        assert!(matches!(
+            parse_expiry("EX", "10").unwrap(),
+            Some(Expiry::After(duration)) if duration == Duration::from_secs(10)
+        ));
+        assert!(matches!(
+            parse_expiry("PXAT", "1500").unwrap(),
+            Some(Expiry::At(at)) if at == UNIX_EPOCH + Duration::from_millis(1500)
+        ));
+
+        for (unit, time) in [
+            ("EX", "0"),
+            ("PX", "-5"),
+            ("EXAT", "18446744073709551615"),
+            ("EXAT", "9223372036854775807"),
+            ("PX", "9223372036854775807"),
+        ] {
+            let err = parse_expiry(unit, time).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref(),
+                    Some(RedisError::InvalidExpire(_) | RedisError::NotInteger)
+                ),
+                "{unit} {time}: {err}"
+            );
+        }
+        let err = parse_expiry("EX", "0").unwrap_err();
+        assert_eq!(err.to_string(), "ERR invalid expire time in 'set' command");
+    }
+}
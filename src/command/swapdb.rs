@@ -0,0 +1,57 @@
+use std::vec;
+
+use anyhow::Context;
+
+use crate::{handler::Session, Command, RedisError, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct SwapDb {
+    first: usize,
+    second: usize,
+}
+
+impl Command for SwapDb {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let first = args
+            .next()
+            .context("Missing argument 'index1' for SWAPDB command")?
+            .parse()
+            .map_err(|_| RedisError::NotInteger)?;
+        let second = args
+            .next()
+            .context("Missing argument 'index2' for SWAPDB command")?
+            .parse()
+            .map_err(|_| RedisError::NotInteger)?;
+
+        Ok(SwapDb { first, second })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let SwapDb { first, second } = *self;
+
+        Ok(invoke(store, first, second)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke(store: &mut Store, first: usize, second: usize) -> anyhow::Result<Resp> {
+    let databases = store.config.databases();
+    if first >= databases || second >= databases {
+        anyhow::bail!("DB index is out of range");
+    }
+
+    if first != second {
+        let (low, high) = (first.min(second), first.max(second));
+        let (head, tail) = store.dbs.split_at_mut(high);
+        head[low].swap_data(&mut tail[0]);
+        store.dirty += 1;
+    }
+
+    Ok(Resp::ok())
+}
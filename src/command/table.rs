@@ -0,0 +1,1049 @@
+use std::vec;
+
+use anyhow::Context;
+
+use super::{
+    acl, auth, client, command_cmd, config, del, flush, get, hello, info, keys, move_cmd, multi,
+    psync, pubsub, repl_conf, reset, select, set, swapdb, type_cmd, wait, watch, xadd,
+};
+use crate::{handler::Session, resp::Protocol, store::Store, Command, RedisError, Resp};
+
+/// Builds a command from the arguments following its name.
+type Parser = fn(&mut vec::IntoIter<String>) -> anyhow::Result<Box<dyn Command>>;
+
+fn parse_boxed<C: Command>(args: &mut vec::IntoIter<String>) -> anyhow::Result<Box<dyn Command>> {
+    Ok(Box::new(C::parse(args)?))
+}
+
+/// How the server treats a command, beyond the flags COMMAND reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Behavior {
+    /// Waits on other connections, so runs off the executor outside MULTI.
+    Blocking,
+    /// Allowed while a RESP2 connection is subscribed.
+    Subscribed,
+    /// Runs right away inside MULTI instead of being queued.
+    NotQueued,
+}
+
+/// Everything the server knows about a command: how to parse and run it,
+/// how it is treated, and what COMMAND INFO and COMMAND DOCS report for it.
+#[derive(Debug)]
+pub(crate) struct CommandSpec {
+    pub(crate) name: &'static str,
+    /// Number of arguments including the name; negative means "at least".
+    pub(crate) arity: i64,
+    pub(crate) flags: &'static [&'static str],
+    /// Position of the first key, or 0 if the command takes none.
+    pub(crate) first_key: i64,
+    /// Position of the last key; negative counts back from the end.
+    pub(crate) last_key: i64,
+    pub(crate) step: i64,
+    pub(crate) categories: &'static [&'static str],
+    pub(crate) group: &'static str,
+    pub(crate) since: &'static str,
+    pub(crate) summary: &'static str,
+    pub(crate) subcommands: &'static [CommandSpec],
+    /// Set on top-level commands; subcommands are parsed and run by their
+    /// container.
+    parser: Option<Parser>,
+    behavior: &'static [Behavior],
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    (first_key, last_key, step): (i64, i64, i64),
+    categories: &'static [&'static str],
+    (group, since, summary): (&'static str, &'static str, &'static str),
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key,
+        last_key,
+        step,
+        categories,
+        group,
+        since,
+        summary,
+        subcommands: &[],
+        parser: None,
+        behavior: &[],
+    }
+}
+
+impl CommandSpec {
+    const fn with_subcommands(self, subcommands: &'static [CommandSpec]) -> Self {
+        CommandSpec {
+            subcommands,
+            ..self
+        }
+    }
+
+    /// Registers `C` as the command this entry parses and runs.
+    const fn handler<C: Command>(self) -> Self {
+        CommandSpec {
+            parser: Some(parse_boxed::<C>),
+            ..self
+        }
+    }
+
+    const fn behaving(self, behavior: &'static [Behavior]) -> Self {
+        CommandSpec { behavior, ..self }
+    }
+
+    pub(crate) fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub(crate) fn is(&self, behavior: Behavior) -> bool {
+        self.behavior.contains(&behavior)
+    }
+
+    /// Whether replicas must replay the command to stay in sync.
+    pub(crate) fn replicates(&self) -> bool {
+        self.has_flag("write") || self.has_flag("may_replicate")
+    }
+
+    /// Builds the command from the arguments following its name.
+    pub(crate) fn parse(
+        &self,
+        args: &mut vec::IntoIter<String>,
+    ) -> anyhow::Result<Box<dyn Command>> {
+        let parse = self
+            .parser
+            .with_context(|| format!("No parser registered for '{}'", self.name))?;
+        parse(args)
+    }
+
+    pub(crate) fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+
+    /// The subcommand `args` invoke if this is a container command such as
+    /// CONFIG, or else this command.
+    pub(crate) fn resolve(&'static self, args: &[String]) -> &'static CommandSpec {
+        let sub = match args.get(1) {
+            Some(sub) => sub.to_lowercase(),
+            None => return self,
+        };
+        self.subcommands
+            .iter()
+            .find(|spec| spec.name.split('|').nth(1) == Some(&sub))
+            .unwrap_or(self)
+    }
+
+    /// Key arguments of `args`, located from the key positions.
+    pub(crate) fn keys<'a>(&self, args: &'a [String]) -> Vec<&'a String> {
+        if self.first_key <= 0 || self.first_key as usize >= args.len() {
+            return vec![];
+        }
+
+        let last = if self.last_key < 0 {
+            args.len() as i64 + self.last_key
+        } else {
+            self.last_key.min(args.len() as i64 - 1)
+        };
+        (self.first_key..=last)
+            .step_by(self.step.max(1) as usize)
+            .map(|i| &args[i as usize])
+            .collect()
+    }
+
+    /// Reply entry for COMMAND and COMMAND INFO.
+    pub(crate) fn info(&self) -> Resp {
+        let status = |values: &[&str]| {
+            Resp::Set(
+                values
+                    .iter()
+                    .map(|value| Resp::SimpleString(value.to_string()))
+                    .collect(),
+            )
+        };
+
+        Resp::Array(vec![
+            Resp::bulk(self.name),
+            Resp::Integer(self.arity),
+            status(self.flags),
+            Resp::Integer(self.first_key),
+            Resp::Integer(self.last_key),
+            Resp::Integer(self.step),
+            status(self.categories),
+            // Command tips and key specifications
+            Resp::Array(vec![]),
+            Resp::Array(vec![]),
+            Resp::Array(self.subcommands.iter().map(CommandSpec::info).collect()),
+        ])
+    }
+
+    /// Reply entry for COMMAND DOCS.
+    pub(crate) fn docs(&self) -> Resp {
+        let mut docs = vec![
+            (Resp::bulk("summary"), Resp::bulk(self.summary)),
+            (Resp::bulk("since"), Resp::bulk(self.since)),
+            (Resp::bulk("group"), Resp::bulk(self.group)),
+        ];
+        if !self.subcommands.is_empty() {
+            docs.push((
+                Resp::bulk("subcommands"),
+                Resp::Map(
+                    self.subcommands
+                        .iter()
+                        .map(|sub| (Resp::bulk(sub.name), sub.docs()))
+                        .collect(),
+                ),
+            ));
+        }
+
+        Resp::Map(docs)
+    }
+}
+
+/// Looks up a top-level command by name, ignoring case.
+pub(crate) fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+/// Spec of the command `args` invoke, descending into container commands
+/// such as CONFIG GET.
+pub(crate) fn resolve(args: &[String]) -> Option<&'static CommandSpec> {
+    Some(lookup(args.first()?)?.resolve(args))
+}
+
+/// Rejects requests with the wrong number of arguments for their command.
+/// Unknown commands and subcommands are left to the parsers to report.
+pub(crate) fn check_arity(args: &[String]) -> anyhow::Result<()> {
+    match resolve(args) {
+        Some(spec) if !spec.accepts(args.len()) => {
+            Err(RedisError::WrongArity(spec.name.to_string()).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug)]
+struct Ping;
+
+impl Command for Ping {
+    fn parse(_args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Ping)
+    }
+
+    fn execute(
+        self: Box<Self>,
+        _store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        // Subscribed RESP2 connections can't take simple replies, so PING
+        // answers in the shape of a pub/sub message
+        let reply = if session.is_subscribed() && session.protocol == Protocol::Resp2 {
+            Resp::array(vec!["pong".to_string(), "".to_string()])
+        } else {
+            Resp::SimpleString("PONG".to_string())
+        };
+
+        Ok(reply.encode_with(session.protocol).into_bytes())
+    }
+}
+
+#[derive(Debug)]
+struct Echo {
+    name: String,
+}
+
+impl Command for Echo {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let name = args
+            .next()
+            .context("Missing argument 'name' for ECHO command")?;
+
+        Ok(Echo { name })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        _store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(Resp::bulk(self.name)
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+#[derive(Debug)]
+struct Quit;
+
+impl Command for Quit {
+    fn parse(_args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Quit)
+    }
+
+    fn execute(
+        self: Box<Self>,
+        _store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        session.close_after_reply = true;
+
+        Ok(Resp::ok().encode_with(session.protocol).into_bytes())
+    }
+}
+
+// (Un)subscribing replies once per channel, which an EXEC reply has no
+// room for
+const PUBSUB_FLAGS: &[&str] = &["pubsub", "noscript", "loading", "stale", "no_multi"];
+const CLIENT_ADMIN_FLAGS: &[&str] = &["admin", "noscript", "loading", "stale"];
+const CLIENT_ADMIN_CATEGORIES: &[&str] = &["@admin", "@slow", "@dangerous", "@connection"];
+const ACL_ADMIN_FLAGS: &[&str] = &["admin", "noscript", "loading", "stale"];
+const ACL_ADMIN_CATEGORIES: &[&str] = &["@admin", "@slow", "@dangerous"];
+const TRANSACTION_FLAGS: &[&str] = &["noscript", "loading", "stale", "fast", "allow_busy"];
+
+pub(crate) static COMMANDS: &[CommandSpec] = &[
+    spec(
+        "ping",
+        -1,
+        &["fast"],
+        (0, 0, 0),
+        &["@fast", "@connection"],
+        ("connection", "1.0.0", "Returns the server's liveliness response."),
+    )
+    .handler::<Ping>()
+    .behaving(&[Behavior::Subscribed]),
+    spec(
+        "echo",
+        2,
+        &["fast"],
+        (0, 0, 0),
+        &["@fast", "@connection"],
+        ("connection", "1.0.0", "Returns the given string."),
+    )
+    .handler::<Echo>(),
+    spec(
+        "quit",
+        -1,
+        &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        (0, 0, 0),
+        &["@fast", "@connection"],
+        ("connection", "1.0.0", "Closes the connection."),
+    )
+    .handler::<Quit>()
+    .behaving(&[Behavior::Subscribed, Behavior::NotQueued]),
+    spec(
+        "reset",
+        1,
+        &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        (0, 0, 0),
+        &["@fast", "@connection"],
+        ("connection", "6.2.0", "Resets the connection."),
+    )
+    .handler::<reset::Reset>()
+    .behaving(&[Behavior::Subscribed, Behavior::NotQueued]),
+    spec(
+        "get",
+        2,
+        &["readonly", "fast"],
+        (1, 1, 1),
+        &["@read", "@string", "@fast"],
+        ("string", "1.0.0", "Returns the string value of a key."),
+    )
+    .handler::<get::Get>(),
+    spec(
+        "set",
+        -3,
+        &["write", "denyoom"],
+        (1, 1, 1),
+        &["@write", "@string", "@slow"],
+        ("string", "1.0.0", "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist."),
+    )
+    .handler::<set::Set>(),
+    spec(
+        "config",
+        -2,
+        &[],
+        (0, 0, 0),
+        &["@slow"],
+        ("server", "2.0.0", "A container for server configuration commands."),
+    )
+    .handler::<config::Config>()
+    .with_subcommands(&[
+        spec(
+            "config|get",
+            -3,
+            &["admin", "noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@admin", "@slow", "@dangerous"],
+            ("server", "2.0.0", "Returns the effective values of configuration parameters."),
+        ),
+        spec(
+            "config|set",
+            -4,
+            &["admin", "noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@admin", "@slow", "@dangerous"],
+            ("server", "2.0.0", "Sets configuration parameters in-flight."),
+        ),
+        spec(
+            "config|resetstat",
+            2,
+            &["admin", "noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@admin", "@slow", "@dangerous"],
+            ("server", "2.0.0", "Resets the server's statistics."),
+        ),
+        spec(
+            "config|rewrite",
+            2,
+            &["admin", "noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@admin", "@slow", "@dangerous"],
+            (
+                "server",
+                "2.8.0",
+                "Persists the effective configuration to file.",
+            ),
+        ),
+    ]),
+    spec(
+        "keys",
+        2,
+        &["readonly"],
+        (0, 0, 0),
+        &["@keyspace", "@read", "@slow", "@dangerous"],
+        ("generic", "1.0.0", "Returns all key names that match a pattern."),
+    )
+    .handler::<keys::Keys>(),
+    spec(
+        "info",
+        -1,
+        &["loading", "stale"],
+        (0, 0, 0),
+        &["@slow", "@dangerous"],
+        ("server", "1.0.0", "Returns information and statistics about the server."),
+    )
+    .handler::<info::Info>(),
+    spec(
+        "replconf",
+        -1,
+        &["admin", "noscript", "loading", "stale", "allow_busy"],
+        (0, 0, 0),
+        &["@admin", "@slow", "@dangerous"],
+        ("server", "3.0.0", "An internal command for configuring the replication stream."),
+    )
+    .handler::<repl_conf::ReplConf>(),
+    spec(
+        "psync",
+        -3,
+        &["admin", "noscript", "no_async_loading", "no_multi"],
+        (0, 0, 0),
+        &["@admin", "@slow", "@dangerous"],
+        ("server", "2.8.0", "An internal command used in replication."),
+    )
+    .handler::<psync::Psync>(),
+    spec(
+        "wait",
+        3,
+        &["noscript"],
+        (0, 0, 0),
+        &["@slow", "@connection"],
+        ("generic", "3.0.0", "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed."),
+    )
+    .handler::<wait::Wait>()
+    .behaving(&[Behavior::Blocking]),
+    spec(
+        "del",
+        -2,
+        &["write"],
+        (1, -1, 1),
+        &["@keyspace", "@write", "@slow"],
+        ("generic", "1.0.0", "Deletes one or more keys."),
+    )
+    .handler::<del::Del>(),
+    spec(
+        "type",
+        2,
+        &["readonly", "fast"],
+        (1, 1, 1),
+        &["@keyspace", "@read", "@fast"],
+        ("generic", "1.0.0", "Determines the type of value stored at a key."),
+    )
+    .handler::<type_cmd::Type>(),
+    spec(
+        "xadd",
+        -5,
+        &["write", "denyoom", "fast"],
+        (1, 1, 1),
+        &["@write", "@stream", "@fast"],
+        ("stream", "5.0.0", "Appends a new message to a stream. Creates the key if it doesn't exist."),
+    )
+    .handler::<xadd::Xadd>(),
+    spec(
+        "select",
+        2,
+        &["loading", "stale", "fast"],
+        (0, 0, 0),
+        &["@fast", "@connection"],
+        ("connection", "1.0.0", "Changes the selected database."),
+    )
+    .handler::<select::Select>(),
+    spec(
+        "move",
+        3,
+        &["write", "fast"],
+        (1, 1, 1),
+        &["@keyspace", "@write", "@fast"],
+        ("generic", "1.0.0", "Moves a key to another database."),
+    )
+    .handler::<move_cmd::Move>(),
+    spec(
+        "swapdb",
+        3,
+        &["write", "fast"],
+        (0, 0, 0),
+        &["@keyspace", "@write", "@fast", "@dangerous"],
+        ("server", "4.0.0", "Swaps two Redis databases."),
+    )
+    .handler::<swapdb::SwapDb>(),
+    spec(
+        "flushdb",
+        -1,
+        &["write"],
+        (0, 0, 0),
+        &["@keyspace", "@write", "@slow", "@dangerous"],
+        ("server", "1.0.0", "Remove all keys from the current database."),
+    )
+    .handler::<flush::FlushDb>(),
+    spec(
+        "flushall",
+        -1,
+        &["write"],
+        (0, 0, 0),
+        &["@keyspace", "@write", "@slow", "@dangerous"],
+        ("server", "1.0.0", "Removes all keys from all databases."),
+    )
+    .handler::<flush::FlushAll>(),
+    spec(
+        "multi",
+        1,
+        TRANSACTION_FLAGS,
+        (0, 0, 0),
+        &["@fast", "@transaction"],
+        ("transactions", "1.2.0", "Starts a transaction."),
+    )
+    .handler::<multi::Multi>()
+    .behaving(&[Behavior::NotQueued]),
+    spec(
+        "exec",
+        1,
+        &["noscript", "loading", "stale", "skip_slowlog"],
+        (0, 0, 0),
+        &["@slow", "@transaction"],
+        ("transactions", "1.2.0", "Executes all commands in a transaction."),
+    )
+    .handler::<multi::Exec>()
+    .behaving(&[Behavior::NotQueued]),
+    spec(
+        "discard",
+        1,
+        TRANSACTION_FLAGS,
+        (0, 0, 0),
+        &["@fast", "@transaction"],
+        ("transactions", "2.0.0", "Discards a transaction."),
+    )
+    .handler::<multi::Discard>()
+    .behaving(&[Behavior::NotQueued]),
+    spec(
+        "watch",
+        -2,
+        TRANSACTION_FLAGS,
+        (1, -1, 1),
+        &["@fast", "@transaction"],
+        ("transactions", "2.2.0", "Monitors changes to keys to determine the execution of a transaction."),
+    )
+    .handler::<watch::Watch>()
+    .behaving(&[Behavior::NotQueued]),
+    spec(
+        "unwatch",
+        1,
+        TRANSACTION_FLAGS,
+        (0, 0, 0),
+        &["@fast", "@transaction"],
+        ("transactions", "2.2.0", "Forgets about watched keys of a transaction."),
+    )
+    .handler::<watch::Unwatch>(),
+    spec(
+        "subscribe",
+        -2,
+        PUBSUB_FLAGS,
+        (0, 0, 0),
+        &["@pubsub", "@slow"],
+        ("pubsub", "2.0.0", "Listens for messages published to channels."),
+    )
+    .handler::<pubsub::Subscribe>()
+    .behaving(&[Behavior::Subscribed]),
+    spec(
+        "unsubscribe",
+        -1,
+        PUBSUB_FLAGS,
+        (0, 0, 0),
+        &["@pubsub", "@slow"],
+        ("pubsub", "2.0.0", "Stops listening to messages posted to channels."),
+    )
+    .handler::<pubsub::Unsubscribe>()
+    .behaving(&[Behavior::Subscribed]),
+    spec(
+        "psubscribe",
+        -2,
+        PUBSUB_FLAGS,
+        (0, 0, 0),
+        &["@pubsub", "@slow"],
+        ("pubsub", "2.0.0", "Listens for messages published to channels that match one or more patterns."),
+    )
+    .handler::<pubsub::Psubscribe>()
+    .behaving(&[Behavior::Subscribed]),
+    spec(
+        "punsubscribe",
+        -1,
+        PUBSUB_FLAGS,
+        (0, 0, 0),
+        &["@pubsub", "@slow"],
+        ("pubsub", "2.0.0", "Stops listening to messages published to channels that match one or more patterns."),
+    )
+    .handler::<pubsub::Punsubscribe>()
+    .behaving(&[Behavior::Subscribed]),
+    spec(
+        "publish",
+        3,
+        &["pubsub", "loading", "stale", "fast", "may_replicate"],
+        (0, 0, 0),
+        &["@pubsub", "@fast"],
+        ("pubsub", "2.0.0", "Posts a message to a channel."),
+    )
+    .handler::<pubsub::Publish>(),
+    spec(
+        "pubsub",
+        -2,
+        &[],
+        (0, 0, 0),
+        &["@slow"],
+        ("pubsub", "2.8.0", "A container for Pub/Sub commands."),
+    )
+    .handler::<pubsub::PubSub>()
+    .with_subcommands(&[
+        spec(
+            "pubsub|channels",
+            -2,
+            &["pubsub", "loading", "stale"],
+            (0, 0, 0),
+            &["@pubsub", "@slow"],
+            ("pubsub", "2.8.0", "Returns the active channels."),
+        ),
+        spec(
+            "pubsub|numsub",
+            -2,
+            &["pubsub", "loading", "stale"],
+            (0, 0, 0),
+            &["@pubsub", "@slow"],
+            ("pubsub", "2.8.0", "Returns a count of subscribers to channels."),
+        ),
+        spec(
+            "pubsub|numpat",
+            2,
+            &["pubsub", "loading", "stale"],
+            (0, 0, 0),
+            &["@pubsub", "@slow"],
+            ("pubsub", "2.8.0", "Returns a count of unique pattern subscriptions."),
+        ),
+        spec(
+            "pubsub|shardchannels",
+            -2,
+            &["pubsub", "loading", "stale"],
+            (0, 0, 0),
+            &["@pubsub", "@slow"],
+            ("pubsub", "7.0.0", "Returns the active shard channels."),
+        ),
+        spec(
+            "pubsub|shardnumsub",
+            -2,
+            &["pubsub", "loading", "stale"],
+            (0, 0, 0),
+            &["@pubsub", "@slow"],
+            ("pubsub", "7.0.0", "Returns the count of subscribers of shard channels."),
+        ),
+    ]),
+    spec(
+        "ssubscribe",
+        -2,
+        PUBSUB_FLAGS,
+        (1, -1, 1),
+        &["@pubsub", "@slow"],
+        ("pubsub", "7.0.0", "Listens for messages published to shard channels."),
+    )
+    .handler::<pubsub::Ssubscribe>()
+    .behaving(&[Behavior::Subscribed]),
+    spec(
+        "sunsubscribe",
+        -1,
+        PUBSUB_FLAGS,
+        (1, -1, 1),
+        &["@pubsub", "@slow"],
+        ("pubsub", "7.0.0", "Stops listening to messages posted to shard channels."),
+    )
+    .handler::<pubsub::Sunsubscribe>()
+    .behaving(&[Behavior::Subscribed]),
+    spec(
+        "spublish",
+        3,
+        &["pubsub", "loading", "stale", "fast", "may_replicate"],
+        (1, 1, 1),
+        &["@pubsub", "@fast"],
+        ("pubsub", "7.0.0", "Post a message to a shard channel"),
+    )
+    .handler::<pubsub::Spublish>(),
+    spec(
+        "hello",
+        -1,
+        &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        (0, 0, 0),
+        &["@fast", "@connection"],
+        ("connection", "6.0.0", "Handshakes with the Redis server."),
+    )
+    .handler::<hello::Hello>(),
+    spec(
+        "auth",
+        -2,
+        &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        (0, 0, 0),
+        &["@fast", "@connection"],
+        ("connection", "1.0.0", "Authenticates the connection."),
+    )
+    .handler::<auth::Auth>(),
+    spec(
+        "acl",
+        -2,
+        &[],
+        (0, 0, 0),
+        &["@slow"],
+        ("server", "6.0.0", "A container for Access List Control commands."),
+    )
+    .handler::<acl::Acl>()
+    .with_subcommands(&[
+        spec(
+            "acl|cat",
+            -2,
+            &["noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@slow"],
+            ("server", "6.0.0", "Lists the ACL categories, or the commands inside a category."),
+        ),
+        spec(
+            "acl|deluser",
+            -3,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Deletes ACL users, and terminates their connections."),
+        ),
+        spec(
+            "acl|dryrun",
+            -4,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "7.0.0", "Simulates the execution of a command by a user, without executing the command."),
+        ),
+        spec(
+            "acl|getuser",
+            3,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Lists the ACL rules of a user."),
+        ),
+        spec(
+            "acl|list",
+            2,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Dumps the effective rules in ACL file format."),
+        ),
+        spec(
+            "acl|load",
+            2,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Reloads the rules from the configured ACL file."),
+        ),
+        spec(
+            "acl|log",
+            -2,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Lists recent security events generated due to ACL rules."),
+        ),
+        spec(
+            "acl|save",
+            2,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Saves the effective ACL rules in the configured ACL file."),
+        ),
+        spec(
+            "acl|setuser",
+            -3,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Creates and modifies an ACL user and its rules."),
+        ),
+        spec(
+            "acl|users",
+            2,
+            ACL_ADMIN_FLAGS,
+            (0, 0, 0),
+            ACL_ADMIN_CATEGORIES,
+            ("server", "6.0.0", "Lists all ACL users."),
+        ),
+        spec(
+            "acl|whoami",
+            2,
+            &["noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@slow"],
+            ("server", "6.0.0", "Returns the authenticated username of the current connection."),
+        ),
+    ]),
+    spec(
+        "client",
+        -2,
+        &[],
+        (0, 0, 0),
+        &["@slow"],
+        ("connection", "2.4.0", "A container for client connection commands."),
+    )
+    .handler::<client::Client>()
+    .with_subcommands(&[
+        spec(
+            "client|getname",
+            2,
+            &["noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("connection", "2.6.9", "Returns the name of the connection."),
+        ),
+        spec(
+            "client|id",
+            2,
+            &["noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("connection", "5.0.0", "Returns the unique client ID of the connection."),
+        ),
+        spec(
+            "client|info",
+            2,
+            &["noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("connection", "6.2.0", "Returns information about the connection."),
+        ),
+        spec(
+            "client|kill",
+            -3,
+            CLIENT_ADMIN_FLAGS,
+            (0, 0, 0),
+            CLIENT_ADMIN_CATEGORIES,
+            ("connection", "2.4.0", "Terminates open connections."),
+        ),
+        spec(
+            "client|list",
+            -2,
+            CLIENT_ADMIN_FLAGS,
+            (0, 0, 0),
+            CLIENT_ADMIN_CATEGORIES,
+            ("connection", "2.4.0", "Lists open connections."),
+        ),
+        spec(
+            "client|no-evict",
+            3,
+            CLIENT_ADMIN_FLAGS,
+            (0, 0, 0),
+            CLIENT_ADMIN_CATEGORIES,
+            ("connection", "7.0.0", "Sets the client eviction mode of the connection."),
+        ),
+        spec(
+            "client|pause",
+            -3,
+            CLIENT_ADMIN_FLAGS,
+            (0, 0, 0),
+            CLIENT_ADMIN_CATEGORIES,
+            ("connection", "3.0.0", "Suspends commands processing."),
+        ),
+        spec(
+            "client|reply",
+            3,
+            &["noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("connection", "3.2.0", "Instructs the server whether to reply to commands."),
+        ),
+        spec(
+            "client|setname",
+            3,
+            &["noscript", "loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("connection", "2.6.9", "Sets the connection name."),
+        ),
+        spec(
+            "client|unpause",
+            2,
+            CLIENT_ADMIN_FLAGS,
+            (0, 0, 0),
+            CLIENT_ADMIN_CATEGORIES,
+            ("connection", "6.2.0", "Resumes processing of clients that were paused."),
+        ),
+    ]),
+    spec(
+        "command",
+        -1,
+        &["loading", "stale"],
+        (0, 0, 0),
+        &["@slow", "@connection"],
+        ("server", "2.8.13", "Returns detailed information about all commands."),
+    )
+    .handler::<command_cmd::Introspect>()
+    .with_subcommands(&[
+        spec(
+            "command|count",
+            2,
+            &["loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("server", "2.8.13", "Returns a count of commands."),
+        ),
+        spec(
+            "command|docs",
+            -2,
+            &["loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("server", "7.0.0", "Returns documentary information about one, multiple or all commands."),
+        ),
+        spec(
+            "command|getkeys",
+            -3,
+            &["loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("server", "2.8.13", "Extracts the key names from an arbitrary command."),
+        ),
+        spec(
+            "command|info",
+            -2,
+            &["loading", "stale"],
+            (0, 0, 0),
+            &["@slow", "@connection"],
+            ("server", "2.8.13", "Returns information about one, multiple or all commands."),
+        ),
+    ]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Call;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_check_arity() {
+        assert!(check_arity(&args("GET a")).is_ok());
+        assert!(check_arity(&args("SET a")).is_err());
+        assert!(check_arity(&args("SET a b PX 10")).is_ok());
+        assert!(check_arity(&args("CONFIG GET")).is_err());
+        assert!(check_arity(&args("PUBSUB NUMPAT extra")).is_err());
+        // Unknown subcommands are reported by the parser
+        assert!(check_arity(&args("CONFIG nope")).is_ok());
+    }
+
+    #[test]
+    fn test_keys() {
+        let watch = lookup("watch").unwrap();
+        assert_eq!(watch.keys(&args("WATCH a b c")), vec!["a", "b", "c"]);
+
+        let set = lookup("set").unwrap();
+        assert_eq!(set.keys(&args("SET a 1 PX 10")), vec!["a"]);
+
+        assert!(lookup("ping").unwrap().keys(&args("PING x")).is_empty());
+    }
+
+    #[test]
+    fn test_top_level_commands_have_handlers() {
+        for spec in COMMANDS {
+            assert!(spec.parser.is_some(), "{} has no handler", spec.name);
+            for sub in spec.subcommands {
+                assert!(sub.parser.is_none(), "{} is run by its container", sub.name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_command_dispatches() {
+        let config = crate::store::load_config(&[]).unwrap();
+        let mut store = Store::init(config).await.unwrap();
+        let commands = COMMANDS
+            .iter()
+            .flat_map(|spec| std::iter::once(spec).chain(spec.subcommands));
+
+        for spec in commands {
+            // Just enough arguments for the arity, naming the subcommand
+            let mut args: Vec<String> = spec.name.split('|').map(str::to_string).collect();
+            while !spec.accepts(args.len()) {
+                args.push("1".to_string());
+            }
+            let call = match Call::parse(args) {
+                Ok(call) => call,
+                Err(err) => {
+                    assert!(
+                        !err.to_string().contains("No parser registered"),
+                        "{} isn't dispatched",
+                        spec.name
+                    );
+                    continue;
+                }
+            };
+            assert_eq!(call.name, spec.name);
+
+            let (sender, _) = tokio::sync::mpsc::unbounded_channel();
+            let mut session = Session::new(
+                sender,
+                "127.0.0.1:50000".to_string(),
+                "127.0.0.1:6379".to_string(),
+            );
+            let applied = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let _ = call.apply(&mut store, &mut session);
+            }));
+            assert!(applied.is_ok(), "{} panicked", spec.name);
+        }
+    }
+}
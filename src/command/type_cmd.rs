@@ -1,14 +1,38 @@
-use crate::{Command, Resp, Store};
+use std::vec;
+
+use crate::{handler::Session, Command, Resp, Store};
 use anyhow::Context;
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let key = args
-        .next()
-        .context("Missing argument 'key' for TYPE command")?;
+#[derive(Debug)]
+pub(crate) struct Type {
+    key: String,
+}
+
+impl Command for Type {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let key = args
+            .next()
+            .context("Missing argument 'key' for TYPE command")?;
+
+        Ok(Type { key })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Type { key } = *self;
 
-    Ok(Command::Type { key })
+        Ok(invoke(store, session, &key)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
 }
 
-pub(crate) async fn invoke(store: &mut Store, key: &str) -> anyhow::Result<Resp> {
-    Ok(Resp::SimpleString(store.db.get_type(key).to_string()))
+pub(crate) fn invoke(store: &mut Store, session: &Session, key: &str) -> anyhow::Result<Resp> {
+    Ok(Resp::SimpleString(
+        store.db(session.db).get_type(key).to_string(),
+    ))
 }
@@ -1,110 +1,151 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+    vec,
+};
 
 use anyhow::Context;
-use tokio::sync::Mutex;
+use bytes::Bytes;
 use tokio::time::Instant;
 
-use crate::{Command, Resp, Store};
-
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let numreplicas: u8 = args
-        .next()
-        .context("Missing argument 'numreplicas' for WAIT command")?
-        .parse()?;
-    let timeout: u16 = args
-        .next()
-        .context("Missing argument 'timeout' for WAIT command")?
-        .parse()?;
-
-    Ok(Command::Wait {
-        numreplicas,
-        timeout,
-    })
+use crate::{handler::Session, store::Executor, Command, RedisError, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct Wait {
+    pub(crate) numreplicas: u8,
+    pub(crate) timeout: u16,
 }
 
+impl Command for Wait {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let numreplicas: u8 = args
+            .next()
+            .context("Missing argument 'numreplicas' for WAIT command")?
+            .parse()
+            .map_err(|_| RedisError::NotInteger)?;
+        let timeout: u16 = args
+            .next()
+            .context("Missing argument 'timeout' for WAIT command")?
+            .parse()
+            .map_err(|_| RedisError::NotInteger)?;
+
+        Ok(Wait {
+            numreplicas,
+            timeout,
+        })
+    }
+
+    /// Only reached inside EXEC; otherwise WAIT blocks off the executor.
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(invoke_nonblocking(store)
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+/// Blocks until `numreplicas` replicas acknowledge every write propagated
+/// so far, or `timeout` milliseconds pass. Runs off the executor, so other
+/// clients carry on meanwhile.
 pub(crate) async fn invoke(
-    store: Arc<Mutex<Store>>,
+    executor: &Executor,
     numreplicas: u8,
     timeout: u16,
-) -> anyhow::Result<Vec<u8>> {
+) -> anyhow::Result<Resp> {
     let deadline = Instant::now() + Duration::from_millis(timeout as u64);
 
-    let (master_offset, replica_count) = {
-        let s = store.lock().await;
-        (s.master_repl_offset, s.replicas.len())
-    };
+    let (master_offset, acks, notify) = executor
+        .run(move |store| {
+            let acks: Vec<_> = store
+                .replicas
+                .iter()
+                .map(|r| Arc::clone(&r.ack_offset))
+                .collect();
+
+            // Ask every replica for its offset only when too few have
+            // acknowledged the current one. The GETACK itself is past the
+            // offset they acknowledge.
+            let offset = store.replication.offset;
+            let acked = acks
+                .iter()
+                .filter(|ack| ack.load(Ordering::SeqCst) >= offset)
+                .count();
+            if offset > 0 && acked < numreplicas as usize {
+                let getack = Resp::array(vec![
+                    "REPLCONF".to_string(),
+                    "GETACK".to_string(),
+                    "*".to_string(),
+                ]);
+                store.send_to_replicas(Bytes::from(getack.encode()));
+            }
+
+            (offset, acks, Arc::clone(&store.replica_acks))
+        })
+        .await?;
 
     // Fast path: no writes have been propagated yet
     if master_offset == 0 {
-        return Ok(Resp::integer(replica_count).encode().into_bytes());
+        return Ok(Resp::integer(acks.len()));
     }
 
-    // Send REPLCONF GETACK * to all replicas
-    let getack = Resp::array(vec![
-        "REPLCONF".to_string(),
-        "GETACK".to_string(),
-        "*".to_string(),
-    ])
-    .encode();
-    {
-        let mut s = store.lock().await;
-        for replica in s.replicas.iter_mut() {
-            if let Err(e) = replica.conn.write_raw(getack.as_bytes()).await {
-                eprintln!("Failed to send GETACK to replica; Err = {:?}", e);
-            }
-        }
-    }
+    let acked = || {
+        acks.iter()
+            .filter(|ack| ack.load(Ordering::SeqCst) >= master_offset)
+            .count()
+    };
 
-    // Poll for ACK responses until enough replicas confirm or timeout expires
     loop {
-        let now = Instant::now();
-        if now >= deadline {
-            break;
-        }
+        // Registered before counting, so an ACK in between isn't missed
+        let notified = notify.notified();
 
+        if acked() >= numreplicas as usize
+            || tokio::time::timeout_at(deadline, notified).await.is_err()
         {
-            let mut s = store.lock().await;
-            for replica in s.replicas.iter_mut() {
-                if replica.ack_offset >= master_offset {
-                    continue;
-                }
-                match tokio::time::timeout(Duration::from_millis(5), replica.conn.read_frame())
-                    .await
-                {
-                    Ok(Ok((_, args))) => {
-                        // Expect: REPLCONF ACK <offset>
-                        if args.get(0).map(|s| s.to_uppercase()) == Some("REPLCONF".to_string())
-                            && args.get(1).map(|s| s.to_uppercase()) == Some("ACK".to_string())
-                        {
-                            if let Some(offset) = args.get(2).and_then(|s| s.parse::<usize>().ok())
-                            {
-                                replica.ack_offset = offset;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-
-            let acked = s
-                .replicas
-                .iter()
-                .filter(|r| r.ack_offset >= master_offset)
-                .count();
-            if acked >= numreplicas as usize {
-                return Ok(Resp::integer(acked).encode().into_bytes());
-            }
+            return Ok(Resp::integer(acked()));
         }
-
-        tokio::time::sleep(Duration::from_millis(5)).await;
     }
+}
 
-    let s = store.lock().await;
-    let acked = s
+/// WAIT inside a transaction can't block, so it only reports replicas that
+/// have already acknowledged the current offset.
+pub(crate) fn invoke_nonblocking(store: &Store) -> Resp {
+    let acked = store
         .replicas
         .iter()
-        .filter(|r| r.ack_offset >= master_offset)
+        .filter(|r| r.ack_offset.load(Ordering::SeqCst) >= store.replication.offset)
         .count();
-    Ok(Resp::integer(acked).encode().into_bytes())
+
+    Resp::integer(acked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::load_config;
+
+    #[tokio::test]
+    async fn test_getack_only_when_replicas_lag() {
+        let mut store = Store::init(load_config(&[]).unwrap()).await.unwrap();
+        let mut link = store.add_replica("127.0.0.1", 6380);
+        store.replication.offset = 100;
+        link.ack_offset.store(100, Ordering::SeqCst);
+        let executor = Executor::spawn(store);
+
+        let reply = invoke(&executor, 1, 1000).await.unwrap();
+        assert_eq!(reply, Resp::integer(1));
+        assert!(link.stream.try_recv().is_err());
+
+        link.ack_offset.store(50, Ordering::SeqCst);
+        let reply = invoke(&executor, 1, 10).await.unwrap();
+        assert_eq!(reply, Resp::integer(0));
+        let sent = link.stream.try_recv().unwrap();
+        assert_eq!(
+            &sent[..],
+            b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n"
+        );
+    }
 }
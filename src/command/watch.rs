@@ -0,0 +1,103 @@
+use std::{sync::atomic::Ordering, vec};
+
+use anyhow::Context;
+
+use crate::{handler::Session, Command, Resp, Store};
+
+#[derive(Debug)]
+pub(crate) struct Watch {
+    keys: Vec<String>,
+}
+
+impl Command for Watch {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let key = args
+            .next()
+            .context("Missing argument 'key' for WATCH command")?;
+        let keys = std::iter::once(key).chain(args).collect();
+
+        Ok(Watch { keys })
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Watch { keys } = *self;
+
+        Ok(invoke_watch(store, session, keys)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke_watch(
+    store: &mut Store,
+    session: &mut Session,
+    keys: Vec<String>,
+) -> anyhow::Result<Resp> {
+    if session.transaction.is_some() {
+        anyhow::bail!("WATCH inside MULTI is not allowed");
+    }
+
+    for key in keys {
+        // A key that already expired must not count as modified later on
+        store.expire_if_needed(session.db, &key);
+        store.db_mut(session.db).watch(&key, &session.watch_dirty);
+
+        let watched = (session.db, key);
+        if !session.watched.contains(&watched) {
+            session.watched.push(watched);
+        }
+    }
+
+    Ok(Resp::ok())
+}
+
+#[derive(Debug)]
+pub(crate) struct Unwatch;
+
+impl Command for Unwatch {
+    fn parse(_args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        Ok(Unwatch)
+    }
+
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        _args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(invoke_unwatch(store, session)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
+}
+
+pub(crate) fn invoke_unwatch(store: &mut Store, session: &mut Session) -> anyhow::Result<Resp> {
+    unwatch_all(store, session);
+
+    Ok(Resp::ok())
+}
+
+/// Reports whether any watched key was modified since WATCH, counting keys
+/// whose TTL has elapsed in the meantime.
+pub(crate) fn is_dirty(store: &mut Store, session: &Session) -> bool {
+    for (db, key) in session.watched.iter() {
+        store.expire_if_needed(*db, key);
+    }
+
+    session.watch_dirty.load(Ordering::SeqCst)
+}
+
+pub(crate) fn unwatch_all(store: &mut Store, session: &mut Session) {
+    for (db, key) in session.watched.drain(..) {
+        if let Some(db) = store.dbs.get_mut(db) {
+            db.unwatch(&key, &session.watch_dirty);
+        }
+    }
+
+    session.watch_dirty.store(false, Ordering::SeqCst);
+}
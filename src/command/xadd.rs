@@ -1,37 +1,71 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use crate::{store::RedisValue, Command, Resp, Store};
+use std::{
+    time::{SystemTime, UNIX_EPOCH},
+    vec,
+};
+
+use crate::{
+    handler::Session,
+    store::{notify, RedisValue},
+    Command, RedisError, Resp, Store,
+};
 use anyhow::Context;
 
-pub(crate) fn parse(args: &mut impl Iterator<Item = String>) -> anyhow::Result<Command> {
-    let key = args
-        .next()
-        .context("Missing argument 'key' for XADD command")?;
-    let id = args
-        .next()
-        .context("Missing argument 'id' for XADD command")?;
-    let mut fields = vec![];
-
-    while let Some(field) = args.next() {
-        let value = args
+#[derive(Debug)]
+pub(crate) struct Xadd {
+    key: String,
+    id: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Command for Xadd {
+    fn parse(args: &mut vec::IntoIter<String>) -> anyhow::Result<Self> {
+        let key = args
             .next()
-            .context("Missing value for 'field' for XADD command")?;
+            .context("Missing argument 'key' for XADD command")?;
+        let id = args
+            .next()
+            .context("Missing argument 'id' for XADD command")?;
+        let mut fields = vec![];
+
+        while let Some(field) = args.next() {
+            let value = args
+                .next()
+                .context("Missing value for 'field' for XADD command")?;
 
-        fields.push((field, value));
+            fields.push((field, value));
+        }
+
+        Ok(Xadd { key, id, fields })
     }
 
-    Ok(Command::Xadd { key, id, fields })
+    fn execute(
+        self: Box<Self>,
+        store: &mut Store,
+        session: &mut Session,
+        args: &mut Vec<String>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let Xadd { key, id, fields } = *self;
+
+        Ok(invoke(store, session, key, id, fields, args)?
+            .encode_with(session.protocol)
+            .into_bytes())
+    }
 }
 
 pub(crate) fn invoke(
     store: &mut Store,
+    session: &Session,
     key: String,
     id: String,
     fields: Vec<(String, String)>,
+    args: &mut [String],
 ) -> anyhow::Result<Resp> {
+    store.expire_if_needed(session.db, &key);
+    let is_new = !store.db(session.db).contains_key(&key);
+
     // Get latest sequence_number
     let (latest_ms_time, latest_seq_num) = {
-        let value = store.db.get(&key);
+        let value = store.db(session.db).get(&key);
 
         match value {
             Some(RedisValue::Stream(values)) => {
@@ -46,9 +80,7 @@ pub(crate) fn invoke(
                     None => (0, 0),
                 }
             }
-            Some(RedisValue::String(..)) => {
-                anyhow::bail!("Invalid Operation: Appending stream data to string type")
-            }
+            Some(RedisValue::String(..)) => return Err(RedisError::WrongType.into()),
             None => (0, 0),
         }
     };
@@ -69,8 +101,19 @@ pub(crate) fn invoke(
 
         format!("{}-{}", ms_time, seq_num)
     };
+    // Replicas get the generated ID rather than generating their own
+    args[2] = id.clone();
+
+    store
+        .db_mut(session.db)
+        .append_stream(key.clone(), id.clone(), fields)?;
+    store.dirty += 1;
+
+    if is_new {
+        store.notify_keyspace_event(notify::NEW, "new", &key, session.db);
+    }
+    store.notify_keyspace_event(notify::STREAM, "xadd", &key, session.db);
 
-    store.db.append_stream(key, id.clone(), fields)?;
     Ok(Resp::BulkString(Some(id)))
 }
 
@@ -106,7 +149,7 @@ fn get_stream_id(
 
     let ms_time: u128 = ms_time.parse()?;
     if seq_num == "*" {
-        if ms_time == latest_ms_time as u128 {
+        if ms_time == latest_ms_time {
             return Ok((ms_time, latest_seq_num + 1));
         } else {
             return Ok((ms_time, 0));
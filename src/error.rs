@@ -0,0 +1,96 @@
+use crate::Resp;
+
+/// Longest argument echoed back in an unknown command error, as in redis-server.
+const MAX_ECHOED_ARG_LEN: usize = 128;
+
+/// Errors with a well-known Redis error code. Each one is replied with the
+/// same code and message as redis-server, so clients can tell them apart;
+/// any other error is replied as a generic `ERR`.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RedisError {
+    #[error("ERR unknown command '{name}', with args beginning with: {args}")]
+    UnknownCommand { name: String, args: String },
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpire(String),
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("EXECABORT Transaction discarded because of previous errors.")]
+    ExecAbort,
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+    #[error("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time")]
+    HelloNoAuth,
+    #[error("READONLY You can't write against a read only replica.")]
+    ReadOnly,
+    #[error("NOPROTO sorry, this protocol version is not supported")]
+    NoProto,
+    #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+    WrongPass,
+    #[error("DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. Use CONFIG REWRITE to make this change permanent. 2) Alternatively you can just disable the protected mode by editing the Redis configuration file, and setting the protected mode option to 'no', and then restarting the server. 3) If you started the server manually just for testing, restart it with the '--protected-mode no' option. 4) Set up an authentication password for the default user. NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.")]
+    ProtectedMode,
+    #[error("NOPERM {0}")]
+    NoPerm(String),
+}
+
+impl RedisError {
+    pub(crate) fn unknown_command(name: &str, args: impl Iterator<Item = String>) -> Self {
+        let args = args
+            .map(|arg| {
+                let arg: String = arg.chars().take(MAX_ECHOED_ARG_LEN).collect();
+                format!("'{}' ", arg)
+            })
+            .collect();
+
+        RedisError::UnknownCommand {
+            name: name.chars().take(MAX_ECHOED_ARG_LEN).collect(),
+            args,
+        }
+    }
+}
+
+/// Error reply for `err`. Only the outermost message is sent, since the
+/// rest of an anyhow chain is internal detail.
+pub(crate) fn reply(err: &anyhow::Error) -> Resp {
+    match err.downcast_ref::<RedisError>() {
+        Some(err) => Resp::SimpleError(err.to_string()),
+        None => Resp::error(&err.to_string()),
+    }
+}
+
+/// Code `err` is replied with, such as `ERR` or `WRONGTYPE`.
+pub(crate) fn code(err: &anyhow::Error) -> String {
+    match reply(err) {
+        Resp::SimpleError(msg) => msg.split(' ').next().unwrap_or_default().to_string(),
+        _ => "ERR".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_keeps_error_codes() {
+        let err = RedisError::WrongType.into();
+        assert_eq!(
+            reply(&err).encode(),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+
+        let err = anyhow::anyhow!("DB index is out of range");
+        assert_eq!(reply(&err).encode(), "-ERR DB index is out of range\r\n");
+
+        let err =
+            RedisError::unknown_command("foo", ["a".to_string(), "b".to_string()].into_iter());
+        assert_eq!(
+            reply(&err.into()).encode(),
+            "-ERR unknown command 'foo', with args beginning with: 'a' 'b' \r\n"
+        );
+    }
+}
@@ -1,93 +1,1214 @@
-use std::sync::Arc;
+mod replica;
+mod session;
+
+use std::{collections::VecDeque, sync::Arc, time::Instant};
 
 use anyhow::Context;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, watch, Notify};
+
+use crate::{
+    command::{self, Behavior, Call, DEFAULT_USER},
+    error,
+    resp::Protocol,
+    server::Request,
+    store::{ClientClass, Executor, OutputBufferLimit, ReplicaLink, User},
+    Conn, RedisError, Resp, Store,
+};
+
+pub(crate) use session::{ClientType, Session, Transaction};
+
+/// A request that can't complete on the executor. The rest of its batch
+/// runs once it is dealt with.
+enum Blocked {
+    Wait {
+        numreplicas: u8,
+        timeout: u16,
+    },
+    /// PSYNC succeeded, so the connection now carries the replication stream.
+    Replica(ReplicaLink),
+    /// Clients are paused through CLIENT PAUSE; the request is retried once
+    /// the deadline passes or they are unpaused.
+    Paused {
+        until: tokio::time::Instant,
+        resumed: watch::Receiver<()>,
+    },
+}
+
+/// What a batch run on the executor hands back to the connection task.
+struct Batch {
+    replies: Vec<Vec<u8>>,
+    blocked: Option<Blocked>,
+    protocol: Protocol,
+    limit: OutputBufferLimit,
+    /// proto-max-bulk-len as of the batch, for parsing the next requests.
+    max_bulk_len: usize,
+    /// Whether to close the connection once the replies are sent.
+    close: bool,
+}
 
-use crate::{Command, Conn, Resp, Store};
+pub async fn handle_client(mut conn: Conn, executor: &Executor) -> anyhow::Result<()> {
+    let (sender, messages) = mpsc::unbounded_channel();
+    let mut session = Session::new(sender, conn.peer_addr(), conn.local_addr());
+    let id = session.id;
+    let killed = session.killed.clone();
+    let common_name = conn.peer_common_name();
+    let is_local = conn.is_local();
+
+    let registered = executor
+        .run(move |store| {
+            store.stats.connections_received += 1;
+            if is_protected(store, is_local) {
+                store.stats.rejected_connections += 1;
+                return None;
+            }
+
+            // Connections start out logged in as the default user, unless
+            // it has a password
+            session.authenticated = store.acl.auto_login();
+            // With tls-auth-clients-user, a TLS client certificate logs in
+            // as the user its common name matches
+            if let Some(name) = common_name.filter(|_| store.config.tls().auth_clients_user) {
+                if store.acl.user(&name).is_some_and(User::is_enabled) {
+                    session.user = name;
+                    session.authenticated = true;
+                }
+            }
+            store.clients.insert(id, session);
+            Some(store.config.proto_max_bulk_len())
+        })
+        .await?;
+    let max_bulk_len = match registered {
+        Some(max_bulk_len) => max_bulk_len,
+        None => {
+            let reply = error::reply(&RedisError::ProtectedMode.into());
+            return conn.write_raw(reply.encode().as_bytes()).await;
+        }
+    };
+    conn.set_max_bulk_len(max_bulk_len);
+
+    let result = serve(conn, id, messages, killed, executor).await;
+
+    // Drop WATCH and pub/sub registrations so the store doesn't keep
+    // references to a dead session
+    executor
+        .run(move |store| {
+            if let Some(mut session) = store.clients.remove(&id) {
+                command::unwatch_all(store, &mut session);
+                command::unsubscribe_all(store, &mut session);
+            }
+        })
+        .await?;
+
+    result
+}
+
+/// Whether protected mode turns the client away. Without a password anyone
+/// who can reach the port could run anything, so only local clients are
+/// let in.
+fn is_protected(store: &Store, is_local: bool) -> bool {
+    let nopass = store.acl.user(DEFAULT_USER).is_some_and(User::is_nopass);
+    store.config.protected_mode() && nopass && !is_local
+}
+
+async fn serve(
+    mut conn: Conn,
+    id: u64,
+    mut messages: mpsc::UnboundedReceiver<Resp>,
+    killed: Arc<Notify>,
+    executor: &Executor,
+) -> anyhow::Result<()> {
+    // Copy of the session's protocol, which lives on the executor
+    let mut protocol = Protocol::default();
+    // Limit for the client's class as of its last batch, which is the pubsub
+    // one whenever messages can reach it
+    let mut limit = OutputBufferLimit::default();
 
-pub async fn handle_client(mut conn: Conn, store: &Arc<Mutex<Store>>) -> anyhow::Result<()> {
     loop {
-        let (_, args) = match conn.read_frame().await {
-            Ok((frame_len, args)) => (frame_len, args),
-            Err(e) => {
-                eprintln!("Faile to read frame; Err: {e}");
-                break;
+        // Execute every complete request that has arrived, replying to the
+        // whole batch with a single flush
+        let mut requests = VecDeque::new();
+        let mut protocol_error = None;
+        loop {
+            match conn.next_request() {
+                Ok(Some(request)) => requests.push_back(request),
+                Ok(None) => break,
+                Err(err) => {
+                    protocol_error = Some(err);
+                    break;
+                }
             }
-        };
+        }
 
-        let cmd = Command::parse(args).context("Failed to parse command")?;
-        let is_psync = matches!(&cmd, Command::Psync { .. });
+        if requests.is_empty() && protocol_error.is_none() {
+            // Messages keep being published while a slow client is flushed,
+            // so they are queued meanwhile to hold them to its limit
+            loop {
+                tokio::select! {
+                    flushed = conn.flush_output() => {
+                        flushed?;
+                        break;
+                    }
+                    Some(message) = messages.recv() => {
+                        if !deliver(&mut conn, message, protocol, limit) {
+                            eprintln!("Client {} closed for overcoming output buffer limits", id);
+                            return Ok(());
+                        }
+                    }
+                    _ = killed.notified() => return Ok(()),
+                }
+            }
 
-        sync(&cmd, store).await?;
+            tokio::select! {
+                read = conn.fill_buffer() => {
+                    if let Err(e) = read {
+                        eprintln!("Faile to read frame; Err: {e}");
+                        break;
+                    }
+                },
+                // Messages published to channels this connection subscribed to
+                Some(message) = messages.recv() => {
+                    let mut within_limit = deliver(&mut conn, message, protocol, limit);
+                    while within_limit {
+                        let Ok(message) = messages.try_recv() else { break };
+                        within_limit = deliver(&mut conn, message, protocol, limit);
+                    }
+                    if !within_limit {
+                        eprintln!("Client {} closed for overcoming output buffer limits", id);
+                        return Ok(());
+                    }
+                }
+                // CLIENT KILL, possibly from this connection itself
+                _ = killed.notified() => break,
+            };
+            continue;
+        }
 
-        let result = match cmd.execute(Arc::clone(store)).await {
-            Ok(result) => result,
-            Err(err) => {
-                let error_msg = Resp::error(&err.to_string()).encode().into_bytes();
-                conn.write_raw(&error_msg).await?;
-                continue;
+        while !requests.is_empty() {
+            let (qbuf, omem) = (conn.input_len(), conn.output_len());
+            let result = executor
+                .run(move |store| {
+                    let batch = store.with_client(id, |store, session| {
+                        session.qbuf = qbuf;
+                        session.omem = omem;
+                        run_batch(&mut requests, store, session)
+                    });
+                    (batch, requests)
+                })
+                .await;
+            let (batch, rest) = match result {
+                Ok((Some(batch), rest)) => (batch, rest),
+                // A panicking job takes the session down with it
+                other => {
+                    conn.flush_output().await?;
+                    return Err(other
+                        .err()
+                        .unwrap_or_else(|| anyhow::anyhow!("Client session no longer exists")));
+                }
+            };
+            requests = rest;
+
+            protocol = batch.protocol;
+            limit = batch.limit;
+            conn.set_max_bulk_len(batch.max_bulk_len);
+            for reply in batch.replies {
+                conn.queue(reply);
+            }
+            if conn.output_limit_reached(limit) {
+                eprintln!("Client {} closed for overcoming output buffer limits", id);
+                return Ok(());
+            }
+            if batch.close {
+                conn.flush_output().await?;
+                return Ok(());
             }
-        };
 
-        conn.write_raw(&result).await?;
+            match batch.blocked {
+                None => {}
+                Some(Blocked::Wait {
+                    numreplicas,
+                    timeout,
+                }) => {
+                    // Send what's ready rather than hold it while blocked
+                    conn.flush_output().await?;
+                    let reply = command::wait(executor, numreplicas, timeout).await?;
+                    conn.queue(reply.encode_with(protocol));
+                }
+                Some(Blocked::Replica(link)) => {
+                    conn.flush_output().await?;
+                    return replica::serve_replica(conn, link, killed).await;
+                }
+                Some(Blocked::Paused { until, mut resumed }) => {
+                    conn.flush_output().await?;
+                    tokio::select! {
+                        _ = tokio::time::timeout_at(until, resumed.changed()) => {}
+                        _ = killed.notified() => return Ok(()),
+                    }
+                }
+            }
+        }
 
-        if is_psync {
-            store.lock().await.add_replica(conn);
+        if let Some(err) = protocol_error {
+            // Reply before closing so the client can tell why
+            conn.queue(Resp::error(&err.to_string()).encode());
+            conn.flush_output().await?;
             return Ok(());
         }
     }
     Ok(())
 }
 
-pub async fn handle_replication(mut conn: Conn, store: Arc<Mutex<Store>>) -> anyhow::Result<()> {
-    loop {
-        let (frame_len, args) = match conn.read_frame().await {
-            Ok((frame_len, args)) => (frame_len, args),
-            Err(e) => {
-                eprintln!("Faile to read frame; Err: {e}");
-                break;
+/// Queues a published message, returning whether the client's output is
+/// still within `limit`.
+fn deliver(conn: &mut Conn, message: Resp, protocol: Protocol, limit: OutputBufferLimit) -> bool {
+    conn.queue(message.encode_with(protocol));
+    !conn.output_limit_reached(limit)
+}
+
+/// Runs requests in order until one has to block, leaving the remainder in
+/// `requests`.
+fn run_batch(requests: &mut VecDeque<Request>, store: &mut Store, session: &mut Session) -> Batch {
+    let mut replies = Vec::with_capacity(requests.len());
+    let mut blocked = None;
+    session.last_interaction = Instant::now();
+
+    while let Some(request) = requests.front() {
+        blocked = paused(request, store, session);
+        if blocked.is_some() {
+            break;
+        }
+        let request = requests.pop_front().unwrap();
+
+        // A skip set by this request applies to the next one instead
+        let skip = std::mem::take(&mut session.skip_reply);
+        let count = replies.len();
+        blocked = process(request, store, session, &mut replies);
+        if skip || session.reply_off {
+            replies.truncate(count);
+        }
+        // Requests after QUIT are left unanswered
+        if blocked.is_some() || session.close_after_reply {
+            break;
+        }
+    }
+
+    let class = if session.is_subscribed() {
+        ClientClass::Pubsub
+    } else {
+        ClientClass::Normal
+    };
+
+    Batch {
+        replies,
+        blocked,
+        protocol: session.protocol,
+        limit: store.config.output_buffer_limits().get(class),
+        max_bulk_len: store.config.proto_max_bulk_len(),
+        close: session.close_after_reply,
+    }
+}
+
+/// Holds `request` back while CLIENT PAUSE applies to it. EXEC counts as
+/// a write when the transaction has queued any.
+fn paused(request: &Request, store: &mut Store, session: &Session) -> Option<Blocked> {
+    let spec = request.name().and_then(command::lookup)?;
+    let replicates = spec.replicates()
+        || (spec.name == "exec"
+            && session.transaction.as_ref().is_some_and(|transaction| {
+                transaction
+                    .commands
+                    .iter()
+                    .any(|call| call.spec.replicates())
+            }));
+
+    let until = store.pause.blocks(replicates)?;
+    Some(Blocked::Paused {
+        until,
+        resumed: store.pause.resumed(),
+    })
+}
+
+fn process(
+    request: Request,
+    store: &mut Store,
+    session: &mut Session,
+    replies: &mut Vec<Vec<u8>>,
+) -> Option<Blocked> {
+    let args = match request.into_args() {
+        Ok(args) => args,
+        Err(err) => {
+            store.stats.record_error(&error::code(&err));
+            replies.push(error::reply(&err).encode().into_bytes());
+            return None;
+        }
+    };
+
+    let call = Call::parse(args).and_then(|call| match check(&call, store, session) {
+        Ok(()) => Ok(call),
+        Err(err) => {
+            store.stats.record_rejected(call.name);
+            Err(err)
+        }
+    });
+    let call = match call {
+        Ok(call) => {
+            session.last_command = call.name;
+            call
+        }
+        Err(err) => {
+            // Rejected commands abort the transaction at EXEC
+            if let Some(transaction) = session.transaction.as_mut() {
+                transaction.dirty = true;
             }
-        };
+            store.stats.record_error(&error::code(&err));
+            replies.push(error::reply(&err).encode().into_bytes());
+            return None;
+        }
+    };
+
+    // Blocking commands wait off the executor; inside EXEC they reply
+    // with what is known right away
+    if call.spec.is(Behavior::Blocking) && session.transaction.is_none() {
+        if let Some(wait) = call.downcast_ref::<command::Wait>() {
+            return Some(Blocked::Wait {
+                numreplicas: wait.numreplicas,
+                timeout: wait.timeout,
+            });
+        }
+    }
+    let is_psync = call.is::<command::Psync>();
 
-        let cmd = Command::parse(args).context("Failed to parse command")?;
-        let is_replconf_cmd = matches!(&cmd, Command::ReplConf { .. });
-        let result = cmd.execute(Arc::clone(&store)).await?;
+    let result = dispatch(call, session, store);
+    store.propagate(std::mem::take(&mut session.propagate));
 
-        if is_replconf_cmd {
-            conn.write_raw(&result).await?;
+    match result {
+        Ok(reply) => replies.push(reply),
+        Err(err) => {
+            replies.push(error::reply(&err).encode().into_bytes());
+            return None;
         }
+    }
 
-        store.lock().await.increment_offset(frame_len);
+    if !is_psync {
+        return None;
+    }
+    // Registered in the same job as the snapshot, so no write is missed
+    session.role = ClientType::Replica;
+    let ip = session
+        .addr
+        .rsplit_once(':')
+        .map_or(session.addr.as_str(), |(ip, _)| ip);
+    Some(Blocked::Replica(
+        store.add_replica(ip, session.listening_port),
+    ))
+}
+
+/// Refuses `call` if the session may not run it right now.
+fn check(call: &Call, store: &mut Store, session: &Session) -> anyhow::Result<()> {
+    if !session.authenticated && !call.spec.has_flag("no_auth") {
+        return Err(RedisError::NoAuth.into());
+    }
+    command::check_acl(store, session, call)?;
+    if call.spec.has_flag("no_multi") && session.transaction.is_some() {
+        anyhow::bail!("Command not allowed inside a transaction");
+    }
+    // Replicas only take writes from their master
+    if call.spec.has_flag("write") && store.config.is_replica() {
+        return Err(RedisError::ReadOnly.into());
+    }
+    // RESP3 can interleave pushes with replies, so only RESP2 clients
+    // are restricted while subscribed
+    if session.is_subscribed()
+        && session.protocol == Protocol::Resp2
+        && !call.spec.is(Behavior::Subscribed)
+    {
+        anyhow::bail!(
+            "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            call.spec.name
+        );
     }
 
     Ok(())
 }
 
-async fn sync(cmd: &Command, store: &Arc<Mutex<Store>>) -> anyhow::Result<()> {
-    let resp_cmd = match cmd {
-        Command::Set {
-            key,
-            value,
-            expiry: _,
-        } => Some(Resp::array(vec![
-            "SET".to_string(),
-            key.to_string(),
-            value.to_string(),
-        ])),
-        _ => None,
-    };
+pub async fn handle_replication(mut conn: Conn, executor: Executor) -> anyhow::Result<()> {
+    // The master link never subscribes, so published messages are discarded
+    let (sender, _) = mpsc::unbounded_channel();
+    let mut session = Session::new(sender, conn.peer_addr(), conn.local_addr());
+    session.role = ClientType::Master;
+    session.authenticated = true;
+    let id = session.id;
+    let killed = session.killed.clone();
+    executor
+        .run(move |store| {
+            store.clients.insert(id, session);
+        })
+        .await?;
+
+    loop {
+        let mut requests = vec![];
+        while let Some(request) = conn.next_request()? {
+            requests.push(request);
+        }
 
-    if let Some(resp_cmd) = resp_cmd {
-        let encoded = resp_cmd.encode();
-        let byte_count = encoded.len();
-        let mut s = store.lock().await;
-        for replica in s.replicas.iter_mut() {
-            if let Err(e) = replica.conn.write_raw(encoded.as_bytes()).await {
-                eprintln!("Failed to write to replica; Err = {:?}", e);
+        if requests.is_empty() {
+            tokio::select! {
+                read = conn.fill_buffer() => {
+                    if let Err(e) = read {
+                        eprintln!("Faile to read frame; Err: {e}");
+                        break;
+                    }
+                }
+                _ = killed.notified() => break,
             }
+            continue;
+        }
+
+        let replies = executor
+            .run(move |store| {
+                store.with_client(id, |store, session| {
+                    apply_replicated(requests, store, session)
+                })
+            })
+            .await?
+            .context("Master session no longer exists")?;
+
+        for reply in replies {
+            conn.queue(reply);
         }
-        s.master_repl_offset += byte_count;
+        conn.flush_output().await?;
     }
 
     Ok(())
 }
+
+/// Applies commands streamed from the master, returning replies for the
+/// few the master expects an answer to. A command that fails is logged and
+/// skipped, as the rest of the stream still has to be applied.
+fn apply_replicated(
+    requests: Vec<Request>,
+    store: &mut Store,
+    session: &mut Session,
+) -> Vec<Vec<u8>> {
+    let mut replies = vec![];
+
+    for request in requests {
+        let frame_len = request.len;
+        let applied = request.into_args().and_then(Call::parse).and_then(|call| {
+            let is_replconf_cmd = call.is::<command::ReplConf>();
+            Ok((is_replconf_cmd, dispatch(call, session, store)?))
+        });
+
+        match applied {
+            Ok((true, reply)) => replies.push(reply),
+            Ok((false, _)) => {}
+            Err(err) => eprintln!("Failed to apply command from master; Err: {err}"),
+        }
+
+        // Writes received from the master are not chained to other replicas
+        session.propagate.clear();
+
+        session.last_interaction = Instant::now();
+        store.replication.offset += frame_len;
+    }
+
+    replies
+}
+
+/// Executes `call`, or queues it while the session has a transaction open.
+fn dispatch(call: Call, session: &mut Session, store: &mut Store) -> anyhow::Result<Vec<u8>> {
+    if let Some(transaction) = session.transaction.as_mut() {
+        if !call.spec.is(Behavior::NotQueued) {
+            transaction.commands.push(call);
+            return Ok(Resp::SimpleString("QUEUED".to_string())
+                .encode()
+                .into_bytes());
+        }
+    }
+
+    call.apply(store, session)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use bytes::BytesMut;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        server::{parser::RequestParser, DEFAULT_MAX_BULK_LEN},
+        store::{load_config, RedisValue},
+    };
+
+    async fn store(args: &[&str]) -> Store {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Store::init(load_config(&args).unwrap()).await.unwrap()
+    }
+
+    fn session() -> Session {
+        let (sender, _) = mpsc::unbounded_channel();
+        Session::new(
+            sender,
+            "127.0.0.1:50000".to_string(),
+            "127.0.0.1:6379".to_string(),
+        )
+    }
+
+    fn requests(bytes: &[u8]) -> Vec<Request> {
+        let mut buf = BytesMut::from(bytes);
+        let mut parser = RequestParser::new(DEFAULT_MAX_BULK_LEN);
+        std::iter::from_fn(|| parser.parse(&mut buf).unwrap()).collect()
+    }
+
+    /// Runs `commands` one at a time, returning the replies as text.
+    fn run(store: &mut Store, session: &mut Session, commands: &[&[&str]]) -> Vec<String> {
+        let mut replies = vec![];
+        for command in commands {
+            let args = command.iter().map(|arg| arg.to_string()).collect();
+            for request in requests(Resp::array(args).encode().as_bytes()) {
+                process(request, store, session, &mut replies);
+            }
+        }
+        replies
+            .into_iter()
+            .map(|reply| String::from_utf8(reply).unwrap())
+            .collect()
+    }
+
+    /// Splits everything queued for a replica into requests.
+    fn received(link: &mut ReplicaLink) -> Vec<Request> {
+        let mut buf = vec![];
+        while let Ok(chunk) = link.stream.try_recv() {
+            buf.extend_from_slice(&chunk);
+        }
+        requests(&buf)
+    }
+
+    #[tokio::test]
+    async fn test_propagated_writes() {
+        let mut master = store(&[]).await;
+        let mut link = master.add_replica("127.0.0.1", 6380);
+        let mut session = session();
+        session.authenticated = true;
+
+        let mut replies = vec![];
+        let batch = b"*5\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n$2\r\nEX\r\n$3\r\n100\r\n\
+            *5\r\n$4\r\nXADD\r\n$1\r\ns\r\n$1\r\n*\r\n$1\r\nf\r\n$1\r\nv\r\n\
+            *2\r\n$3\r\nDEL\r\n$7\r\nmissing\r\n\
+            *3\r\n$4\r\nMOVE\r\n$7\r\nmissing\r\n$1\r\n1\r\n";
+        for request in requests(batch) {
+            process(request, &mut master, &mut session, &mut replies);
+        }
+        assert_eq!(replies[2..], [b":0\r\n".to_vec(), b":0\r\n".to_vec()]);
+
+        let sent: Vec<Vec<String>> = received(&mut link)
+            .into_iter()
+            .map(|request| request.into_args().unwrap())
+            .collect();
+        // Only SELECT, SET and XADD; the DEL and MOVE changed nothing
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], ["SELECT", "0"]);
+
+        // The relative expiry goes out as an absolute one
+        assert_eq!(sent[1][..4], ["SET", "a", "1", "PXAT"]);
+        let at = Duration::from_millis(sent[1][4].parse().unwrap());
+        let ttl = at.saturating_sub(SystemTime::now().duration_since(UNIX_EPOCH).unwrap());
+        assert!(ttl > Duration::from_secs(99) && ttl <= Duration::from_secs(100));
+
+        // So does the generated stream ID
+        let id = String::from_utf8(replies[1].clone()).unwrap();
+        assert_eq!(id, format!("${}\r\n{}\r\n", sent[2][2].len(), sent[2][2]));
+        assert_eq!(sent[2], ["XADD", "s", &sent[2][2], "f", "v"]);
+    }
+
+    #[tokio::test]
+    async fn test_replica_applies_expiry_del() {
+        let mut master = store(&[]).await;
+        let mut link = master.add_replica("127.0.0.1", 6380);
+        let expired = Some(SystemTime::now() - Duration::from_secs(1));
+        master
+            .db_mut(0)
+            .set("k".to_string(), "v".to_string(), expired)
+            .unwrap();
+        assert!(master.expire_if_needed(0, "k"));
+
+        let mut replica = store(&["--replicaof", "127.0.0.1 6379"]).await;
+        replica
+            .db_mut(0)
+            .set("k".to_string(), "v".to_string(), expired)
+            .unwrap();
+        let mut link_session = session();
+        link_session.role = ClientType::Master;
+        apply_replicated(received(&mut link), &mut replica, &mut link_session);
+
+        // Replicas keep expired keys until the master deletes them
+        assert_eq!(replica.db(0).keyspace_info().0, 0);
+        assert_eq!(replica.replication.offset, master.replication.offset);
+    }
+
+    #[tokio::test]
+    async fn test_replica_skips_failing_commands() {
+        let mut replica = store(&["--replicaof", "127.0.0.1 6379"]).await;
+        let mut link_session = session();
+        link_session.role = ClientType::Master;
+        // A WRONGTYPE and an unknown command, each followed by a valid write
+        let stream: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n\
+            *5\r\n$4\r\nXADD\r\n$1\r\nk\r\n$3\r\n1-1\r\n$1\r\nf\r\n$1\r\nv\r\n\
+            *3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n\
+            *1\r\n$4\r\nNOPE\r\n\
+            *3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n";
+
+        let replies = apply_replicated(requests(stream), &mut replica, &mut link_session);
+
+        assert!(replies.is_empty());
+        assert!(replica.db(0).contains_key("a"));
+        assert!(replica.db(0).contains_key("b"));
+        assert_eq!(replica.replication.offset, stream.len());
+    }
+
+    #[tokio::test]
+    async fn test_replica_offset_ignores_own_clients() {
+        let mut replica = store(&["--replicaof", "127.0.0.1 6379"]).await;
+        let mut session = session();
+        session.authenticated = true;
+
+        let mut replies = vec![];
+        for request in requests(b"*3\r\n$7\r\nPUBLISH\r\n$2\r\nch\r\n$1\r\nm\r\n") {
+            process(request, &mut replica, &mut session, &mut replies);
+        }
+
+        assert_eq!(replies, vec![b":0\r\n".to_vec()]);
+        assert_eq!(replica.replication.offset, 0);
+    }
+
+    #[tokio::test]
+    async fn test_reset_and_quit_while_subscribed() {
+        let mut store = store(&[]).await;
+        let mut session = session();
+        session.authenticated = true;
+        let mut batch = requests(
+            b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n*2\r\n$6\r\nSELECT\r\n$1\r\n1\r\n\
+              *2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nnews\r\n*1\r\n$5\r\nRESET\r\n",
+        )
+        .into();
+        let replies = run_batch(&mut batch, &mut store, &mut session).replies;
+        assert_eq!(replies.last().unwrap(), b"+RESET\r\n");
+        assert!(!session.is_subscribed());
+        assert_eq!(store.pubsub.publish("news", "hi"), 0);
+        assert_eq!((session.protocol, session.db), (Protocol::Resp2, 0));
+
+        let mut batch = requests(
+            b"*2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nnews\r\n*1\r\n$4\r\nQUIT\r\n\
+              *1\r\n$4\r\nPING\r\n",
+        )
+        .into();
+        let done = run_batch(&mut batch, &mut store, &mut session);
+        assert_eq!(done.replies.last().unwrap(), b"+OK\r\n");
+        assert!(done.close);
+        assert_eq!(batch.len(), 1, "requests after QUIT are not run");
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_rejected_in_multi() {
+        let mut store = store(&[]).await;
+        let mut session = session();
+        session.authenticated = true;
+        let mut batch = requests(
+            b"*1\r\n$5\r\nMULTI\r\n*3\r\n$9\r\nSUBSCRIBE\r\n$1\r\na\r\n$1\r\nb\r\n\
+              *1\r\n$4\r\nEXEC\r\n",
+        )
+        .into();
+        let replies = run_batch(&mut batch, &mut store, &mut session).replies;
+        assert_eq!(
+            replies,
+            [
+                b"+OK\r\n".to_vec(),
+                b"-ERR Command not allowed inside a transaction\r\n".to_vec(),
+                b"-EXECABORT Transaction discarded because of previous errors.\r\n".to_vec(),
+            ]
+        );
+        assert!(!session.is_subscribed());
+    }
+
+    #[tokio::test]
+    async fn test_session_kept_after_panic() {
+        let mut store = store(&[]).await;
+        let session = session();
+        let id = session.id;
+        store.clients.insert(id, session);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            store.with_client(id, |_, _| panic!("command failed"))
+        }));
+        assert!(panicked.is_err());
+        assert!(store.clients.contains_key(&id));
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_closed_over_pubsub_limit() {
+        let store = store(&["--client-output-buffer-limit", "pubsub 1024 0 0"]).await;
+        let executor = Executor::spawn(store);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let subscriber = async {
+            client
+                .write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nnews\r\n")
+                .await
+                .unwrap();
+            let confirmation = b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n";
+            let mut buf = vec![0; confirmation.len()];
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, confirmation);
+
+            // A single message over the hard limit closes the connection
+            // before any of it is written
+            let message = "x".repeat(2048);
+            let receivers = executor
+                .run(move |store| store.pubsub.publish("news", &message))
+                .await
+                .unwrap();
+            assert_eq!(receivers, 1);
+
+            let mut rest = vec![];
+            let closed = client.read_to_end(&mut rest);
+            tokio::time::timeout(Duration::from_secs(5), closed)
+                .await
+                .expect("connection was left open")
+                .unwrap();
+            assert!(rest.is_empty());
+        };
+
+        let (served, ()) = tokio::join!(handle_client(Conn::new(stream), &executor), subscriber);
+        served.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_exec_rechecks_acl() {
+        let mut store = store(&[]).await;
+        let mut admin = session();
+        admin.authenticated = true;
+        let mut alice = session();
+        let setup = run(
+            &mut store,
+            &mut admin,
+            &[&["ACL", "SETUSER", "alice", "on", ">pw", "~*", "+@all"]],
+        );
+        assert_eq!(setup, ["+OK\r\n"]);
+        run(&mut store, &mut alice, &[&["AUTH", "alice", "pw"]]);
+
+        let queued = run(&mut store, &mut alice, &[&["MULTI"], &["SET", "k", "v"]]);
+        assert_eq!(queued, ["+OK\r\n", "+QUEUED\r\n"]);
+        run(
+            &mut store,
+            &mut admin,
+            &[&["ACL", "SETUSER", "alice", "-set"]],
+        );
+
+        let exec = run(&mut store, &mut alice, &[&["EXEC"]]);
+        assert!(exec[0].starts_with("*1\r\n-NOPERM"), "{}", exec[0]);
+        assert!(!store.db(0).contains_key("k"));
+        assert_eq!(store.acl.log_entries().next().unwrap().context, "multi");
+    }
+
+    #[tokio::test]
+    async fn test_batch_carries_max_bulk_len() {
+        let mut store = store(&[]).await;
+        let mut session = session();
+        session.authenticated = true;
+
+        let set = Resp::array(
+            ["CONFIG", "SET", "proto-max-bulk-len", "2mb"]
+                .map(str::to_string)
+                .to_vec(),
+        );
+        let mut requests = VecDeque::from(requests(set.encode().as_bytes()));
+        let batch = run_batch(&mut requests, &mut store, &mut session);
+        assert_eq!(batch.replies, [b"+OK\r\n".to_vec()]);
+        assert_eq!(batch.max_bulk_len, 2 * 1024 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_databases_are_isolated() {
+        let mut store = store(&[]).await;
+        let mut first = session();
+        let mut second = session();
+        first.authenticated = true;
+        second.authenticated = true;
+
+        run(&mut store, &mut first, &[&["SET", "k", "zero"]]);
+        let replies = run(
+            &mut store,
+            &mut second,
+            &[&["SELECT", "1"], &["GET", "k"], &["SET", "k", "one"]],
+        );
+        assert_eq!(replies[1], "$-1\r\n");
+        assert_eq!(
+            run(&mut store, &mut first, &[&["GET", "k"]]),
+            ["$4\r\nzero\r\n"]
+        );
+        assert_eq!(
+            run(&mut store, &mut second, &[&["GET", "k"]]),
+            ["$3\r\none\r\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_move_keeps_existing_target() {
+        let mut store = store(&[]).await;
+        let mut session = session();
+        session.authenticated = true;
+
+        let replies = run(
+            &mut store,
+            &mut session,
+            &[
+                &["SET", "k", "source"],
+                &["SELECT", "1"],
+                &["SET", "k", "target"],
+                &["SELECT", "0"],
+                &["MOVE", "k", "1"],
+                &["MOVE", "k", "0"],
+            ],
+        );
+        assert_eq!(
+            replies[4..],
+            [
+                ":0\r\n",
+                "-ERR source and destination objects are the same\r\n"
+            ]
+        );
+        assert!(store.db(0).contains_key("k"));
+        assert!(matches!(store.db(1).get("k"), Some(RedisValue::String(v)) if v == "target"));
+    }
+
+    #[tokio::test]
+    async fn test_swapdb_invalidates_watchers() {
+        let mut store = store(&[]).await;
+        let mut watcher = session();
+        let mut other = session();
+        watcher.authenticated = true;
+        other.authenticated = true;
+
+        // The watched key is missing in both databases, so only a swap of
+        // non-empty databases touches it
+        run(
+            &mut store,
+            &mut other,
+            &[&["SELECT", "1"], &["SET", "k", "v"]],
+        );
+        run(&mut store, &mut watcher, &[&["WATCH", "k"]]);
+        run(&mut store, &mut other, &[&["SWAPDB", "0", "1"]]);
+
+        let replies = run(
+            &mut store,
+            &mut watcher,
+            &[&["MULTI"], &["GET", "k"], &["EXEC"]],
+        );
+        assert_eq!(replies[2], "*-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_exec_aborts_after_queueing_error() {
+        let mut store = store(&[]).await;
+        let mut session = session();
+        session.authenticated = true;
+
+        let replies = run(
+            &mut store,
+            &mut session,
+            &[
+                &["MULTI"],
+                &["SET", "k", "v"],
+                &["NOSUCHCOMMAND"],
+                &["EXEC"],
+            ],
+        );
+        assert_eq!(replies[1], "+QUEUED\r\n");
+        assert!(
+            replies[2].starts_with("-ERR unknown command"),
+            "{}",
+            replies[2]
+        );
+        assert_eq!(
+            replies[3],
+            "-EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+        assert!(!store.db(0).contains_key("k"));
+        assert!(session.transaction.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_exec_propagates_as_transaction() {
+        let mut master = store(&[]).await;
+        let mut link = master.add_replica("127.0.0.1", 6380);
+        let mut session = session();
+        session.authenticated = true;
+
+        run(
+            &mut master,
+            &mut session,
+            &[
+                &["MULTI"],
+                &["SET", "a", "1"],
+                &["GET", "a"],
+                &["DEL", "a"],
+                &["EXEC"],
+            ],
+        );
+        let sent: Vec<Vec<String>> = received(&mut link)
+            .into_iter()
+            .map(|request| request.into_args().unwrap())
+            .collect();
+        assert_eq!(
+            sent,
+            [
+                vec!["SELECT", "0"],
+                vec!["MULTI"],
+                vec!["SET", "a", "1"],
+                vec!["DEL", "a"],
+                vec!["EXEC"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watched_key_changes_abort_exec() {
+        let changes: [&[&[&str]]; 5] = [
+            &[&["SET", "k", "new"]],
+            &[&["DEL", "k"]],
+            &[&["FLUSHDB"]],
+            &[&["FLUSHALL"]],
+            &[&["SWAPDB", "0", "1"]],
+        ];
+        for change in changes {
+            let mut store = store(&[]).await;
+            let mut watcher = session();
+            let mut other = session();
+            watcher.authenticated = true;
+            other.authenticated = true;
+
+            run(&mut store, &mut other, &[&["SET", "k", "old"]]);
+            run(&mut store, &mut watcher, &[&["WATCH", "k"], &["MULTI"]]);
+            run(&mut store, &mut other, change);
+            let replies = run(
+                &mut store,
+                &mut watcher,
+                &[&["SET", "k", "mine"], &["EXEC"]],
+            );
+            assert_eq!(replies[1], "*-1\r\n", "after {:?}", change);
+            assert!(
+                !matches!(store.db(0).get("k"), Some(RedisValue::String(v)) if v == "mine"),
+                "after {:?}",
+                change
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watched_key_expiry_aborts_exec() {
+        let mut store = store(&[]).await;
+        let mut session = session();
+        session.authenticated = true;
+
+        run(
+            &mut store,
+            &mut session,
+            &[&["SET", "k", "v", "PX", "10"], &["WATCH", "k"]],
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let replies = run(
+            &mut store,
+            &mut session,
+            &[&["MULTI"], &["GET", "k"], &["EXEC"]],
+        );
+        assert_eq!(replies[2], "*-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_watch_runs_exec() {
+        let mut store = store(&[]).await;
+        let mut session = session();
+        session.authenticated = true;
+
+        let replies = run(
+            &mut store,
+            &mut session,
+            &[
+                &["SET", "k", "v"],
+                &["WATCH", "k"],
+                &["GET", "k"],
+                &["MULTI"],
+                &["SET", "k", "w"],
+                &["EXEC"],
+                &["WATCH", "k"],
+                &["UNWATCH"],
+                &["SET", "k", "x"],
+                &["MULTI"],
+                &["EXEC"],
+            ],
+        );
+        assert_eq!(replies[5], "*1\r\n+OK\r\n");
+        assert_eq!(replies[10], "*0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_and_messages() {
+        let mut store = store(&[]).await;
+        let (sender, mut messages) = mpsc::unbounded_channel();
+        let mut subscriber =
+            Session::new(sender, "127.0.0.1:50001".into(), "127.0.0.1:6379".into());
+        let mut publisher = session();
+        subscriber.authenticated = true;
+        publisher.authenticated = true;
+
+        let replies = run(
+            &mut store,
+            &mut subscriber,
+            &[&["SUBSCRIBE", "news", "sport"], &["PSUBSCRIBE", "news.*"]],
+        );
+        assert_eq!(
+            replies.concat(),
+            "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n\
+             *3\r\n$9\r\nsubscribe\r\n$5\r\nsport\r\n:2\r\n\
+             *3\r\n$10\r\npsubscribe\r\n$6\r\nnews.*\r\n:3\r\n"
+        );
+
+        let published = run(
+            &mut store,
+            &mut publisher,
+            &[
+                &["PUBLISH", "news", "a"],
+                &["PUBLISH", "news.uk", "b"],
+                &["PUBLISH", "weather", "c"],
+            ],
+        );
+        assert_eq!(published, [":1\r\n", ":1\r\n", ":0\r\n"]);
+        let received: Vec<String> = std::iter::from_fn(|| messages.try_recv().ok())
+            .map(|message| message.encode())
+            .collect();
+        assert_eq!(
+            received,
+            [
+                "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$1\r\na\r\n",
+                "*4\r\n$8\r\npmessage\r\n$6\r\nnews.*\r\n$7\r\nnews.uk\r\n$1\r\nb\r\n",
+            ]
+        );
+
+        // Without arguments, every channel is dropped in turn
+        let replies = run(&mut store, &mut subscriber, &[&["UNSUBSCRIBE"]]);
+        assert_eq!(replies[0].matches("unsubscribe").count(), 2);
+        assert!(replies[0].ends_with(":1\r\n"), "{}", replies[0]);
+        let replies = run(&mut store, &mut subscriber, &[&["PUNSUBSCRIBE", "news.*"]]);
+        assert_eq!(
+            replies,
+            ["*3\r\n$12\r\npunsubscribe\r\n$6\r\nnews.*\r\n:0\r\n"]
+        );
+        assert!(!subscriber.is_subscribed());
+    }
+
+    #[tokio::test]
+    async fn test_resp2_subscribed_context() {
+        let mut store = store(&[]).await;
+        let mut resp3 = session();
+        let mut session = session();
+        session.authenticated = true;
+
+        let replies = run(
+            &mut store,
+            &mut session,
+            &[&["SUBSCRIBE", "news"], &["GET", "k"], &["PING"]],
+        );
+        assert!(
+            replies[1].starts_with("-ERR Can't execute 'get': only (P|S)SUBSCRIBE"),
+            "{}",
+            replies[1]
+        );
+        // PING answers in the pub/sub form while subscribed
+        assert_eq!(replies[2], "*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+
+        // RESP3 interleaves pushes with replies, so anything goes
+        resp3.authenticated = true;
+        let replies = run(
+            &mut store,
+            &mut resp3,
+            &[&["HELLO", "3"], &["SUBSCRIBE", "news"], &["GET", "k"]],
+        );
+        assert_eq!(replies[2], "_\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_auth_gates_commands() {
+        let mut store = store(&["--requirepass", "pw"]).await;
+        let mut session = session();
+
+        let replies = run(
+            &mut store,
+            &mut session,
+            &[
+                &["GET", "k"],
+                &["AUTH", "wrong"],
+                &["AUTH", "pw"],
+                &["GET", "k"],
+                &["AUTH", "default", "pw"],
+                &["AUTH", "nobody", "pw"],
+                &["AUTH", "a", "b", "c"],
+            ],
+        );
+        let wrongpass = "-WRONGPASS invalid username-password pair or user is disabled.\r\n";
+        assert_eq!(
+            replies,
+            [
+                "-NOAUTH Authentication required.\r\n",
+                wrongpass,
+                "+OK\r\n",
+                "$-1\r\n",
+                "+OK\r\n",
+                wrongpass,
+                "-ERR syntax error\r\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hello_authenticates() {
+        let mut store = store(&["--requirepass", "pw"]).await;
+        let mut session = session();
+
+        let replies = run(
+            &mut store,
+            &mut session,
+            &[
+                &["HELLO", "3"],
+                &["HELLO", "3", "AUTH", "default", "wrong"],
+                &["HELLO", "3", "AUTH", "default", "pw"],
+                &["GET", "k"],
+            ],
+        );
+        assert!(
+            replies[0].starts_with("-NOAUTH HELLO must be called"),
+            "{}",
+            replies[0]
+        );
+        assert!(replies[1].starts_with("-WRONGPASS"), "{}", replies[1]);
+        assert!(replies[2].starts_with("%7\r\n"), "{}", replies[2]);
+        assert_eq!(replies[3], "_\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_protected_mode_rejects_remote_clients_without_password() {
+        let open = store(&[]).await;
+        assert!(is_protected(&open, false));
+        assert!(!is_protected(&open, true));
+
+        let with_password = store(&["--requirepass", "pw"]).await;
+        assert!(!is_protected(&with_password, false));
+
+        let unprotected = store(&["--protected-mode", "no"]).await;
+        assert!(!is_protected(&unprotected, false));
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use tokio::sync::Notify;
+
+use crate::{
+    store::{unix_time, ReplicaLink},
+    Conn,
+};
+
+/// Streams propagated writes to a replica after PSYNC and records the
+/// offsets it acknowledges.
+pub(super) async fn serve_replica(
+    mut conn: Conn,
+    mut link: ReplicaLink,
+    killed: Arc<Notify>,
+) -> anyhow::Result<()> {
+    loop {
+        tokio::select! {
+            chunk = link.stream.recv() => {
+                // Closed once the store drops this replica
+                let chunk = match chunk {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+
+                let mut written = chunk.len();
+                conn.queue(chunk);
+                while let Ok(chunk) = link.stream.try_recv() {
+                    written += chunk.len();
+                    conn.queue(chunk);
+                }
+                conn.flush_output().await?;
+                link.pending.fetch_sub(written, Ordering::SeqCst);
+            }
+            request = conn.read_request() => {
+                let args = request?.into_args()?;
+
+                // Expect: REPLCONF ACK <offset>
+                if args.first().map(|s| s.to_uppercase()) == Some("REPLCONF".to_string())
+                    && args.get(1).map(|s| s.to_uppercase()) == Some("ACK".to_string())
+                {
+                    if let Some(offset) = args.get(2).and_then(|s| s.parse::<usize>().ok()) {
+                        link.ack_offset.store(offset, Ordering::SeqCst);
+                        link.ack_time.store(unix_time(), Ordering::SeqCst);
+                        link.acks.notify_waiters();
+                    }
+                }
+            }
+            _ = killed.notified() => break,
+        }
+    }
+
+    Ok(())
+}
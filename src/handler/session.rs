@@ -0,0 +1,150 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    command::{Call, DEFAULT_USER},
+    resp::Protocol,
+    store::Subscriber,
+};
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Per-connection state that outlives a single command.
+#[derive(Debug)]
+pub(crate) struct Session {
+    pub(crate) id: u64,
+    /// Peer and local addresses of the connection, as `ip:port`.
+    pub(crate) addr: String,
+    pub(crate) laddr: String,
+    /// Name set through HELLO SETNAME or CLIENT SETNAME.
+    pub(crate) name: Option<String>,
+    pub(crate) user: String,
+    /// Whether the connection may run commands other than AUTH and HELLO.
+    pub(crate) authenticated: bool,
+    pub(crate) role: ClientType,
+    /// Port a replica listens on, as told through REPLCONF listening-port.
+    pub(crate) listening_port: u16,
+    pub(crate) created: Instant,
+    pub(crate) last_interaction: Instant,
+    /// Full name of the last command run, such as `client|list`.
+    pub(crate) last_command: &'static str,
+    /// Unparsed input and unsent output, as of the last batch of requests.
+    pub(crate) qbuf: usize,
+    pub(crate) omem: usize,
+    /// Set through CLIENT NO-EVICT.
+    pub(crate) no_evict: bool,
+    /// Set through CLIENT REPLY OFF.
+    pub(crate) reply_off: bool,
+    /// Set through CLIENT REPLY SKIP, dropping the next command's reply.
+    pub(crate) skip_reply: bool,
+    /// Set by QUIT, closing the connection once its reply is sent.
+    pub(crate) close_after_reply: bool,
+    /// Signalled by CLIENT KILL to close the connection.
+    pub(crate) killed: Arc<Notify>,
+    pub(crate) protocol: Protocol,
+    /// Index of the currently selected database.
+    pub(crate) db: usize,
+    /// Commands queued since MULTI, if a transaction is open.
+    pub(crate) transaction: Option<Transaction>,
+    /// Keys WATCHed for the next EXEC, paired with their DB.
+    pub(crate) watched: Vec<(usize, String)>,
+    /// Raised by the store when any watched key is modified.
+    pub(crate) watch_dirty: Arc<AtomicBool>,
+    /// Writes to forward to replicas, paired with the DB they ran against.
+    pub(crate) propagate: Vec<(usize, Vec<String>)>,
+    /// Where published messages for this connection are delivered.
+    pub(crate) sender: Subscriber,
+    pub(crate) channels: HashSet<String>,
+    pub(crate) patterns: HashSet<String>,
+    pub(crate) shard_channels: HashSet<String>,
+}
+
+impl Session {
+    pub(crate) fn new(sender: Subscriber, addr: String, laddr: String) -> Self {
+        let now = Instant::now();
+
+        Self {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            addr,
+            laddr,
+            name: None,
+            user: DEFAULT_USER.to_string(),
+            authenticated: false,
+            role: ClientType::Normal,
+            listening_port: 0,
+            created: now,
+            last_interaction: now,
+            last_command: "NULL",
+            qbuf: 0,
+            omem: 0,
+            no_evict: false,
+            reply_off: false,
+            skip_reply: false,
+            close_after_reply: false,
+            killed: Arc::default(),
+            protocol: Protocol::default(),
+            db: 0,
+            transaction: None,
+            watched: vec![],
+            watch_dirty: Arc::default(),
+            propagate: vec![],
+            sender,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn subscription_count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// A subscribed connection only accepts pub/sub commands.
+    pub(crate) fn is_subscribed(&self) -> bool {
+        self.subscription_count() > 0 || !self.shard_channels.is_empty()
+    }
+
+    pub(crate) fn client_type(&self) -> ClientType {
+        match self.role {
+            ClientType::Normal if self.is_subscribed() => ClientType::Pubsub,
+            role => role,
+        }
+    }
+}
+
+/// Kind of connection, as reported by CLIENT LIST and matched by
+/// CLIENT KILL TYPE.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ClientType {
+    Normal,
+    Master,
+    Replica,
+    Pubsub,
+}
+
+impl ClientType {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "normal" => Some(ClientType::Normal),
+            "master" => Some(ClientType::Master),
+            "replica" | "slave" => Some(ClientType::Replica),
+            "pubsub" => Some(ClientType::Pubsub),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Transaction {
+    pub(crate) commands: Vec<Call>,
+    /// Set when a command fails to queue, making EXEC abort.
+    pub(crate) dirty: bool,
+}
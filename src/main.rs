@@ -1,35 +1,28 @@
-use std::sync::Arc;
-
 use clap::Parser;
 
 mod command;
+mod error;
 mod handler;
 mod rdb_parser;
+mod rdb_writer;
 mod resp;
 mod server;
 mod store;
 
 pub(crate) use command::Command;
+pub(crate) use error::RedisError;
 pub(crate) use resp::Resp;
 pub(crate) use server::{replica, Conn};
+use store::Executor;
 pub(crate) use store::Store;
-use tokio::sync::Mutex;
-
-const HOST_URL: &str = "127.0.0.1";
+use tokio::task::JoinSet;
 
 #[derive(Debug, Parser)]
 pub(crate) struct Args {
-    #[arg(long = "dir", default_value = "")]
-    dir: String,
-
-    #[arg(long = "dbfilename", default_value = "")]
-    dbfilename: String,
-
-    #[arg(long = "port", default_value = "6379")]
-    port: String,
-
-    #[arg(long = "replicaof", default_value = "")]
-    replica_of: String,
+    /// Path of a redis.conf file, then `--name value...` options that
+    /// override its settings
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    args: Vec<String>,
 }
 
 #[tokio::main]
@@ -38,16 +31,57 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::parse();
 
-    let store = Store::init(&args.dir, &args.dbfilename, &args.replica_of).await?;
+    let config = store::load_config(&args.args)?;
+    let store = Store::init(config).await?;
     let is_replica = store.config.is_replica();
-    let store = Arc::new(Mutex::new(store));
+    // Built before serving so bad certificates fail startup
+    let tls_acceptor = match store.config.tls().port {
+        0 => None,
+        port => Some((port, server::tls::acceptor(store.config.tls())?)),
+    };
+    // Bound up front, so an unusable address fails startup
+    let bind = store.config.bind().to_vec();
+    let plain_listeners = match store.config.port() {
+        0 => vec![],
+        port => server::bind(&bind, port)?,
+    };
+    let tls_listeners = match &tls_acceptor {
+        Some((port, _)) => server::bind(&bind, *port)?,
+        None => vec![],
+    };
+    let unixsocket = store
+        .config
+        .unixsocket()
+        .map(|path| (path.to_string(), store.config.unixsocketperm()));
+    let executor = Executor::spawn(store);
+
+    tokio::spawn(store::expire_cycle(executor.clone()));
 
     // Handshake with master server
     if is_replica {
-        replica::init(&store).await?;
+        replica::init(&executor).await?;
+    }
+
+    // Handle incoming requests; port 0 turns off the plain TCP listener
+    let mut listeners = JoinSet::new();
+    for listener in plain_listeners {
+        listeners.spawn(server::listen(listener, None, executor.clone()));
+    }
+    if let Some((path, perm)) = unixsocket {
+        listeners.spawn(server::listen_unix(path, perm, executor.clone()));
+    }
+    if let Some((_, acceptor)) = tls_acceptor {
+        for listener in tls_listeners {
+            listeners.spawn(server::listen(
+                listener,
+                Some(acceptor.clone()),
+                executor.clone(),
+            ));
+        }
+    }
+    while let Some(result) = listeners.join_next().await {
+        result??;
     }
 
-    // Handle incoming requests
-    let server_addr = format!("{}:{}", HOST_URL, args.port);
-    server::listen(&server_addr, store).await
+    Ok(())
 }
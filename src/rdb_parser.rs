@@ -7,7 +7,7 @@ use std::{
 };
 use tokio::{
     fs::File,
-    io::{AsyncReadExt, BufReader},
+    io::{AsyncRead, AsyncReadExt, BufReader},
 };
 
 #[derive(Debug)]
@@ -20,7 +20,7 @@ pub(crate) struct RedisValue {
 pub(crate) struct Rdb {
     header: String,
     metadata: HashMap<String, String>,
-    pub data: HashMap<String, RedisValue>,
+    pub data: HashMap<usize, HashMap<String, RedisValue>>,
 }
 
 impl Rdb {
@@ -40,8 +40,8 @@ impl Rdb {
         self.metadata.insert(key.to_string(), val.to_string());
     }
 
-    pub(crate) fn data(&mut self, key: &str, val: &str, expiry: Option<SystemTime>) {
-        self.data.insert(
+    pub(crate) fn data(&mut self, db: usize, key: &str, val: &str, expiry: Option<SystemTime>) {
+        self.data.entry(db).or_default().insert(
             key.to_string(),
             RedisValue {
                 value: val.to_string(),
@@ -56,17 +56,23 @@ enum LengthEncoding {
     Integer(i64),
 }
 
-pub(crate) struct RdbParser {
-    reader: BufReader<File>,
+pub(crate) struct RdbParser<R> {
+    reader: R,
 }
 
-impl RdbParser {
+impl RdbParser<BufReader<File>> {
     pub(crate) async fn new(path: PathBuf) -> anyhow::Result<Self> {
         let file = File::open(path).await?;
 
-        Ok(Self {
-            reader: BufReader::new(file),
-        })
+        Ok(Self::from_reader(BufReader::new(file)))
+    }
+}
+
+impl<R: AsyncRead + Unpin> RdbParser<R> {
+    /// Reads a snapshot from memory, such as the one a master sends on
+    /// FULLRESYNC.
+    pub(crate) fn from_reader(reader: R) -> Self {
+        Self { reader }
     }
 
     async fn read_exact(&mut self, len: usize) -> anyhow::Result<Bytes> {
@@ -130,6 +136,13 @@ impl RdbParser {
         }
     }
 
+    async fn read_length(&mut self) -> anyhow::Result<usize> {
+        match self.read_length_or_int().await? {
+            LengthEncoding::Length(len) => Ok(len),
+            LengthEncoding::Integer(_) => anyhow::bail!("Expected length, found integer encoding"),
+        }
+    }
+
     pub(crate) async fn parse(&mut self) -> anyhow::Result<Rdb> {
         let header = self.read_exact(9).await?;
         let header = String::from_utf8(header.to_vec())?;
@@ -137,6 +150,9 @@ impl RdbParser {
         let mut rdb = Rdb::new();
         rdb.header(&header);
 
+        let mut db_index = 0;
+        let mut expiry = None;
+
         loop {
             let next = self.read_byte().await?;
 
@@ -148,53 +164,41 @@ impl RdbParser {
                     rdb.metadata(&key, &val);
                 }
                 0xFE => {
-                    let _db_index = self.read_byte().await?;
-
-                    let _ = self.read_byte().await?;
-
-                    let total_keys = self.read_byte().await?[0] as usize;
-                    let _expire_keys = self.read_byte().await?[0] as usize;
-
-                    for _ in 0..total_keys {
-                        let data_index = self.read_byte().await?;
-
-                        let expiry = match data_index[0] {
-                            0xFC => {
-                                let bytes = self.read_exact(8).await?;
-                                let bytes = [
-                                    bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5],
-                                    bytes[6], bytes[7],
-                                ];
-                                let expiry = u64::from_le_bytes(bytes) as u64;
-                                let _data_index = self.read_byte().await?;
-
-                                Some(Duration::from_millis(expiry))
-                            }
-                            0xFD => {
-                                let bytes = self.read_exact(4).await?;
-                                let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
-                                let expiry = u32::from_le_bytes(bytes) as u64;
-                                let _data_index = self.read_byte().await?;
+                    db_index = self.read_length().await?;
+                }
+                0xFB => {
+                    let _total_keys = self.read_length().await?;
+                    let _expire_keys = self.read_length().await?;
+                }
+                0xFC => {
+                    let bytes = self.read_exact(8).await?;
+                    let bytes = [
+                        bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6],
+                        bytes[7],
+                    ];
 
-                                Some(Duration::from_secs(expiry))
-                            }
-                            _ => None,
-                        };
+                    expiry = Some(Duration::from_millis(u64::from_le_bytes(bytes)));
+                }
+                0xFD => {
+                    let bytes = self.read_exact(4).await?;
+                    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
 
-                        let key = self.read_string().await?;
-                        let val = self.read_string().await?;
+                    expiry = Some(Duration::from_secs(u32::from_le_bytes(bytes) as u64));
+                }
+                0x00 => {
+                    let key = self.read_string().await?;
+                    let val = self.read_string().await?;
 
-                        match expiry {
-                            Some(duration) => {
-                                let system_time = SystemTime::now();
-                                let expiry_time = UNIX_EPOCH + duration;
+                    match expiry.take() {
+                        Some(duration) => {
+                            let system_time = SystemTime::now();
+                            let expiry_time = UNIX_EPOCH + duration;
 
-                                if system_time < expiry_time {
-                                    rdb.data(&key, &val, Some(expiry_time));
-                                }
+                            if system_time < expiry_time {
+                                rdb.data(db_index, &key, &val, Some(expiry_time));
                             }
-                            None => rdb.data(&key, &val, None),
                         }
+                        None => rdb.data(db_index, &key, &val, None),
                     }
                 }
                 0xFF => {
@@ -209,3 +213,50 @@ impl RdbParser {
         Ok(rdb)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(out: &mut Vec<u8>, value: &str) {
+        out.push(value.len() as u8);
+        out.extend_from_slice(value.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_selectdb_assigns_keys_to_databases() {
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let later_ms = later.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        let mut file = b"REDIS0011".to_vec();
+        file.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x01, 0x00, 0x00]);
+        string(&mut file, "a");
+        string(&mut file, "1");
+        file.extend_from_slice(&[0xFE, 0x02, 0xFB, 0x03, 0x02, 0xFC]);
+        file.extend_from_slice(&later_ms.to_le_bytes());
+        file.push(0x00);
+        string(&mut file, "b");
+        string(&mut file, "2");
+        // Already expired, so it is dropped
+        file.push(0xFD);
+        file.extend_from_slice(&1u32.to_le_bytes());
+        file.push(0x00);
+        string(&mut file, "old");
+        string(&mut file, "3");
+        file.push(0x00);
+        string(&mut file, "c");
+        string(&mut file, "4");
+        file.push(0xFF);
+        file.extend_from_slice(&[0; 8]);
+
+        let rdb = RdbParser::from_reader(&file[..]).parse().await.unwrap();
+        let mut dbs: Vec<_> = rdb.data.keys().copied().collect();
+        dbs.sort();
+        assert_eq!(dbs, [0, 2]);
+        assert_eq!(rdb.data[&0]["a"].value, "1");
+        assert_eq!(rdb.data[&2].len(), 2);
+        assert!(rdb.data[&2]["b"].expiry.is_some());
+        // An expiry only applies to the key right after it
+        assert_eq!(rdb.data[&2]["c"].expiry, None);
+    }
+}
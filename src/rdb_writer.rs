@@ -0,0 +1,108 @@
+use std::time::UNIX_EPOCH;
+
+use crate::store::Db;
+
+/// Serializes `dbs` into an RDB snapshot that `RdbParser` can read back.
+///
+/// Only string keys are written, since the parser has no encoding for the
+/// other types. The checksum is left as zero, which readers treat as
+/// "checksum disabled".
+pub(crate) fn write(dbs: &[Db]) -> Vec<u8> {
+    let mut out = b"REDIS0011".to_vec();
+    write_aux(&mut out, "redis-ver", "7.2.0");
+    write_aux(&mut out, "redis-bits", "64");
+
+    for (index, db) in dbs.iter().enumerate() {
+        let strings: Vec<_> = db.strings().collect();
+        if strings.is_empty() {
+            continue;
+        }
+        let expires = strings.iter().filter(|(_, _, exp)| exp.is_some()).count();
+
+        out.push(0xFE);
+        write_length(&mut out, index);
+        out.push(0xFB);
+        write_length(&mut out, strings.len());
+        write_length(&mut out, expires);
+
+        for (key, value, expiry) in strings {
+            if let Some(expiry) = expiry {
+                let ms = expiry
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                out.push(0xFC);
+                out.extend_from_slice(&ms.to_le_bytes());
+            }
+            out.push(0x00);
+            write_string(&mut out, key);
+            write_string(&mut out, value);
+        }
+    }
+
+    out.push(0xFF);
+    out.extend_from_slice(&[0; 8]);
+    out
+}
+
+fn write_aux(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.push(0xFA);
+    write_string(out, key);
+    write_string(out, value);
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_length(out, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_length(out: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0x3F => out.push(len as u8),
+        0x40..=0x3FFF => out.extend_from_slice(&(0x4000 | len as u16).to_be_bytes()),
+        _ => {
+            out.push(0x80);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::rdb_parser::RdbParser;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let mut dbs: Vec<Db> = (0..3).map(|_| Db::new()).collect();
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        dbs[0]
+            .set("plain".to_string(), "a".to_string(), None)
+            .unwrap();
+        dbs[0]
+            .set("gone".to_string(), "b".to_string(), Some(earlier))
+            .unwrap();
+        dbs[2]
+            .set("ttl".to_string(), "x".repeat(100), Some(later))
+            .unwrap();
+        dbs[2]
+            .append_stream("events".to_string(), "1-1".to_string(), vec![])
+            .unwrap();
+
+        let snapshot = write(&dbs);
+        let rdb = RdbParser::from_reader(&snapshot[..]).parse().await.unwrap();
+
+        assert_eq!(rdb.data.len(), 2);
+        assert_eq!(rdb.data[&0].len(), 1);
+        assert_eq!(rdb.data[&0]["plain"].value, "a");
+        assert_eq!(rdb.data[&0]["plain"].expiry, None);
+        let ttl = &rdb.data[&2]["ttl"];
+        assert_eq!(ttl.value, "x".repeat(100));
+        let ms = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap().as_millis();
+        assert_eq!(ms(ttl.expiry.unwrap()), ms(later));
+        assert!(!rdb.data[&2].contains_key("events"));
+    }
+}
@@ -1,41 +1,138 @@
-use anyhow::{Context, Ok};
-use bytes::Bytes;
+use anyhow::Context;
+
+/// Wire protocol negotiated by a connection through HELLO.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
 
+impl Protocol {
+    pub(crate) fn version(&self) -> usize {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) enum Resp {
     SimpleString(String),
     SimpleError(String),
     BulkString(Option<String>),
     Array(Vec<Resp>),
-    Integer(usize),
+    NullArray,
+    Integer(i64),
+    Null,
+    // Not produced by any command yet, but part of the RESP3 type set
+    #[allow(dead_code)]
+    Boolean(bool),
+    #[allow(dead_code)]
+    Double(f64),
+    #[allow(dead_code)]
+    BigNumber(String),
+    VerbatimString {
+        format: String,
+        text: String,
+    },
+    Map(Vec<(Resp, Resp)>),
+    Set(Vec<Resp>),
+    #[allow(dead_code)]
+    Attribute {
+        attributes: Vec<(Resp, Resp)>,
+        value: Box<Resp>,
+    },
+    Push(Vec<Resp>),
 }
 
 impl Resp {
     pub(crate) fn encode(&self) -> String {
+        self.encode_with(Protocol::Resp2)
+    }
+
+    /// Encodes for `protocol`; under RESP2 the RESP3-only types degrade to
+    /// their closest RESP2 equivalent, the way Redis replies to old clients.
+    pub(crate) fn encode_with(&self, protocol: Protocol) -> String {
+        let mut encoded = String::new();
+        self.write(&mut encoded, protocol);
+        encoded
+    }
+
+    fn write(&self, out: &mut String, protocol: Protocol) {
+        let resp3 = protocol == Protocol::Resp3;
+
         match self {
-            Resp::SimpleString(msg) => format!("+{}\r\n", msg),
-            Resp::SimpleError(msg) => format!("-ERR {}\r\n", msg),
-            Resp::BulkString(Some(msg)) => format!("${}\r\n{}\r\n", msg.len(), msg),
-            Resp::BulkString(None) => "$-1\r\n".to_string(),
-            Resp::Integer(msg) => format!(":{}\r\n", msg),
-            Resp::Array(msgs) => {
-                let mut encoded = format!("*{}\r\n", msgs.len());
-                for msg in msgs {
-                    encoded.push_str(&msg.encode());
+            Resp::SimpleString(msg) => out.push_str(&format!("+{}\r\n", msg)),
+            Resp::SimpleError(msg) => out.push_str(&format!("-{}\r\n", msg)),
+            Resp::BulkString(Some(msg)) => out.push_str(&format!("${}\r\n{}\r\n", msg.len(), msg)),
+            Resp::BulkString(None) | Resp::Null if resp3 => out.push_str("_\r\n"),
+            Resp::BulkString(None) | Resp::Null => out.push_str("$-1\r\n"),
+            Resp::Integer(msg) => out.push_str(&format!(":{}\r\n", msg)),
+            Resp::Array(msgs) => Self::write_aggregate(out, '*', msgs, protocol),
+            Resp::NullArray if resp3 => out.push_str("_\r\n"),
+            Resp::NullArray => out.push_str("*-1\r\n"),
+            Resp::Boolean(value) if resp3 => out.push_str(if *value { "#t\r\n" } else { "#f\r\n" }),
+            Resp::Boolean(value) => out.push_str(&format!(":{}\r\n", *value as i64)),
+            Resp::Double(value) if resp3 => {
+                out.push_str(&format!(",{}\r\n", format_double(*value)))
+            }
+            Resp::Double(value) => Resp::bulk(format_double(*value)).write(out, protocol),
+            Resp::BigNumber(value) if resp3 => out.push_str(&format!("({}\r\n", value)),
+            Resp::BigNumber(value) => Resp::bulk(value.as_str()).write(out, protocol),
+            Resp::VerbatimString { format, text } if resp3 => out.push_str(&format!(
+                "={}\r\n{}:{}\r\n",
+                format.len() + 1 + text.len(),
+                format,
+                text
+            )),
+            Resp::VerbatimString { text, .. } => Resp::bulk(text.as_str()).write(out, protocol),
+            Resp::Map(entries) if resp3 => {
+                out.push_str(&format!("%{}\r\n", entries.len()));
+                for (key, value) in entries {
+                    key.write(out, protocol);
+                    value.write(out, protocol);
+                }
+            }
+            Resp::Map(entries) => {
+                out.push_str(&format!("*{}\r\n", entries.len() * 2));
+                for (key, value) in entries {
+                    key.write(out, protocol);
+                    value.write(out, protocol);
                 }
-                encoded
             }
+            Resp::Set(msgs) if resp3 => Self::write_aggregate(out, '~', msgs, protocol),
+            Resp::Set(msgs) => Self::write_aggregate(out, '*', msgs, protocol),
+            Resp::Attribute { attributes, value } if resp3 => {
+                out.push_str(&format!("|{}\r\n", attributes.len()));
+                for (key, attr) in attributes {
+                    key.write(out, protocol);
+                    attr.write(out, protocol);
+                }
+                value.write(out, protocol);
+            }
+            // RESP2 has no way to carry attributes, so only the value is sent
+            Resp::Attribute { value, .. } => value.write(out, protocol),
+            Resp::Push(msgs) if resp3 => Self::write_aggregate(out, '>', msgs, protocol),
+            Resp::Push(msgs) => Self::write_aggregate(out, '*', msgs, protocol),
         }
     }
 
-    pub(crate) fn decode(buf: Bytes) -> anyhow::Result<Vec<String>> {
-        let args = match buf.first() {
-            Some(b'*') => parse_array(buf.slice(1..))?,
-            Some(b'$') => parse_bulk_string(buf.slice(1..))?,
-            Some(b'+') => parse_simple_string(buf.slice(1..))?,
-            _ => anyhow::bail!("Unsupported RESP type"),
-        };
+    fn write_aggregate(out: &mut String, prefix: char, msgs: &[Resp], protocol: Protocol) {
+        out.push_str(&format!("{}{}\r\n", prefix, msgs.len()));
+        for msg in msgs {
+            msg.write(out, protocol);
+        }
+    }
+
+    /// Decodes one complete frame from the start of `buf`, returning it with
+    /// the number of bytes consumed, or `None` if more data is needed.
+    pub(crate) fn decode(buf: &[u8]) -> anyhow::Result<Option<(Resp, usize)>> {
+        let mut decoder = Decoder { buf, pos: 0 };
+        let value = decoder.value()?;
 
-        Ok(args)
+        Ok(value.map(|value| (value, decoder.pos)))
     }
 
     pub(crate) fn array(keys: Vec<String>) -> Resp {
@@ -47,7 +144,7 @@ impl Resp {
     }
 
     pub(crate) fn integer(msg: usize) -> Resp {
-        Resp::Integer(msg)
+        Resp::Integer(msg as i64)
     }
 
     pub(crate) fn ok() -> Resp {
@@ -55,83 +152,199 @@ impl Resp {
     }
 
     pub(crate) fn null() -> Resp {
-        Resp::BulkString(None)
+        Resp::Null
     }
 
     pub(crate) fn error(msg: &str) -> Resp {
-        Resp::SimpleError(msg.to_string())
+        Resp::SimpleError(format!("ERR {}", msg))
     }
 }
 
-fn parse_array(buf: Bytes) -> anyhow::Result<Vec<String>> {
-    let mut pos = buf
-        .iter()
-        .position(|&b| b == b'\r')
-        .ok_or_else(|| anyhow::anyhow!("Invalid RESP array: missing CRLF"))?;
-
-    let param_count = std::str::from_utf8(&buf[..pos])?
-        .parse::<usize>()
-        .context("Failed to parse array length")?;
-
-    let mut result = vec![];
-
-    pos += 2;
-    for _ in 0..param_count {
-        let word = buf.slice(pos..);
-
-        let crlf_pos = word
-            .iter()
-            .position(|&b| b == b'\r')
-            .ok_or_else(|| anyhow::anyhow!("Invalid RESP string; missing CRLF"))?;
-        let data_len = std::str::from_utf8(&word[1..crlf_pos])?
-            .parse::<usize>()
-            .context("Failed to parse RESP string")?;
-
-        let data_start = crlf_pos + 2;
-        let data_end = data_start + data_len;
-        result.push(
-            std::str::from_utf8(&word[data_start..data_end])
-                .context("Failed parse UTF-8")?
-                .to_string(),
-        );
-
-        pos = pos + data_end + 2;
+fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        value.to_string()
     }
+}
 
-    Ok(result)
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
 }
 
-fn parse_bulk_string(buf: Bytes) -> anyhow::Result<Vec<String>> {
-    let crlf_pos = buf
-        .iter()
-        .position(|&b| b == b'\r')
-        .ok_or_else(|| anyhow::anyhow!("Invalid bulk string: missing CRLF"))?;
+impl Decoder<'_> {
+    /// Reads up to the next CRLF, without it.
+    fn line(&mut self) -> anyhow::Result<Option<&str>> {
+        let rest = &self.buf[self.pos..];
+        let end = match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None => return Ok(None),
+        };
 
-    let data_len = std::str::from_utf8(&buf[..crlf_pos])?
-        .parse::<usize>()
-        .context("Failed to parse bulk string length")?;
+        self.pos += end + 2;
+        let line = std::str::from_utf8(&rest[..end]).context("Protocol error: invalid UTF-8")?;
 
-    let data_start = crlf_pos + 2;
-    let data_end = data_start + data_len;
+        Ok(Some(line))
+    }
 
-    let s = std::str::from_utf8(&buf[data_start..data_end])
-        .unwrap_or("")
-        .to_string();
+    fn length(&mut self) -> anyhow::Result<Option<i64>> {
+        match self.line()? {
+            Some(line) => {
+                Ok(Some(line.parse().with_context(|| {
+                    format!("Protocol error: invalid length '{}'", line)
+                })?))
+            }
+            None => Ok(None),
+        }
+    }
 
-    Ok(vec![s])
-}
+    /// Reads `len` bytes followed by CRLF.
+    fn blob(&mut self, len: usize) -> anyhow::Result<Option<String>> {
+        if self.buf.len() < self.pos + len + 2 {
+            return Ok(None);
+        }
+
+        let data = &self.buf[self.pos..self.pos + len];
+        if &self.buf[self.pos + len..self.pos + len + 2] != b"\r\n" {
+            anyhow::bail!("Protocol error: missing CRLF after bulk data");
+        }
+        self.pos += len + 2;
+
+        let data = std::str::from_utf8(data).context("Protocol error: invalid UTF-8")?;
+        Ok(Some(data.to_string()))
+    }
+
+    fn values(&mut self, count: usize) -> anyhow::Result<Option<Vec<Resp>>> {
+        let mut values = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            match self.value()? {
+                Some(value) => values.push(value),
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(values))
+    }
+
+    fn pairs(&mut self, count: usize) -> anyhow::Result<Option<Vec<(Resp, Resp)>>> {
+        let mut pairs = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            let (key, value) = match (self.value()?, self.value()?) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Ok(None),
+            };
+            pairs.push((key, value));
+        }
 
-fn parse_simple_string(buf: Bytes) -> anyhow::Result<Vec<String>> {
-    let crlf_pos = buf
-        .iter()
-        .position(|&b| b == b'\r')
-        .ok_or_else(|| anyhow::anyhow!("Invalid simple string: missing CRLF"))?;
+        Ok(Some(pairs))
+    }
 
-    let s = std::str::from_utf8(&buf[..crlf_pos])
-        .context("Failed to parse UTF-8")?
-        .to_string();
+    fn value(&mut self) -> anyhow::Result<Option<Resp>> {
+        let prefix = match self.buf.get(self.pos) {
+            Some(prefix) => *prefix,
+            None => return Ok(None),
+        };
+        self.pos += 1;
 
-    Ok(vec![s])
+        macro_rules! ready {
+            ($e:expr) => {
+                match $e {
+                    Some(value) => value,
+                    None => return Ok(None),
+                }
+            };
+        }
+
+        let value = match prefix {
+            b'+' => Resp::SimpleString(ready!(self.line()?).to_string()),
+            b'-' => Resp::SimpleError(ready!(self.line()?).to_string()),
+            b':' => {
+                let line = ready!(self.line()?);
+                Resp::Integer(
+                    line.parse()
+                        .with_context(|| format!("Protocol error: invalid integer '{}'", line))?,
+                )
+            }
+            b'$' => match ready!(self.length()?) {
+                -1 => Resp::BulkString(None),
+                len if len >= 0 => Resp::BulkString(Some(ready!(self.blob(len as usize)?))),
+                len => anyhow::bail!("Protocol error: invalid bulk length {}", len),
+            },
+            b'!' => {
+                let len = ready!(self.length()?);
+                anyhow::ensure!(len >= 0, "Protocol error: invalid bulk length {}", len);
+                Resp::SimpleError(ready!(self.blob(len as usize)?))
+            }
+            b'=' => {
+                let len = ready!(self.length()?);
+                anyhow::ensure!(len >= 4, "Protocol error: invalid verbatim length {}", len);
+                let data = ready!(self.blob(len as usize)?);
+                let (format, text) = data
+                    .split_once(':')
+                    .context("Protocol error: verbatim string without format")?;
+                Resp::VerbatimString {
+                    format: format.to_string(),
+                    text: text.to_string(),
+                }
+            }
+            b'*' => match ready!(self.length()?) {
+                -1 => Resp::NullArray,
+                len if len >= 0 => Resp::Array(ready!(self.values(len as usize)?)),
+                len => anyhow::bail!("Protocol error: invalid multibulk length {}", len),
+            },
+            b'~' | b'>' => {
+                let len = ready!(self.length()?);
+                anyhow::ensure!(len >= 0, "Protocol error: invalid aggregate length {}", len);
+                let values = ready!(self.values(len as usize)?);
+                if prefix == b'~' {
+                    Resp::Set(values)
+                } else {
+                    Resp::Push(values)
+                }
+            }
+            b'%' | b'|' => {
+                let len = ready!(self.length()?);
+                anyhow::ensure!(len >= 0, "Protocol error: invalid aggregate length {}", len);
+                let pairs = ready!(self.pairs(len as usize)?);
+                if prefix == b'%' {
+                    Resp::Map(pairs)
+                } else {
+                    // Attributes decorate the value that follows them
+                    Resp::Attribute {
+                        attributes: pairs,
+                        value: Box::new(ready!(self.value()?)),
+                    }
+                }
+            }
+            b'_' => {
+                ready!(self.line()?);
+                Resp::Null
+            }
+            b'#' => match ready!(self.line()?) {
+                "t" => Resp::Boolean(true),
+                "f" => Resp::Boolean(false),
+                other => anyhow::bail!("Protocol error: invalid boolean '{}'", other),
+            },
+            b',' => {
+                let line = ready!(self.line()?);
+                let value = match line {
+                    "inf" => f64::INFINITY,
+                    "-inf" => f64::NEG_INFINITY,
+                    _ => line
+                        .parse()
+                        .with_context(|| format!("Protocol error: invalid double '{}'", line))?,
+                };
+                Resp::Double(value)
+            }
+            b'(' => Resp::BigNumber(ready!(self.line()?).to_string()),
+            other => anyhow::bail!("Protocol error: unexpected type byte '{}'", other as char),
+        };
+
+        Ok(Some(value))
+    }
 }
 
 #[cfg(test)]
@@ -162,6 +375,59 @@ mod tests {
         assert_eq!(resp.encode(), "$-1\r\n");
     }
 
+    #[test]
+    fn test_encode_negative_integer() {
+        assert_eq!(Resp::Integer(-2).encode(), ":-2\r\n");
+    }
+
+    #[test]
+    fn test_encode_resp3_types() {
+        let p = Protocol::Resp3;
+        assert_eq!(Resp::Null.encode_with(p), "_\r\n");
+        assert_eq!(Resp::null().encode_with(p), "_\r\n");
+        assert_eq!(Resp::Boolean(true).encode_with(p), "#t\r\n");
+        assert_eq!(Resp::Double(1.5).encode_with(p), ",1.5\r\n");
+        assert_eq!(Resp::Double(f64::NEG_INFINITY).encode_with(p), ",-inf\r\n");
+        assert_eq!(
+            Resp::BigNumber("3492890328409238509324850943850943825024385".to_string())
+                .encode_with(p),
+            "(3492890328409238509324850943850943825024385\r\n"
+        );
+        let verbatim = Resp::VerbatimString {
+            format: "txt".to_string(),
+            text: "Some string".to_string(),
+        };
+        assert_eq!(verbatim.encode_with(p), "=15\r\ntxt:Some string\r\n");
+        let map = Resp::Map(vec![(Resp::bulk("a"), Resp::Integer(1))]);
+        assert_eq!(map.encode_with(p), "%1\r\n$1\r\na\r\n:1\r\n");
+        assert_eq!(
+            Resp::Set(vec![Resp::Integer(1)]).encode_with(p),
+            "~1\r\n:1\r\n"
+        );
+        assert_eq!(
+            Resp::Push(vec![Resp::bulk("a")]).encode_with(p),
+            ">1\r\n$1\r\na\r\n"
+        );
+        let attribute = Resp::Attribute {
+            attributes: vec![(Resp::bulk("ttl"), Resp::Integer(5))],
+            value: Box::new(Resp::ok()),
+        };
+        assert_eq!(attribute.encode_with(p), "|1\r\n$3\r\nttl\r\n:5\r\n+OK\r\n");
+    }
+
+    #[test]
+    fn test_encode_resp3_types_downgrade_to_resp2() {
+        assert_eq!(Resp::Null.encode(), "$-1\r\n");
+        assert_eq!(Resp::Boolean(false).encode(), ":0\r\n");
+        assert_eq!(Resp::Double(2.0).encode(), "$1\r\n2\r\n");
+        let map = Resp::Map(vec![(Resp::bulk("a"), Resp::Integer(1))]);
+        assert_eq!(map.encode(), "*2\r\n$1\r\na\r\n:1\r\n");
+        assert_eq!(
+            Resp::Push(vec![Resp::bulk("a")]).encode(),
+            "*1\r\n$1\r\na\r\n"
+        );
+    }
+
     #[test]
     fn test_ok_helper() {
         assert_eq!(Resp::ok().encode(), "+OK\r\n");
@@ -172,31 +438,129 @@ mod tests {
         assert_eq!(Resp::null().encode(), "$-1\r\n");
     }
 
+    fn decode_all(input: &[u8]) -> Resp {
+        let (value, len) = Resp::decode(input).unwrap().unwrap();
+        assert_eq!(len, input.len());
+        value
+    }
+
     #[test]
     fn test_decode_single_element_array() {
-        let input = Bytes::from("*1\r\n$4\r\nPING\r\n");
-        let result = Resp::decode(input).unwrap();
-        assert_eq!(result, vec!["PING"]);
+        let result = decode_all(b"*1\r\n$4\r\nPING\r\n");
+        assert_eq!(result, Resp::array(vec!["PING".to_string()]));
     }
 
     #[test]
     fn test_decode_two_element_array() {
-        let input = Bytes::from("*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n");
-        let result = Resp::decode(input).unwrap();
-        assert_eq!(result, vec!["ECHO", "hello"]);
+        let result = decode_all(b"*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n");
+        assert_eq!(
+            result,
+            Resp::array(vec!["ECHO".to_string(), "hello".to_string()])
+        );
     }
 
     #[test]
     fn test_decode_set_command() {
-        let input = Bytes::from("*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n");
-        let result = Resp::decode(input).unwrap();
-        assert_eq!(result, vec!["SET", "foo", "bar"]);
+        let result = decode_all(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n");
+        assert_eq!(
+            result,
+            Resp::array(vec![
+                "SET".to_string(),
+                "foo".to_string(),
+                "bar".to_string()
+            ])
+        );
     }
 
     #[test]
     fn test_decode_invalid_type() {
-        let input = Bytes::from("+OK\r\n");
-        let result = Resp::decode(input);
+        let result = Resp::decode(b"*1\r\n@OK\r\n");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_decode_simple_types() {
+        assert_eq!(decode_all(b"+OK\r\n"), Resp::ok());
+        assert_eq!(decode_all(b"-ERR bad\r\n"), Resp::error("bad"));
+        assert_eq!(decode_all(b":-42\r\n"), Resp::Integer(-42));
+        assert_eq!(decode_all(b"$-1\r\n"), Resp::BulkString(None));
+        assert_eq!(decode_all(b"*-1\r\n"), Resp::NullArray);
+    }
+
+    #[test]
+    fn test_decode_nested_array() {
+        let input = b"*2\r\n*2\r\n:1\r\n-ERR x\r\n$1\r\na\r\n";
+        assert_eq!(
+            decode_all(input),
+            Resp::Array(vec![
+                Resp::Array(vec![Resp::Integer(1), Resp::error("x")]),
+                Resp::bulk("a"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_decode_resp3_types() {
+        assert_eq!(decode_all(b"_\r\n"), Resp::Null);
+        assert_eq!(decode_all(b"#t\r\n"), Resp::Boolean(true));
+        assert_eq!(decode_all(b",-inf\r\n"), Resp::Double(f64::NEG_INFINITY));
+        assert_eq!(decode_all(b",1.5\r\n"), Resp::Double(1.5));
+        assert_eq!(decode_all(b"(123\r\n"), Resp::BigNumber("123".to_string()));
+        assert_eq!(
+            decode_all(b"=7\r\ntxt:abc\r\n"),
+            Resp::VerbatimString {
+                format: "txt".to_string(),
+                text: "abc".to_string()
+            }
+        );
+        assert_eq!(
+            decode_all(b"%1\r\n+a\r\n:1\r\n"),
+            Resp::Map(vec![(
+                Resp::SimpleString("a".to_string()),
+                Resp::Integer(1)
+            )])
+        );
+        assert_eq!(
+            decode_all(b"~1\r\n:1\r\n"),
+            Resp::Set(vec![Resp::Integer(1)])
+        );
+        assert_eq!(
+            decode_all(b">1\r\n:1\r\n"),
+            Resp::Push(vec![Resp::Integer(1)])
+        );
+        assert_eq!(
+            decode_all(b"|1\r\n+ttl\r\n:5\r\n+OK\r\n"),
+            Resp::Attribute {
+                attributes: vec![(Resp::SimpleString("ttl".to_string()), Resp::Integer(5))],
+                value: Box::new(Resp::ok()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_roundtrips_resp3_encoding() {
+        let value = Resp::Map(vec![(
+            Resp::bulk("k"),
+            Resp::Push(vec![Resp::Double(2.5), Resp::Null, Resp::Boolean(false)]),
+        )]);
+        let encoded = value.encode_with(Protocol::Resp3);
+        assert_eq!(decode_all(encoded.as_bytes()), value);
+    }
+
+    #[test]
+    fn test_decode_incomplete_frame() {
+        assert_eq!(
+            Resp::decode(b"*2\r\n$4\r\nECHO\r\n$5\r\nhel").unwrap(),
+            None
+        );
+        assert_eq!(Resp::decode(b"$5\r\nhello").unwrap(), None);
+        assert_eq!(Resp::decode(b"+PO").unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_consumes_single_frame() {
+        let (value, len) = Resp::decode(b"+OK\r\n+PONG\r\n").unwrap().unwrap();
+        assert_eq!(value, Resp::ok());
+        assert_eq!(len, 5);
+    }
 }
@@ -1,26 +1,89 @@
+use std::{collections::VecDeque, io::IoSlice, time::Instant};
+
 use anyhow::Context;
-use bytes::BytesMut;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    net::TcpStream,
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{
+    parser::{ProtocolError, Request, RequestParser, DEFAULT_MAX_BULK_LEN},
+    Stream,
 };
+use crate::{store::OutputBufferLimit, Resp};
 
-use crate::Resp;
+/// Replies at least this large are written from their own allocation with a
+/// vectored write instead of being copied into the shared output buffer.
+const VECTORED_REPLY_MIN: usize = 16 * 1024;
+/// Most buffers handed to the kernel in a single vectored write.
+const MAX_IOV: usize = 64;
 
 #[derive(Debug)]
 pub struct Conn {
-    stream: BufWriter<TcpStream>,
+    stream: Stream,
     buffer: BytesMut,
+    parser: RequestParser,
+    /// Replies waiting to be written, flushed once per batch of requests.
+    output: VecDeque<Bytes>,
+    /// Small replies are coalesced here before joining `output`.
+    output_tail: BytesMut,
+    output_len: usize,
+    /// When the output buffer last rose above its soft limit.
+    soft_limit_since: Option<Instant>,
 }
 
 impl Conn {
-    pub fn new(stream: TcpStream) -> Self {
+    pub(crate) fn new(stream: impl Into<Stream>) -> Self {
+        let stream = stream.into();
+        // Replies are already coalesced per batch, so Nagle would only add
+        // latency waiting on delayed ACKs
+        if let Err(e) = stream.set_nodelay(true) {
+            eprintln!("Failed to set TCP_NODELAY; Err: {e}");
+        }
+
         Self {
-            stream: BufWriter::new(stream),
+            stream,
             buffer: BytesMut::with_capacity(1024),
+            parser: RequestParser::new(DEFAULT_MAX_BULK_LEN),
+            output: VecDeque::new(),
+            output_tail: BytesMut::new(),
+            output_len: 0,
+            soft_limit_since: None,
         }
     }
 
+    pub(crate) fn set_max_bulk_len(&mut self, max_bulk_len: usize) {
+        self.parser.set_max_bulk_len(max_bulk_len);
+    }
+
+    /// Address of the peer, as `ip:port`.
+    pub(crate) fn peer_addr(&self) -> String {
+        self.stream.peer_addr()
+    }
+
+    /// Address the connection was accepted on, as `ip:port`.
+    pub(crate) fn local_addr(&self) -> String {
+        self.stream.local_addr()
+    }
+
+    pub(crate) fn is_local(&self) -> bool {
+        self.stream.is_local()
+    }
+
+    /// Common name of the client certificate, on TLS connections that
+    /// presented one.
+    pub(crate) fn peer_common_name(&self) -> Option<String> {
+        self.stream.peer_common_name()
+    }
+
+    /// Bytes read but not yet parsed into requests.
+    pub(crate) fn input_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Bytes queued but not yet written.
+    pub(crate) fn output_len(&self) -> usize {
+        self.output_len
+    }
+
     pub fn _clear_buffer(&mut self) {
         self.buffer.clear();
     }
@@ -34,6 +97,92 @@ impl Conn {
         Ok(())
     }
 
+    /// Reads more bytes into the input buffer, failing once the peer closes.
+    /// Cancel safe, so it can be raced against other events.
+    pub(crate) async fn fill_buffer(&mut self) -> anyhow::Result<()> {
+        // Grow once up front rather than repeatedly for large arguments
+        let pending = self.parser.pending(self.buffer.len());
+        self.buffer.reserve(pending);
+
+        let n = self.read_raw().await?;
+        if n == 0 {
+            anyhow::bail!("Connection closed");
+        }
+
+        Ok(())
+    }
+
+    /// Takes the next complete request from the input buffer without reading.
+    pub(crate) fn next_request(&mut self) -> Result<Option<Request>, ProtocolError> {
+        self.parser.parse(&mut self.buffer)
+    }
+
+    /// Appends a reply to the output buffer; nothing is written until
+    /// `flush_output`.
+    pub(crate) fn queue(&mut self, reply: impl Into<Bytes>) {
+        let reply = reply.into();
+        self.output_len += reply.len();
+
+        if reply.len() >= VECTORED_REPLY_MIN {
+            if !self.output_tail.is_empty() {
+                self.output.push_back(self.output_tail.split().freeze());
+            }
+            self.output.push_back(reply);
+        } else {
+            self.output_tail.extend_from_slice(&reply);
+        }
+    }
+
+    /// Checks the pending output against `limit`, returning whether the
+    /// client should be disconnected.
+    pub(crate) fn output_limit_reached(&mut self, limit: OutputBufferLimit) -> bool {
+        limit.exceeded(self.output_len, &mut self.soft_limit_since)
+    }
+
+    /// Writes every queued reply, using vectored writes so large replies
+    /// go out without being copied.
+    pub(crate) async fn flush_output(&mut self) -> anyhow::Result<()> {
+        if !self.output_tail.is_empty() {
+            self.output.push_back(self.output_tail.split().freeze());
+        }
+
+        while !self.output.is_empty() {
+            let slices: Vec<IoSlice> = self
+                .output
+                .iter()
+                .take(MAX_IOV)
+                .map(|chunk| IoSlice::new(chunk))
+                .collect();
+            let mut n = self
+                .stream
+                .write_vectored(&slices)
+                .await
+                .context("Failed to write to stream")?;
+            anyhow::ensure!(n > 0, "Connection closed");
+            self.output_len -= n;
+
+            while n > 0 {
+                let chunk = self.output.front_mut().expect("written bytes were queued");
+                if n < chunk.len() {
+                    chunk.advance(n);
+                    break;
+                }
+                n -= chunk.len();
+                self.output.pop_front();
+            }
+        }
+
+        // TLS buffers records until flushed
+        self.flush().await?;
+
+        // Give back the memory of an unusually large batch
+        if self.output_tail.capacity() > VECTORED_REPLY_MIN {
+            self.output_tail = BytesMut::new();
+        }
+
+        Ok(())
+    }
+
     pub async fn read_raw(&mut self) -> anyhow::Result<usize> {
         let n = self
             .stream
@@ -58,12 +207,24 @@ impl Conn {
         Ok(())
     }
 
-    pub async fn read_frame(&mut self) -> anyhow::Result<(usize, Vec<String>)> {
+    /// Reads the next client request. Cancel safe: a partially received
+    /// request stays buffered and parsing resumes where it stopped.
+    pub(crate) async fn read_request(&mut self) -> anyhow::Result<Request> {
+        loop {
+            if let Some(request) = self.next_request()? {
+                return Ok(request);
+            }
+            self.fill_buffer().await?;
+        }
+    }
+
+    /// Reads the next reply frame, used when talking to another server.
+    pub async fn read_frame(&mut self) -> anyhow::Result<(usize, Resp)> {
         loop {
             // Try to parse a complete frame from what's already buffered
-            if let Some(frame_len) = self.find_frame_end() {
-                let data = self.buffer.split_to(frame_len).freeze();
-                return Ok((frame_len, Resp::decode(data)?));
+            if let Some((frame, frame_len)) = Resp::decode(&self.buffer)? {
+                self.buffer.advance(frame_len);
+                return Ok((frame_len, frame));
             }
             // Not enough data yet, read more
             let n = self.read_raw().await?;
@@ -73,52 +234,29 @@ impl Conn {
         }
     }
 
-    fn find_frame_end(&self) -> Option<usize> {
-        match self.buffer.first()? {
-            b'+' | b'-' => {
-                // SimpleString/Error: ends at \r\n
-                let pos = self.buffer.iter().position(|&b| b == b'\n')?;
-                Some(pos + 1)
-            }
-            b'$' => {
-                // BulkString: $<len>\r\n<data>\r\n
-                let crlf = self.buffer.iter().position(|&b| b == b'\n')?;
-                let len: usize = std::str::from_utf8(&self.buffer[1..crlf - 1])
-                    .ok()?
+    /// Reads the RDB snapshot a master sends after FULLRESYNC: a bulk string
+    /// header followed by raw bytes, without the trailing CRLF.
+    pub async fn read_rdb(&mut self) -> anyhow::Result<Bytes> {
+        loop {
+            if let Some(lf) = self.buffer.iter().position(|&b| b == b'\n') {
+                anyhow::ensure!(
+                    self.buffer.first() == Some(&b'$') && lf >= 2,
+                    "Expected RDB payload"
+                );
+                let len: usize = std::str::from_utf8(&self.buffer[1..lf - 1])?
                     .parse()
-                    .ok()?;
-                let total = crlf + 1 + len;
-                if self.buffer.len() >= total {
-                    Some(total)
-                } else {
-                    None
+                    .context("Invalid RDB payload length")?;
+
+                if self.buffer.len() >= lf + 1 + len {
+                    self.buffer.advance(lf + 1);
+                    return Ok(self.buffer.split_to(len).freeze());
                 }
             }
-            b'*' => {
-                // Array: need to scan all elements
-                // Simple approach: find end by counting \r\n pairs
-                let crlf = self.buffer.iter().position(|&b| b == b'\n')?;
-                let count: usize = std::str::from_utf8(&self.buffer[1..crlf - 1])
-                    .ok()?
-                    .parse()
-                    .ok()?;
-                let mut pos = crlf + 1;
-                for _ in 0..count {
-                    // skip $<len>\r\n
-                    let lf = self.buffer[pos..].iter().position(|&b| b == b'\n')? + pos;
-                    let len: usize = std::str::from_utf8(&self.buffer[pos + 1..lf - 1])
-                        .ok()?
-                        .parse()
-                        .ok()?;
-                    pos = lf + 1 + len + 2;
-                }
-                if self.buffer.len() >= pos {
-                    Some(pos)
-                } else {
-                    None
-                }
+
+            let n = self.read_raw().await?;
+            if n == 0 {
+                anyhow::bail!("Connection closed");
             }
-            _ => None,
         }
     }
 }
@@ -1,20 +1,205 @@
-use crate::{handler::handle_client, server::Conn, Store};
-use std::sync::Arc;
-use tokio::{net::TcpListener, sync::Mutex};
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::unix::fs::PermissionsExt,
+};
 
-pub async fn listen(addr: &str, store: Arc<Mutex<Store>>) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(addr).await?;
+use anyhow::Context;
+use socket2::{Domain, Socket, Type};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::TlsAcceptor;
 
+use crate::{handler::handle_client, server::Conn, store::Executor};
+
+/// Pending connections the kernel queues for each listener, as
+/// `tcp-backlog` defaults to.
+const TCP_BACKLOG: i32 = 511;
+
+/// Binds `port` on every address of the `bind` option. `*` and `::*` are
+/// any IPv4 and IPv6 address, and addresses prefixed with `-` are skipped
+/// if they can't be bound, such as `::1` on a host without IPv6.
+pub fn bind(addrs: &[String], port: u16) -> anyhow::Result<Vec<TcpListener>> {
+    let mut listeners = vec![];
+
+    for addr in addrs {
+        let (optional, host) = match addr.strip_prefix('-') {
+            Some(host) => (true, host),
+            None => (false, addr.as_str()),
+        };
+        let ip = match host {
+            "*" => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            "::*" => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            host => host
+                .parse()
+                .with_context(|| format!("Invalid bind address '{}'", host))?,
+        };
+
+        match bind_addr(SocketAddr::new(ip, port)) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if optional => eprintln!("Skipping bind address {}; err = {}", host, e),
+            Err(e) => return Err(e).with_context(|| format!("Failed to bind {}:{}", host, port)),
+        }
+    }
+    anyhow::ensure!(
+        !listeners.is_empty(),
+        "No bind address available for port {}",
+        port
+    );
+
+    Ok(listeners)
+}
+
+fn bind_addr(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    // Keep IPv6 sockets off IPv4, so `* ::*` can bind both on one port
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(TCP_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Serves clients accepted by `listener`, over TLS if given an acceptor.
+pub async fn listen(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    executor: Executor,
+) -> anyhow::Result<()> {
     loop {
         let (stream, _addr) = listener.accept().await?;
-        let store = store.clone();
+        let executor = executor.clone();
+        let tls = tls.clone();
 
         tokio::spawn(async move {
-            let conn = Conn::new(stream);
+            // The handshake runs on the connection's task, so a slow client
+            // doesn't hold up the others
+            let conn = match tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => Conn::new(stream),
+                    Err(e) => {
+                        eprintln!("TLS handshake failed; err = {}", e);
+                        return;
+                    }
+                },
+                None => Conn::new(stream),
+            };
 
-            if let Err(e) = handle_client(conn, &store).await {
-                eprintln!("Failed to handle client; err = {}", e);
-            }
+            serve(conn, executor).await;
         });
     }
 }
+
+/// Serves clients connecting to the Unix socket at `path`. A non-zero
+/// `perm` sets the socket's file mode, as `unixsocketperm` does.
+pub async fn listen_unix(path: String, perm: u32, executor: Executor) -> anyhow::Result<()> {
+    // A socket left behind by an earlier run would fail the bind
+    if fs::metadata(&path).is_ok() {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove stale Unix socket '{}'", path))?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to open Unix socket '{}'", path))?;
+    if perm != 0 {
+        fs::set_permissions(&path, fs::Permissions::from_mode(perm))
+            .with_context(|| format!("Failed to set permissions of Unix socket '{}'", path))?;
+    }
+
+    loop {
+        let (stream, _addr) = listener.accept().await?;
+        let executor = executor.clone();
+
+        tokio::spawn(serve(Conn::new(stream), executor));
+    }
+}
+
+async fn serve(conn: Conn, executor: Executor) {
+    if let Err(e) = handle_client(conn, &executor).await {
+        eprintln!("Failed to handle client; err = {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpStream, UnixStream},
+    };
+
+    use super::*;
+    use crate::{
+        server::Stream,
+        store::{load_config, Store},
+    };
+
+    fn addrs(addrs: &[&str]) -> Vec<String> {
+        addrs.iter().map(|addr| addr.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_bind_skips_optional_addresses() {
+        // 192.0.2.0/24 is reserved for documentation, so never local
+        let listeners = bind(&addrs(&["-192.0.2.1", "127.0.0.1"]), 0).unwrap();
+        assert_eq!(listeners.len(), 1);
+        let addr = listeners[0].local_addr().unwrap();
+        assert!(addr.ip().is_loopback());
+
+        let err = bind(&addrs(&["192.0.2.1"]), 0).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Failed to bind 192.0.2.1"),
+            "{}",
+            err
+        );
+        let err = bind(&addrs(&["-192.0.2.1"]), 0).unwrap_err();
+        assert!(err.to_string().starts_with("No bind address"), "{}", err);
+        let err = bind(&addrs(&["localhost"]), 0).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid bind address 'localhost'");
+        assert_eq!(bind(&addrs(&["*"]), 0).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_loopback_clients_are_local() {
+        let listener = bind(&addrs(&["127.0.0.1"]), 0).unwrap().remove(0);
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let (accepted, client) = tokio::join!(listener.accept(), client);
+        let (accepted, _) = accepted.unwrap();
+        assert!(Stream::from(accepted).is_local());
+        assert!(Stream::from(client.unwrap()).is_local());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_replaces_stale_file() {
+        let path = std::env::temp_dir().join(format!("redis-test-{}.sock", std::process::id()));
+        fs::write(&path, "stale").unwrap();
+        let store = Store::init(load_config(&[]).unwrap()).await.unwrap();
+        let executor = Executor::spawn(store);
+        let listener = path.to_str().unwrap().to_string();
+        tokio::spawn(listen_unix(listener, 0o700, executor));
+
+        let mut stream = None;
+        for _ in 0..100 {
+            if let Ok(connected) = UnixStream::connect(&path).await {
+                stream = Some(connected);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut stream = stream.expect("Unix socket never came up");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // Unix socket clients count as local, so protected mode lets
+        // them in without a password
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let mut reply = [0; 7];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,11 @@
 mod conn;
 mod listener;
+pub(crate) mod parser;
 pub(crate) mod replica;
+mod stream;
+pub(crate) mod tls;
 
 pub(crate) use conn::*;
 pub(crate) use listener::*;
+pub(crate) use parser::{Request, DEFAULT_MAX_BULK_LEN};
+pub(crate) use stream::Stream;
@@ -0,0 +1,322 @@
+use bytes::{Bytes, BytesMut};
+
+/// Default for `proto-max-bulk-len`, matching redis-server.
+pub(crate) const DEFAULT_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Largest number of arguments accepted in a single multibulk request.
+pub(crate) const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+/// Longest inline command or `*`/`$` header line accepted before its CRLF.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Malformed or oversized input. The connection can't be resynchronised
+/// after one of these, so it is closed once the error has been replied.
+#[derive(Debug, thiserror::Error)]
+#[error("Protocol error: {0}")]
+pub(crate) struct ProtocolError(&'static str);
+
+/// A complete client request, with arguments sliced out of the read buffer.
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) args: Vec<Bytes>,
+    /// Bytes the request occupied on the wire.
+    pub(crate) len: usize,
+}
+
+impl Request {
+    /// The command name, if it is valid UTF-8.
+    pub(crate) fn name(&self) -> Option<&str> {
+        std::str::from_utf8(self.args.first()?).ok()
+    }
+
+    pub(crate) fn into_args(self) -> anyhow::Result<Vec<String>> {
+        self.args
+            .into_iter()
+            .map(|arg| {
+                String::from_utf8(arg.to_vec())
+                    .map_err(|_| anyhow::anyhow!("Invalid UTF-8 in command argument"))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Waiting for the first byte of a request.
+    Start,
+    Inline,
+    /// Waiting for the `$<len>` header of the next argument.
+    BulkHeader {
+        remaining: usize,
+    },
+    /// Waiting for `len` bytes of argument data plus CRLF.
+    BulkData {
+        remaining: usize,
+        len: usize,
+    },
+}
+
+/// Resumable parser for client requests (multibulk arrays or inline
+/// commands). State survives across reads so every byte is examined once,
+/// no matter how many reads a request spans.
+#[derive(Debug)]
+pub(crate) struct RequestParser {
+    state: State,
+    /// Offset of the next unparsed byte of the current request.
+    pos: usize,
+    /// Offset up to which the buffer has already been searched for a line end.
+    scanned: usize,
+    /// Argument spans, relative to the start of the request.
+    args: Vec<(usize, usize)>,
+    max_bulk_len: usize,
+}
+
+impl RequestParser {
+    pub(crate) fn new(max_bulk_len: usize) -> Self {
+        Self {
+            state: State::Start,
+            pos: 0,
+            scanned: 0,
+            args: Vec::new(),
+            max_bulk_len,
+        }
+    }
+
+    pub(crate) fn set_max_bulk_len(&mut self, max_bulk_len: usize) {
+        self.max_bulk_len = max_bulk_len;
+    }
+
+    /// Bytes still missing before the argument being read is complete, so
+    /// the caller can grow the buffer once for large payloads.
+    pub(crate) fn pending(&self, buffered: usize) -> usize {
+        match self.state {
+            State::BulkData { len, .. } => (self.pos + len + 2).saturating_sub(buffered),
+            _ => 0,
+        }
+    }
+
+    /// Consumes one complete request from the front of `buf`, or returns
+    /// `None` once everything buffered has been parsed.
+    pub(crate) fn parse(&mut self, buf: &mut BytesMut) -> Result<Option<Request>, ProtocolError> {
+        loop {
+            match self.state {
+                State::Start => match buf.get(self.pos) {
+                    None => return Ok(None),
+                    Some(b'*') => {
+                        let count = match self.header(buf, "invalid multibulk length")? {
+                            Some(count) => count,
+                            None => return Ok(None),
+                        };
+                        if count > MAX_MULTIBULK_LEN as i64 {
+                            return Err(ProtocolError("invalid multibulk length"));
+                        }
+                        if count <= 0 {
+                            // Empty and null arrays carry no command
+                            self.finish(buf);
+                            continue;
+                        }
+                        self.args.reserve(count.min(1024) as usize);
+                        self.state = State::BulkHeader {
+                            remaining: count as usize,
+                        };
+                    }
+                    Some(_) => self.state = State::Inline,
+                },
+                State::Inline => {
+                    let end = match self.find(buf, b"\n") {
+                        Some(end) => end,
+                        None if buf.len() - self.pos > MAX_INLINE_LEN => {
+                            return Err(ProtocolError("too big inline request"))
+                        }
+                        None => return Ok(None),
+                    };
+
+                    let mut start = None;
+                    for (i, byte) in buf[self.pos..end].iter().enumerate() {
+                        let i = self.pos + i;
+                        match (byte.is_ascii_whitespace(), start) {
+                            (false, None) => start = Some(i),
+                            (true, Some(s)) => {
+                                self.args.push((s, i - s));
+                                start = None;
+                            }
+                            _ => {}
+                        }
+                    }
+                    if let Some(s) = start {
+                        self.args.push((s, end - s));
+                    }
+
+                    self.pos = end + 1;
+                    // Blank lines are ignored, as redis-server does
+                    if self.args.is_empty() {
+                        self.finish(buf);
+                        continue;
+                    }
+                    return Ok(Some(self.finish(buf)));
+                }
+                State::BulkHeader { remaining } => {
+                    match buf.get(self.pos) {
+                        None => return Ok(None),
+                        Some(b'$') => {}
+                        Some(_) => return Err(ProtocolError("expected '$'")),
+                    }
+                    let len = match self.header(buf, "invalid bulk length")? {
+                        Some(len) => len,
+                        None => return Ok(None),
+                    };
+                    if len < 0 || len as usize > self.max_bulk_len {
+                        return Err(ProtocolError("invalid bulk length"));
+                    }
+                    self.state = State::BulkData {
+                        remaining,
+                        len: len as usize,
+                    };
+                }
+                State::BulkData { remaining, len } => {
+                    if buf.len() < self.pos + len + 2 {
+                        return Ok(None);
+                    }
+                    if &buf[self.pos + len..self.pos + len + 2] != b"\r\n" {
+                        return Err(ProtocolError("expected CRLF after bulk data"));
+                    }
+
+                    self.args.push((self.pos, len));
+                    self.pos += len + 2;
+                    self.scanned = self.pos;
+
+                    if remaining > 1 {
+                        self.state = State::BulkHeader {
+                            remaining: remaining - 1,
+                        };
+                    } else {
+                        return Ok(Some(self.finish(buf)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parses a `*<n>` or `$<n>` header line at the current position.
+    fn header(
+        &mut self,
+        buf: &BytesMut,
+        invalid: &'static str,
+    ) -> Result<Option<i64>, ProtocolError> {
+        let end = match self.find(buf, b"\r\n") {
+            Some(end) => end,
+            None if buf.len() - self.pos > MAX_INLINE_LEN => {
+                return Err(ProtocolError("too big count string"))
+            }
+            None => return Ok(None),
+        };
+
+        let value = std::str::from_utf8(&buf[self.pos + 1..end])
+            .ok()
+            .and_then(|line| line.parse().ok())
+            .ok_or(ProtocolError(invalid))?;
+        self.pos = end + 2;
+        self.scanned = self.pos;
+
+        Ok(Some(value))
+    }
+
+    /// Finds `needle` after the current position, resuming the search where
+    /// the previous call left off.
+    fn find(&mut self, buf: &BytesMut, needle: &[u8]) -> Option<usize> {
+        let from = self.scanned.max(self.pos);
+        match buf[from..]
+            .windows(needle.len())
+            .position(|window| window == needle)
+        {
+            Some(i) => Some(from + i),
+            None => {
+                // A CRLF may straddle this read and the next one
+                self.scanned = buf.len().saturating_sub(needle.len() - 1).max(self.pos);
+                None
+            }
+        }
+    }
+
+    /// Splits the current request off the buffer and resets for the next one.
+    fn finish(&mut self, buf: &mut BytesMut) -> Request {
+        let frame = buf.split_to(self.pos).freeze();
+        let args = self
+            .args
+            .drain(..)
+            .map(|(start, len)| frame.slice(start..start + len))
+            .collect();
+
+        let len = self.pos;
+        self.state = State::Start;
+        self.pos = 0;
+        self.scanned = 0;
+
+        Request { args, len }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(parser: &mut RequestParser, buf: &mut BytesMut) -> Vec<Vec<Bytes>> {
+        let mut requests = vec![];
+        while let Some(request) = parser.parse(buf).unwrap() {
+            requests.push(request.args);
+        }
+        requests
+    }
+
+    #[test]
+    fn test_parse_pipelined_requests() {
+        let mut parser = RequestParser::new(DEFAULT_MAX_BULK_LEN);
+        let mut buf =
+            BytesMut::from("*1\r\n$4\r\nPING\r\nECHO  hi\r\n\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\n");
+
+        let requests = parse_all(&mut parser, &mut buf);
+
+        assert_eq!(
+            requests,
+            vec![
+                vec![Bytes::from("PING")],
+                vec![Bytes::from("ECHO"), Bytes::from("hi")],
+                vec![Bytes::from("GET"), Bytes::from("a")],
+            ]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_parse_resumes_across_reads() {
+        let input = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nva\r\nl\r\n";
+        let mut parser = RequestParser::new(DEFAULT_MAX_BULK_LEN);
+        let mut buf = BytesMut::new();
+
+        // Feed one byte at a time; only the final byte completes the request
+        for (i, byte) in input.iter().enumerate() {
+            buf.extend_from_slice(&[*byte]);
+            let request = parser.parse(&mut buf).unwrap();
+            if i + 1 < input.len() {
+                assert!(request.is_none());
+            } else {
+                let request = request.unwrap();
+                assert_eq!(request.len, input.len());
+                assert_eq!(request.args[2], Bytes::from("va\r\nl"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_enforces_limits() {
+        let mut parser = RequestParser::new(4);
+        let mut buf = BytesMut::from("*1\r\n$5\r\nhello\r\n");
+        assert!(parser.parse(&mut buf).is_err());
+
+        let mut parser = RequestParser::new(DEFAULT_MAX_BULK_LEN);
+        let mut buf = BytesMut::from(format!("*{}\r\n", MAX_MULTIBULK_LEN + 1).as_str());
+        assert!(parser.parse(&mut buf).is_err());
+
+        let mut parser = RequestParser::new(DEFAULT_MAX_BULK_LEN);
+        let mut buf = BytesMut::from("*1\r\n:1\r\n");
+        assert!(parser.parse(&mut buf).is_err());
+    }
+}
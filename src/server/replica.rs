@@ -1,43 +1,192 @@
-use std::sync::Arc;
+use anyhow::Context;
+use rustls_pki_types::ServerName;
+use tokio::net::TcpStream;
 
-use tokio::{net::TcpStream, sync::Mutex};
+use crate::{
+    handler::handle_replication,
+    rdb_parser::RdbParser,
+    server::{tls, Conn},
+    store::Executor,
+    Resp,
+};
 
-use crate::{handler::handle_replication, server::Conn, Store};
+pub(crate) async fn init(executor: &Executor) -> anyhow::Result<()> {
+    let (master_addr, masterauth, tls_config, port) = executor
+        .run(|store| {
+            (
+                store.config.master_addr().to_string(),
+                store.config.masterauth().map(str::to_string),
+                store
+                    .config
+                    .tls()
+                    .replication
+                    .then(|| store.config.tls().clone()),
+                store.config.port(),
+            )
+        })
+        .await?;
 
-pub(crate) async fn init(store: &Arc<Mutex<Store>>) -> anyhow::Result<()> {
-    let master_addr = store.lock().await.config.master_addr().to_string();
+    let stream = TcpStream::connect(&master_addr).await?;
+    let mut conn = match tls_config {
+        Some(tls_config) => {
+            let host = master_addr
+                .rsplit_once(':')
+                .map_or(master_addr.as_str(), |(host, _)| host);
+            let name = ServerName::try_from(host.to_string())
+                .with_context(|| format!("Invalid master host '{}'", host))?;
+            let stream = tls::connector(&tls_config)?
+                .connect(name, stream)
+                .await
+                .context("TLS handshake with master failed")?;
+            Conn::new(stream)
+        }
+        None => Conn::new(stream),
+    };
 
-    let stream = TcpStream::connect(master_addr).await?;
-    let mut conn = Conn::new(stream);
+    // PING command to master; a master with a password answers NOAUTH,
+    // which still shows it is reachable
+    conn.write_raw(b"*1\r\n$4\r\nping\r\n").await?;
+    match conn.read_frame().await? {
+        (_, Resp::SimpleError(err)) if !err.starts_with("NOAUTH") => {
+            anyhow::bail!("Master rejected replication handshake: {}", err)
+        }
+        _ => {}
+    }
 
-    // PING command to master
-    let msg = "*1\r\n$4\r\nping\r\n";
-    conn.write_raw(msg.as_bytes()).await?;
-    conn.read_frame().await?;
+    if let Some(password) = masterauth {
+        let msg = Resp::array(vec!["AUTH".to_string(), password]).encode();
+        handshake(&mut conn, &msg).await?;
+    }
 
     // REPL_CONF command to send listening_port and capa to master
-    let msg = "*3\r\n$8\r\nREPLCONF\r\n$14\r\nlistening-port\r\n$4\r\n6380\r\n";
-    conn.write_raw(msg.as_bytes()).await?;
-    conn.read_frame().await?;
+    let msg = Resp::array(vec![
+        "REPLCONF".to_string(),
+        "listening-port".to_string(),
+        port.to_string(),
+    ])
+    .encode();
+    handshake(&mut conn, &msg).await?;
 
     let msg = "*3\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n";
-    conn.write_raw(msg.as_bytes()).await?;
-    conn.read_frame().await?;
+    handshake(&mut conn, msg).await?;
 
-    // PSYNC command to master
-    // TODO: PSYNC and RDB file is read in single frame. Fix this.
+    // PSYNC command to master, answered by FULLRESYNC and an RDB snapshot
     let msg = "*3\r\n$5\r\npsync\r\n$1\r\n?\r\n$2\r\n-1\r\n";
-    conn.write_raw(msg.as_bytes()).await?;
-    conn.read_frame().await?;
-    conn.read_frame().await?;
-    // conn.clear_buffer();
+    let reply = handshake(&mut conn, msg).await?;
+    let (id, offset) = match &reply {
+        Resp::SimpleString(reply) => parse_fullresync(reply),
+        _ => None,
+    }
+    .with_context(|| format!("Unexpected reply to PSYNC: {:?}", reply))?;
+    let snapshot = conn.read_rdb().await?;
+    let rdb = RdbParser::from_reader(&snapshot[..]).parse().await?;
+    // The stream continues the master's history from its offset
+    executor
+        .run(move |store| {
+            store.replication.resync(&id, offset);
+            store.load_snapshot(rdb)
+        })
+        .await??;
 
-    let store = store.clone();
+    let executor = executor.clone();
     tokio::spawn(async move {
-        if let Err(e) = handle_replication(conn, store).await {
+        if let Err(e) = handle_replication(conn, executor).await {
             eprintln!("Failed to handle replication; Err = {:?}", e);
         }
     });
 
     Ok(())
 }
+
+/// Sends one handshake command, failing if the master replies with an error.
+async fn handshake(conn: &mut Conn, msg: &str) -> anyhow::Result<Resp> {
+    conn.write_raw(msg.as_bytes()).await?;
+    let (_, reply) = conn.read_frame().await?;
+
+    if let Resp::SimpleError(err) = &reply {
+        anyhow::bail!("Master rejected replication handshake: {}", err);
+    }
+
+    Ok(reply)
+}
+
+/// Replication ID and offset from a `FULLRESYNC <id> <offset>` reply.
+fn parse_fullresync(reply: &str) -> Option<(String, usize)> {
+    match reply.split(' ').collect::<Vec<_>>()[..] {
+        ["FULLRESYNC", id, offset] => Some((id.to_string(), offset.parse().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{
+        rdb_writer,
+        store::{load_config, Store},
+    };
+
+    /// Answers each handshake command in turn with `replies`, returning the
+    /// commands received.
+    async fn fake_master(listener: TcpListener, replies: Vec<Vec<u8>>) -> Vec<String> {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = vec![];
+        for reply in replies {
+            let mut buf = vec![0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            received.push(String::from_utf8_lossy(&buf[..n]).to_string());
+            stream.write_all(&reply).await.unwrap();
+        }
+        received
+    }
+
+    async fn replica_of(listener: &TcpListener, masterauth: &str) -> Executor {
+        let master = format!("127.0.0.1 {}", listener.local_addr().unwrap().port());
+        let args = ["--replicaof", &master, "--masterauth", masterauth].map(str::to_string);
+        Executor::spawn(Store::init(load_config(&args).unwrap()).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_handshake_sends_masterauth() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let executor = replica_of(&listener, "secret").await;
+        let rdb = rdb_writer::write(&[]);
+        let replies = vec![
+            b"-NOAUTH Authentication required.\r\n".to_vec(),
+            b"+OK\r\n".to_vec(),
+            b"+OK\r\n".to_vec(),
+            b"+OK\r\n".to_vec(),
+            [
+                format!("+FULLRESYNC {} 0\r\n${}\r\n", "a".repeat(40), rdb.len()).into_bytes(),
+                rdb,
+            ]
+            .concat(),
+        ];
+        let master = tokio::spawn(fake_master(listener, replies));
+
+        init(&executor).await.unwrap();
+        let received = master.await.unwrap();
+        assert_eq!(received[1], "*2\r\n$4\r\nAUTH\r\n$6\r\nsecret\r\n");
+        assert!(received[2].contains("REPLCONF"));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_fails_on_wrong_masterauth() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let executor = replica_of(&listener, "wrong").await;
+        let replies = vec![
+            b"-NOAUTH Authentication required.\r\n".to_vec(),
+            b"-WRONGPASS invalid username-password pair or user is disabled.\r\n".to_vec(),
+        ];
+        let master = tokio::spawn(fake_master(listener, replies));
+
+        let err = init(&executor).await.unwrap_err();
+        assert!(err.to_string().contains("WRONGPASS"), "{}", err);
+        master.await.unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::store::{TlsAuthClients, TlsConfig};
+
+/// Accepts TLS clients on the TLS port, verifying their certificates
+/// against the CA as `tls-auth-clients` requires.
+pub(crate) fn acceptor(tls: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+
+    let builder = match tls.auth_clients {
+        TlsAuthClients::No => builder.with_no_client_auth(),
+        auth_clients => {
+            let roots = Arc::new(load_roots(&tls.ca_cert_file)?);
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider());
+            let verifier = match auth_clients {
+                TlsAuthClients::Optional => verifier.allow_unauthenticated(),
+                _ => verifier,
+            };
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+    };
+    let config = builder
+        .with_single_cert(load_certs(&tls.cert_file)?, load_key(&tls.key_file)?)
+        .context("Invalid TLS certificate or key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Connects a replica to its master, presenting the server's own
+/// certificate in case the master authenticates clients.
+pub(crate) fn connector(tls: &TlsConfig) -> anyhow::Result<TlsConnector> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(load_roots(&tls.ca_cert_file)?);
+
+    let config = if tls.cert_file.is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        builder
+            .with_client_auth_cert(load_certs(&tls.cert_file)?, load_key(&tls.key_file)?)
+            .context("Invalid TLS certificate or key")?
+    };
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Common name in the subject of `cert`.
+pub(crate) fn common_name(cert: &CertificateDer) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let name = cert.subject().iter_common_name().next()?;
+
+    name.as_str().ok().map(str::to_string)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to load TLS certificates from '{}'", path))?;
+    anyhow::ensure!(!certs.is_empty(), "No TLS certificates in '{}'", path);
+
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .with_context(|| format!("Failed to load TLS private key from '{}'", path))
+}
+
+fn load_roots(path: &str) -> anyhow::Result<RootCertStore> {
+    anyhow::ensure!(!path.is_empty(), "tls-ca-cert-file must be set");
+
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid CA certificate in '{}'", path))?;
+    }
+
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls_pki_types::ServerName;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Writes a CA, a certificate for `localhost` and one for `common_name`
+    /// signed by it, returning the config of a server and of a client.
+    fn write_certs(dir: &Path, common_name: &str) -> (TlsConfig, TlsConfig) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let write = |name: &str, mut params: CertificateParams, usage| {
+            let key = KeyPair::generate().unwrap();
+            params.extended_key_usages = vec![usage];
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            let cert_file = dir.join(format!("{}.crt", name));
+            let key_file = dir.join(format!("{}.key", name));
+            fs::write(&cert_file, cert.pem()).unwrap();
+            fs::write(&key_file, key.serialize_pem()).unwrap();
+            (
+                cert_file.to_string_lossy().to_string(),
+                key_file.to_string_lossy().to_string(),
+            )
+        };
+        let server = write(
+            "server",
+            CertificateParams::new(vec!["localhost".to_string()]).unwrap(),
+            ExtendedKeyUsagePurpose::ServerAuth,
+        );
+        let mut client_params = CertificateParams::new(vec![]).unwrap();
+        client_params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        let client = write("client", client_params, ExtendedKeyUsagePurpose::ClientAuth);
+
+        let ca_cert_file = dir.join("ca.crt");
+        fs::write(&ca_cert_file, ca.pem()).unwrap();
+        let ca_cert_file = ca_cert_file.to_string_lossy().to_string();
+
+        let config = |(cert_file, key_file)| TlsConfig {
+            cert_file,
+            key_file,
+            ca_cert_file: ca_cert_file.clone(),
+            ..TlsConfig::default()
+        };
+        (config(server), config(client))
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_common_name() {
+        let dir = std::env::temp_dir().join(format!("redis-tls-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (server, client) = write_certs(&dir, "alice");
+
+        let acceptor = acceptor(&server).unwrap();
+        let connector = connector(&client).unwrap();
+        let (client_io, server_io) = tokio::io::duplex(4096);
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let (client, server) = tokio::join!(
+            connector.connect(name, client_io),
+            acceptor.accept(server_io)
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        let certs = server.get_ref().1.peer_certificates().unwrap();
+        assert_eq!(common_name(&certs[0]).as_deref(), Some("alice"));
+
+        client.write_all(b"+PING\r\n").await.unwrap();
+        client.flush().await.unwrap();
+        let mut buf = [0; 7];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"+PING\r\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
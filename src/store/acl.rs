@@ -0,0 +1,766 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use glob::Pattern;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    command::{Call, CommandSpec, COMMANDS, DEFAULT_USER},
+    RedisError,
+};
+
+/// Categories ACL rules can refer to as `@name`, as listed by ACL CAT.
+pub(crate) const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+/// Entries kept in the ACL LOG, as `acllog-max-len` defaults to.
+const ACL_LOG_MAX_LEN: usize = 128;
+/// Denials matching an entry updated within this window are counted in it
+/// instead of being logged again.
+const ACL_LOG_GROUPING: Duration = Duration::from_secs(60);
+
+const UNKNOWN_COMMAND: &str = "Unknown command or category name in ACL";
+
+#[derive(Debug, Clone)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct User {
+    pub(crate) name: String,
+    enabled: bool,
+    nopass: bool,
+    /// Hex encoded SHA-256 digests of the user's passwords.
+    passwords: Vec<String>,
+    /// Full names of the commands the user may run, such as `config|get`.
+    commands: Vec<&'static str>,
+    /// Command rules in the order they were applied, as ACL LIST shows them.
+    command_rules: String,
+    all_keys: bool,
+    keys: Vec<KeyPattern>,
+    all_channels: bool,
+    channels: Vec<String>,
+}
+
+impl User {
+    /// A user created by ACL SETUSER, which can't do anything yet.
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: vec![],
+            command_rules: String::from("-@all"),
+            all_keys: false,
+            keys: vec![],
+            all_channels: false,
+            channels: vec![],
+        }
+    }
+
+    /// The user connections start out as, allowed to do anything.
+    fn default_user() -> Self {
+        let mut user = Self::new(DEFAULT_USER);
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.set(rule).expect("default user rules are valid");
+        }
+
+        user
+    }
+
+    /// Applies one ACL SETUSER rule, failing with the reason it's invalid.
+    pub(crate) fn set(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => {
+                self.all_keys = true;
+                self.keys.clear();
+            }
+            "resetkeys" => {
+                self.all_keys = false;
+                self.keys.clear();
+            }
+            "allchannels" => {
+                self.all_channels = true;
+                self.channels.clear();
+            }
+            "resetchannels" => {
+                self.all_channels = false;
+                self.channels.clear();
+            }
+            "allcommands" => self.set_command("@all", true)?,
+            "nocommands" => self.set_command("@all", false)?,
+            "reset" => *self = User::new(&self.name),
+            _ => return self.set_pattern(rule),
+        }
+
+        Ok(())
+    }
+
+    /// Applies the rules that take an argument, such as `>password`.
+    fn set_pattern(&mut self, rule: &str) -> Result<(), String> {
+        let mut chars = rule.chars();
+        let prefix = chars.next().ok_or("Syntax error")?;
+        let value = chars.as_str();
+
+        match prefix {
+            '>' => self.add_password(hash_password(value)),
+            '#' => {
+                let hash = validate_hash(value)?;
+                self.add_password(hash);
+            }
+            '<' => self.remove_password(&hash_password(value))?,
+            '!' => self.remove_password(&validate_hash(value)?)?,
+            '~' => self.add_key_pattern(value, true, true)?,
+            '%' => {
+                let (perms, pattern) = value.split_once('~').ok_or("Syntax error")?;
+                let mut read = false;
+                let mut write = false;
+                for perm in perms.chars() {
+                    match perm.to_ascii_uppercase() {
+                        'R' => read = true,
+                        'W' => write = true,
+                        _ => return Err("Syntax error".to_string()),
+                    }
+                }
+                if !read && !write {
+                    return Err("Syntax error".to_string());
+                }
+                self.add_key_pattern(pattern, read, write)?;
+            }
+            '&' => {
+                if self.all_channels {
+                    return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels".to_string());
+                }
+                if value == "*" {
+                    self.all_channels = true;
+                    self.channels.clear();
+                } else if !self.channels.iter().any(|channel| channel == value) {
+                    self.channels.push(value.to_string());
+                }
+            }
+            '+' => self.set_command(&value.to_lowercase(), true)?,
+            '-' => self.set_command(&value.to_lowercase(), false)?,
+            _ => return Err("Syntax error".to_string()),
+        }
+
+        Ok(())
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<(), String> {
+        let len = self.passwords.len();
+        self.passwords.retain(|password| password != hash);
+        if self.passwords.len() == len {
+            return Err(
+                "The password you are trying to remove from the user does not exist".to_string(),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn add_key_pattern(&mut self, pattern: &str, read: bool, write: bool) -> Result<(), String> {
+        if self.all_keys {
+            return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns".to_string());
+        }
+        if pattern == "*" && read && write {
+            self.all_keys = true;
+            self.keys.clear();
+            return Ok(());
+        }
+
+        match self.keys.iter_mut().find(|key| key.pattern == pattern) {
+            Some(key) => {
+                key.read |= read;
+                key.write |= write;
+            }
+            None => self.keys.push(KeyPattern {
+                pattern: pattern.to_string(),
+                read,
+                write,
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Allows or denies a command, a subcommand such as `config|get`, or a
+    /// category such as `@read`.
+    fn set_command(&mut self, name: &str, allow: bool) -> Result<(), String> {
+        let rule = format!("{}{}", if allow { '+' } else { '-' }, name);
+        let specs: Vec<&'static CommandSpec> = match name.strip_prefix('@') {
+            Some("all") => {
+                self.commands.clear();
+                if allow {
+                    self.commands = all_specs().map(|spec| spec.name).collect();
+                }
+                self.command_rules = rule;
+                return Ok(());
+            }
+            Some(category) => {
+                if !CATEGORIES.contains(&category) {
+                    return Err(UNKNOWN_COMMAND.to_string());
+                }
+                all_specs()
+                    .filter(|spec| spec.categories.iter().any(|c| c[1..] == *category))
+                    .collect()
+            }
+            None => {
+                let (command, sub) = match name.split_once('|') {
+                    Some((command, sub)) => (command, Some(sub)),
+                    None => (name, None),
+                };
+                let spec = crate::command::lookup(command).ok_or(UNKNOWN_COMMAND)?;
+                match sub {
+                    Some(_) => vec![spec
+                        .subcommands
+                        .iter()
+                        .find(|sub| sub.name == name)
+                        .ok_or(UNKNOWN_COMMAND)?],
+                    None => std::iter::once(spec)
+                        .chain(spec.subcommands.iter())
+                        .collect(),
+                }
+            }
+        };
+
+        for spec in specs {
+            self.commands.retain(|command| *command != spec.name);
+            if allow {
+                self.commands.push(spec.name);
+            }
+        }
+        self.command_rules.push(' ');
+        self.command_rules.push_str(&rule);
+
+        Ok(())
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn is_nopass(&self) -> bool {
+        self.nopass
+    }
+
+    pub(crate) fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    pub(crate) fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+
+        flags
+    }
+
+    pub(crate) fn command_rules(&self) -> &str {
+        &self.command_rules
+    }
+
+    /// Key patterns, as ACL GETUSER shows them.
+    pub(crate) fn key_rules(&self) -> String {
+        if self.all_keys {
+            return String::from("~*");
+        }
+
+        self.keys
+            .iter()
+            .map(|key| match (key.read, key.write) {
+                (true, true) => format!("~{}", key.pattern),
+                (true, false) => format!("%R~{}", key.pattern),
+                _ => format!("%W~{}", key.pattern),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Channel patterns, as ACL GETUSER shows them.
+    pub(crate) fn channel_rules(&self) -> String {
+        if self.all_channels {
+            return String::from("&*");
+        }
+
+        self.channels
+            .iter()
+            .map(|channel| format!("&{}", channel))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The user as a line of ACL LIST or of an ACL file.
+    pub(crate) fn describe(&self) -> String {
+        let mut rules: Vec<String> = vec![format!("user {}", self.name)];
+        rules.extend(self.flags().into_iter().map(str::to_string));
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        let keys = self.key_rules();
+        if !keys.is_empty() {
+            rules.push(keys);
+        }
+        if !self.all_channels {
+            rules.push(String::from("resetchannels"));
+        }
+        let channels = self.channel_rules();
+        if !channels.is_empty() {
+            rules.push(channels);
+        }
+        rules.push(self.command_rules.clone());
+
+        rules.join(" ")
+    }
+
+    fn check_password(&self, password: &str) -> bool {
+        if self.nopass {
+            return true;
+        }
+
+        let hash = hash_password(password);
+        self.passwords
+            .iter()
+            .any(|expected| constant_time_eq(expected.as_bytes(), hash.as_bytes()))
+    }
+
+    /// Checks the user may run `call`, including on the keys and channels it
+    /// touches.
+    pub(crate) fn check(&self, call: &Call) -> Result<(), Denied> {
+        // AUTH and HELLO are how users switch, so anyone can run them
+        if call.spec.has_flag("no_auth") {
+            return Ok(());
+        }
+        if !self.commands.contains(&call.name) {
+            return Err(Denied::Command(call.name.to_string()));
+        }
+
+        let write = call.spec.has_flag("write");
+        if let Some(key) = call
+            .keys()
+            .into_iter()
+            .find(|key| !self.can_access_key(key, write))
+        {
+            return Err(Denied::Key(key.to_string()));
+        }
+        if let Some((channel, _)) = call
+            .channels()
+            .into_iter()
+            .find(|(channel, is_pattern)| !self.can_access_channel(channel, *is_pattern))
+        {
+            return Err(Denied::Channel(channel.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn can_access_key(&self, key: &str, write: bool) -> bool {
+        self.all_keys
+            || self.keys.iter().any(|pattern| {
+                (if write { pattern.write } else { pattern.read })
+                    && glob_match(&pattern.pattern, key)
+            })
+    }
+
+    /// PSUBSCRIBE patterns must be granted as they are, while channels can
+    /// match any granted pattern.
+    fn can_access_channel(&self, channel: &str, is_pattern: bool) -> bool {
+        self.all_channels
+            || self.channels.iter().any(|pattern| {
+                if is_pattern {
+                    pattern == channel
+                } else {
+                    glob_match(pattern, channel)
+                }
+            })
+    }
+}
+
+/// Why a user was refused a command.
+#[derive(Debug)]
+pub(crate) enum Denied {
+    Command(String),
+    Key(String),
+    Channel(String),
+}
+
+impl Denied {
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            Denied::Command(_) => "command",
+            Denied::Key(_) => "key",
+            Denied::Channel(_) => "channel",
+        }
+    }
+
+    pub(crate) fn object(&self) -> &str {
+        match self {
+            Denied::Command(name) | Denied::Key(name) | Denied::Channel(name) => name,
+        }
+    }
+
+    /// Error replied to the client, which doesn't name the key or channel.
+    pub(crate) fn error(&self, username: &str) -> RedisError {
+        let message = match self {
+            Denied::Command(_) => self.describe(username),
+            Denied::Key(_) => String::from("No permissions to access a key"),
+            Denied::Channel(_) => String::from("No permissions to access a channel"),
+        };
+
+        RedisError::NoPerm(message)
+    }
+
+    /// Explanation given by ACL DRYRUN.
+    pub(crate) fn describe(&self, username: &str) -> String {
+        match self {
+            Denied::Command(name) => format!(
+                "User {} has no permissions to run the '{}' command",
+                username, name
+            ),
+            Denied::Key(key) => format!(
+                "User {} has no permissions to access the '{}' key",
+                username, key
+            ),
+            Denied::Channel(channel) => format!(
+                "User {} has no permissions to access the '{}' channel",
+                username, channel
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct LogEntry {
+    pub(crate) count: usize,
+    pub(crate) reason: &'static str,
+    /// Whether the command ran on its own (`toplevel`) or in a transaction.
+    pub(crate) context: &'static str,
+    pub(crate) object: String,
+    pub(crate) username: String,
+    pub(crate) client_info: String,
+    pub(crate) entry_id: u64,
+    pub(crate) created: SystemTime,
+    pub(crate) updated: SystemTime,
+}
+
+/// Users and the log of the commands and logins they were refused.
+#[derive(Debug)]
+pub(crate) struct Acl {
+    users: BTreeMap<String, User>,
+    log: VecDeque<LogEntry>,
+    next_entry_id: u64,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self {
+            users: BTreeMap::from([(DEFAULT_USER.to_string(), User::default_user())]),
+            log: VecDeque::new(),
+            next_entry_id: 0,
+        }
+    }
+}
+
+impl Acl {
+    pub(crate) fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    pub(crate) fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Whether new connections are logged in as the default user without
+    /// calling AUTH.
+    pub(crate) fn auto_login(&self) -> bool {
+        self.user(DEFAULT_USER)
+            .is_some_and(|user| user.enabled && user.nopass)
+    }
+
+    pub(crate) fn authenticate(&self, username: &str, password: &str) -> bool {
+        self.user(username)
+            .is_some_and(|user| user.enabled && user.check_password(password))
+    }
+
+    /// Creates or updates a user. Either every rule applies or none do.
+    pub(crate) fn set_user(&mut self, name: &str, rules: &[String]) -> anyhow::Result<()> {
+        if name.contains(' ') || name.contains('\0') {
+            anyhow::bail!("Usernames can't contain spaces or null characters");
+        }
+
+        let mut user = self
+            .users
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.set(rule).map_err(|reason| {
+                anyhow::anyhow!("Error in ACL SETUSER modifier '{}': {}", rule, reason)
+            })?;
+        }
+        self.users.insert(name.to_string(), user);
+
+        Ok(())
+    }
+
+    /// Removes a user, returning whether it existed.
+    pub(crate) fn delete_user(&mut self, name: &str) -> anyhow::Result<bool> {
+        if name == DEFAULT_USER {
+            anyhow::bail!("The '{}' user cannot be removed", DEFAULT_USER);
+        }
+
+        Ok(self.users.remove(name).is_some())
+    }
+
+    /// Applies `requirepass`, which is the default user's password.
+    pub(crate) fn set_requirepass(&mut self, password: Option<&str>) {
+        let user = self
+            .users
+            .entry(DEFAULT_USER.to_string())
+            .or_insert_with(User::default_user);
+        user.passwords.clear();
+        user.nopass = password.is_none();
+        if let Some(password) = password {
+            user.passwords.push(hash_password(password));
+        }
+    }
+
+    /// Replaces every user with those defined in an ACL file. Nothing
+    /// changes if any line is invalid.
+    pub(crate) fn load(&mut self, path: &str) -> anyhow::Result<()> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Error loading ACLs, opening file '{}'", path))?;
+
+        let mut users = BTreeMap::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let user = match (words.next(), words.next()) {
+                (Some("user"), Some(name)) if !users.contains_key(name) => {
+                    let mut user = User::new(name);
+                    words.try_fold((), |_, rule| user.set(rule)).map(|_| user)
+                }
+                (Some("user"), Some(name)) => Err(format!("Duplicate user '{}' found", name)),
+                _ => Err(String::from("line should start with user keyword")),
+            };
+            let user =
+                user.map_err(|reason| anyhow::anyhow!("{}:{}: {}. ", path, i + 1, reason))?;
+            users.insert(user.name.clone(), user);
+        }
+        users
+            .entry(DEFAULT_USER.to_string())
+            .or_insert_with(User::default_user);
+        self.users = users;
+
+        Ok(())
+    }
+
+    /// Writes every user to an ACL file, replacing it only once complete.
+    pub(crate) fn save(&self, path: &str) -> anyhow::Result<()> {
+        let contents: String = self
+            .users
+            .values()
+            .map(|user| user.describe() + "\n")
+            .collect();
+
+        let temp = format!("{}.tmp", path);
+        fs::write(&temp, contents)
+            .and_then(|_| fs::rename(&temp, path))
+            .with_context(|| format!("Error saving ACLs to file '{}'", path))
+    }
+
+    pub(crate) fn log(
+        &mut self,
+        reason: &'static str,
+        context: &'static str,
+        object: &str,
+        username: &str,
+        client_info: String,
+    ) {
+        let now = SystemTime::now();
+        let similar = self.log.iter().position(|entry| {
+            entry.reason == reason
+                && entry.context == context
+                && entry.object == object
+                && entry.username == username
+                && now
+                    .duration_since(entry.updated)
+                    .is_ok_and(|age| age < ACL_LOG_GROUPING)
+        });
+        if let Some(mut entry) = similar.and_then(|i| self.log.remove(i)) {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = client_info;
+            self.log.push_front(entry);
+            return;
+        }
+
+        self.log.push_front(LogEntry {
+            count: 1,
+            reason,
+            context,
+            object: object.to_string(),
+            username: username.to_string(),
+            client_info,
+            entry_id: self.next_entry_id,
+            created: now,
+            updated: now,
+        });
+        self.next_entry_id += 1;
+        self.log.truncate(ACL_LOG_MAX_LEN);
+    }
+
+    /// Logged denials, most recent first.
+    pub(crate) fn log_entries(&self) -> impl Iterator<Item = &LogEntry> {
+        self.log.iter()
+    }
+
+    pub(crate) fn reset_log(&mut self) {
+        self.log.clear();
+    }
+}
+
+/// Every command and subcommand in the command table.
+fn all_specs() -> impl Iterator<Item = &'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .flat_map(|spec| std::iter::once(spec).chain(spec.subcommands.iter()))
+}
+
+fn hash_password(password: &str) -> String {
+    hex::encode(Sha256::digest(password.as_bytes()))
+}
+
+fn validate_hash(hash: &str) -> Result<String, String> {
+    if hash.len() != 64 || !hash.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')) {
+        return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+    }
+
+    Ok(hash.to_string())
+}
+
+fn glob_match(pattern: &str, value: &str) -> bool {
+    Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(value))
+}
+
+/// Compares secrets without exiting early, so timing doesn't reveal how
+/// much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(args: &[&str]) -> Call {
+        Call::parse(args.iter().map(|arg| arg.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_user_permissions() {
+        let mut acl = Acl::default();
+        let rules: Vec<String> = [
+            "on",
+            ">secret",
+            "+@read",
+            "+set",
+            "%R~cache:*",
+            "~app:*",
+            "&news",
+        ]
+        .iter()
+        .map(|rule| rule.to_string())
+        .collect();
+        acl.set_user("alice", &rules).unwrap();
+        let user = acl.user("alice").unwrap();
+
+        assert!(acl.authenticate("alice", "secret"));
+        assert!(!acl.authenticate("alice", "wrong"));
+        assert_eq!(
+            user.describe(),
+            format!(
+                "user alice on #{} %R~cache:* ~app:* resetchannels &news -@all +@read +set",
+                hash_password("secret")
+            )
+        );
+
+        assert!(user.check(&call(&["get", "cache:1"])).is_ok());
+        assert!(user.check(&call(&["set", "app:1", "v"])).is_ok());
+        assert!(matches!(
+            user.check(&call(&["set", "cache:1", "v"])),
+            Err(Denied::Key(key)) if key == "cache:1"
+        ));
+        assert!(matches!(
+            user.check(&call(&["config", "GET", "dir"])),
+            Err(Denied::Command(name)) if name == "config|get"
+        ));
+        assert!(user.check(&call(&["auth", "secret"])).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_rules_leave_user_unchanged() {
+        let mut acl = Acl::default();
+        let rules = vec!["on".to_string(), "+nosuchcommand".to_string()];
+        let err = acl.set_user("bob", &rules).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Error in ACL SETUSER modifier '+nosuchcommand': Unknown command or category name in ACL"
+        );
+        assert!(acl.user("bob").is_none());
+        assert!(acl.delete_user(DEFAULT_USER).is_err());
+    }
+}
@@ -1,8 +1,220 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::server::DEFAULT_MAX_BULK_LEN;
+
+mod params;
+
+pub(crate) use params::{lookup, Format, Param, PARAMS};
+
 #[derive(Debug)]
 pub(crate) struct Config {
     dir: String,
     db_file_name: String,
-    replication: Replication,
+    databases: usize,
+    notify_keyspace_events: u32,
+    proto_max_bulk_len: usize,
+    output_buffer_limits: OutputBufferLimits,
+    /// Password of the default user; connections must AUTH when it is set.
+    requirepass: Option<String>,
+    /// Password a replica authenticates to its master with.
+    masterauth: Option<String>,
+    /// File ACL LOAD and ACL SAVE read and write users from.
+    aclfile: Option<String>,
+    tls: TlsConfig,
+    /// Addresses listened on, as given to the `bind` option.
+    bind: Vec<String>,
+    /// Refuses clients from other hosts while the default user has no
+    /// password.
+    protected_mode: bool,
+    /// TCP port clients connect to; zero disables the TCP listener.
+    port: u16,
+    unixsocket: Option<String>,
+    /// File mode of the Unix socket; zero leaves the umask's.
+    unixsocketperm: u32,
+    /// Snapshot points of the `save` option, as (seconds, changes) pairs.
+    save: Vec<(u64, u64)>,
+    /// Master this server replicates, as set by `replicaof`.
+    role: Role,
+    /// The redis.conf the server started with, which CONFIG REWRITE updates.
+    config_file: Option<String>,
+}
+
+/// Certificates and options for the TLS port and for TLS replication.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsConfig {
+    /// Port TLS clients connect to; zero disables the TLS listener.
+    pub(crate) port: u16,
+    pub(crate) cert_file: String,
+    pub(crate) key_file: String,
+    /// CA bundle client certificates and the master's certificate are
+    /// verified against.
+    pub(crate) ca_cert_file: String,
+    pub(crate) auth_clients: TlsAuthClients,
+    /// Whether a client certificate logs in as the ACL user its common
+    /// name matches, as set by `tls-auth-clients-user CN`.
+    pub(crate) auth_clients_user: bool,
+    /// Whether a replica connects to its master over TLS.
+    pub(crate) replication: bool,
+}
+
+/// Whether TLS clients must present a certificate signed by the CA.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum TlsAuthClients {
+    #[default]
+    Yes,
+    No,
+    Optional,
+}
+
+/// Client classes that `client-output-buffer-limit` is configured for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ClientClass {
+    Normal,
+    Replica,
+    Pubsub,
+}
+
+/// A client is disconnected once its pending replies exceed `hard` bytes,
+/// or stay above `soft` bytes for `soft_seconds`. Zero disables a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct OutputBufferLimit {
+    pub(crate) hard: usize,
+    pub(crate) soft: usize,
+    pub(crate) soft_seconds: u64,
+}
+
+impl OutputBufferLimit {
+    /// Whether `pending` bytes of output break the limit, tracking in
+    /// `soft_limit_since` how long the soft limit has been exceeded.
+    pub(crate) fn exceeded(&self, pending: usize, soft_limit_since: &mut Option<Instant>) -> bool {
+        if self.hard > 0 && pending >= self.hard {
+            return true;
+        }
+
+        if self.soft > 0 && pending >= self.soft {
+            let since = *soft_limit_since.get_or_insert_with(Instant::now);
+            return since.elapsed() >= Duration::from_secs(self.soft_seconds);
+        }
+
+        *soft_limit_since = None;
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct OutputBufferLimits {
+    normal: OutputBufferLimit,
+    replica: OutputBufferLimit,
+    pubsub: OutputBufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        Self {
+            normal: OutputBufferLimit::default(),
+            replica: OutputBufferLimit {
+                hard: 256 * 1024 * 1024,
+                soft: 64 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+            pubsub: OutputBufferLimit {
+                hard: 32 * 1024 * 1024,
+                soft: 8 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+        }
+    }
+}
+
+impl OutputBufferLimits {
+    /// Parses `<class> <hard> <soft> <seconds>` groups, as accepted by
+    /// redis-server. Classes that aren't mentioned keep their current limit.
+    pub(crate) fn parse(&self, value: &str) -> anyhow::Result<Self> {
+        let words: Vec<&str> = value.split_whitespace().collect();
+        anyhow::ensure!(
+            !words.is_empty() && words.len().is_multiple_of(4),
+            "Wrong number of arguments in buffer limit configuration."
+        );
+
+        let mut limits = *self;
+        for group in words.chunks(4) {
+            let limit = OutputBufferLimit {
+                hard: parse_memory(group[1])?,
+                soft: parse_memory(group[2])?,
+                soft_seconds: group[3]
+                    .parse()
+                    .with_context(|| format!("Invalid soft limit seconds '{}'", group[3]))?,
+            };
+            match group[0].to_lowercase().as_str() {
+                "normal" => limits.normal = limit,
+                "replica" | "slave" => limits.replica = limit,
+                "pubsub" => limits.pubsub = limit,
+                class => anyhow::bail!(
+                    "Invalid client class specified in buffer limit configuration: {}",
+                    class
+                ),
+            }
+        }
+
+        Ok(limits)
+    }
+
+    pub(crate) fn get(&self, class: ClientClass) -> OutputBufferLimit {
+        match class {
+            ClientClass::Normal => self.normal,
+            ClientClass::Replica => self.replica,
+            ClientClass::Pubsub => self.pubsub,
+        }
+    }
+}
+
+impl fmt::Display for OutputBufferLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let classes = [
+            ("normal", self.normal),
+            ("slave", self.replica),
+            ("pubsub", self.pubsub),
+        ];
+        let groups: Vec<String> = classes
+            .iter()
+            .map(|(name, limit)| {
+                format!(
+                    "{} {} {} {}",
+                    name, limit.hard, limit.soft, limit.soft_seconds
+                )
+            })
+            .collect();
+
+        write!(f, "{}", groups.join(" "))
+    }
+}
+
+/// Parses a byte count with an optional unit: `k`/`m`/`g` are powers of
+/// 1000, `kb`/`mb`/`gb` powers of 1024.
+pub(crate) fn parse_memory(value: &str) -> anyhow::Result<usize> {
+    let lower = value.to_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: usize = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => anyhow::bail!("Invalid memory value '{}'", value),
+    };
+
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .with_context(|| format!("Invalid memory value '{}'", value))
 }
 
 #[derive(Debug, PartialEq)]
@@ -11,32 +223,45 @@ pub(crate) enum Role {
     Replica { master_addr: String },
 }
 
-#[derive(Debug)]
-pub(crate) struct Replication {
-    pub(crate) role: Role,
-    pub(crate) id: String,
-    pub(crate) offset: u64,
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dir: String::new(),
+            db_file_name: String::new(),
+            databases: 16,
+            notify_keyspace_events: 0,
+            proto_max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            output_buffer_limits: OutputBufferLimits::default(),
+            requirepass: None,
+            masterauth: None,
+            aclfile: None,
+            tls: TlsConfig::default(),
+            bind: vec!["127.0.0.1".to_string(), "-::1".to_string()],
+            protected_mode: true,
+            port: 6379,
+            unixsocket: None,
+            unixsocketperm: 0,
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            role: Role::Master,
+            config_file: None,
+        }
+    }
 }
 
 impl Config {
-    pub(crate) fn new(dir: &str, db_file_name: &str, replica_of: &str) -> Self {
-        let role = if replica_of.is_empty() {
-            Role::Master
-        } else {
-            Role::Replica {
-                master_addr: replica_of.split(" ").collect::<Vec<_>>().join(":"),
-            }
+    /// Applies a redis.conf directive, read from the config file or given
+    /// as a `--name value` argument.
+    pub(crate) fn apply(&mut self, name: &str, args: &[String]) -> anyhow::Result<()> {
+        let param = lookup(name).context("Bad directive or wrong number of arguments")?;
+        let value = match (param.format, args) {
+            (Format::Single, [value]) => value.clone(),
+            (Format::Single, _) => anyhow::bail!("wrong number of arguments"),
+            // Multi-word values can be quoted as a single argument, like
+            // `--replicaof "127.0.0.1 6379"`
+            _ => args.join(" "),
         };
 
-        Self {
-            dir: dir.to_string(),
-            db_file_name: db_file_name.to_string(),
-            replication: Replication {
-                id: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".into(),
-                role,
-                offset: 0,
-            },
-        }
+        param.set(self, &value)
     }
 
     pub(crate) fn dir(&self) -> &str {
@@ -47,16 +272,68 @@ impl Config {
         self.db_file_name.as_str()
     }
 
+    pub(crate) fn databases(&self) -> usize {
+        self.databases
+    }
+
+    pub(crate) fn notify_keyspace_events(&self) -> u32 {
+        self.notify_keyspace_events
+    }
+
+    pub(crate) fn proto_max_bulk_len(&self) -> usize {
+        self.proto_max_bulk_len
+    }
+
+    pub(crate) fn output_buffer_limits(&self) -> OutputBufferLimits {
+        self.output_buffer_limits
+    }
+
+    pub(crate) fn requirepass(&self) -> Option<&str> {
+        self.requirepass.as_deref()
+    }
+
+    pub(crate) fn masterauth(&self) -> Option<&str> {
+        self.masterauth.as_deref()
+    }
+
+    pub(crate) fn aclfile(&self) -> Option<&str> {
+        self.aclfile.as_deref()
+    }
+
+    pub(crate) fn tls(&self) -> &TlsConfig {
+        &self.tls
+    }
+
+    pub(crate) fn bind(&self) -> &[String] {
+        &self.bind
+    }
+
+    pub(crate) fn protected_mode(&self) -> bool {
+        self.protected_mode
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn unixsocket(&self) -> Option<&str> {
+        self.unixsocket.as_deref()
+    }
+
+    pub(crate) fn unixsocketperm(&self) -> u32 {
+        self.unixsocketperm
+    }
+
     pub(crate) fn is_master(&self) -> bool {
-        matches!(self.replication.role, Role::Master)
+        matches!(self.role, Role::Master)
     }
 
     pub(crate) fn is_replica(&self) -> bool {
-        matches!(self.replication.role, Role::Replica { .. })
+        matches!(self.role, Role::Replica { .. })
     }
 
     pub(crate) fn master_addr(&self) -> &str {
-        match &self.replication.role {
+        match &self.role {
             Role::Master => {
                 panic!("master_addr called on master role");
             }
@@ -64,7 +341,46 @@ impl Config {
         }
     }
 
-    pub(crate) fn get_repl_info(&self) -> &Replication {
-        &self.replication
+    pub(crate) fn config_file(&self) -> Option<&str> {
+        self.config_file.as_deref()
+    }
+
+    pub(crate) fn set_config_file(&mut self, path: &str) {
+        self.config_file = Some(path.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_buffer_limits() {
+        let limits = OutputBufferLimits::default()
+            .parse("normal 1mb 512kb 10 pubsub 0 0 0")
+            .unwrap();
+
+        assert_eq!(
+            limits.get(ClientClass::Normal),
+            OutputBufferLimit {
+                hard: 1024 * 1024,
+                soft: 512 * 1024,
+                soft_seconds: 10,
+            }
+        );
+        assert_eq!(
+            limits.get(ClientClass::Pubsub),
+            OutputBufferLimit::default()
+        );
+        assert_eq!(
+            limits.get(ClientClass::Replica),
+            OutputBufferLimits::default().get(ClientClass::Replica)
+        );
+        assert_eq!(
+            OutputBufferLimits::default().to_string(),
+            "normal 0 0 0 slave 268435456 67108864 60 pubsub 33554432 8388608 60"
+        );
+        assert!(limits.parse("normal 1 2").is_err());
+        assert!(limits.parse("other 1 2 3").is_err());
     }
 }
@@ -0,0 +1,604 @@
+use std::path::Path;
+
+use super::{parse_memory, Config, Role, TlsAuthClients};
+use crate::store::{notify, Store};
+
+/// How a parameter's value is parsed, checked and shown.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Kind {
+    Bool,
+    Int {
+        min: i64,
+        max: i64,
+    },
+    /// A byte count, which may carry a unit like `512mb`.
+    Memory {
+        min: i64,
+        max: i64,
+    },
+    String,
+    Enum(&'static [&'static str]),
+    /// A value with its own syntax, parsed by the setter.
+    Special,
+}
+
+/// A parameter value, as its kind parses it.
+#[derive(Debug)]
+pub(crate) enum Value {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+impl Value {
+    fn bool(self) -> bool {
+        match self {
+            Value::Bool(value) => value,
+            value => unreachable!("expected a bool, got {:?}", value),
+        }
+    }
+
+    fn int(self) -> i64 {
+        match self {
+            Value::Int(value) => value,
+            value => unreachable!("expected an integer, got {:?}", value),
+        }
+    }
+
+    fn string(self) -> String {
+        match self {
+            Value::String(value) => value,
+            value => unreachable!("expected a string, got {:?}", value),
+        }
+    }
+}
+
+/// How a value is spelled in redis.conf.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    /// One argument, quoted if needed.
+    Single,
+    /// Any number of arguments on one line, like `bind`.
+    Words,
+    /// Groups of this many arguments, one line each, like `save 900 1`.
+    /// Repeated lines add to the value instead of replacing it.
+    Lines(usize),
+}
+
+/// A configuration parameter: its type, whether CONFIG SET may change it,
+/// and how it is read from and written to the config.
+#[derive(Debug)]
+pub(crate) struct Param {
+    pub(crate) name: &'static str,
+    pub(crate) alias: Option<&'static str>,
+    pub(crate) kind: Kind,
+    pub(crate) format: Format,
+    /// Whether CONFIG SET may change the value, or only the config file
+    /// and options at startup.
+    pub(crate) mutable: bool,
+    get: fn(&Config) -> Value,
+    set: fn(&mut Config, Value) -> anyhow::Result<()>,
+    /// Applies a value CONFIG SET changed to the rest of the server.
+    pub(crate) hook: Option<fn(&mut Store) -> anyhow::Result<()>>,
+}
+
+const fn param(
+    name: &'static str,
+    kind: Kind,
+    get: fn(&Config) -> Value,
+    set: fn(&mut Config, Value) -> anyhow::Result<()>,
+) -> Param {
+    Param {
+        name,
+        alias: None,
+        kind,
+        format: Format::Single,
+        mutable: true,
+        get,
+        set,
+        hook: None,
+    }
+}
+
+impl Param {
+    const fn alias(self, alias: &'static str) -> Self {
+        Param {
+            alias: Some(alias),
+            ..self
+        }
+    }
+
+    const fn format(self, format: Format) -> Self {
+        Param { format, ..self }
+    }
+
+    const fn immutable(self) -> Self {
+        Param {
+            mutable: false,
+            ..self
+        }
+    }
+
+    const fn hook(self, hook: fn(&mut Store) -> anyhow::Result<()>) -> Self {
+        Param {
+            hook: Some(hook),
+            ..self
+        }
+    }
+
+    /// Whether `name` is this parameter's name or alias.
+    pub(crate) fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .alias
+                .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn get(&self, config: &Config) -> String {
+        match (self.get)(config) {
+            Value::Bool(value) => String::from(if value { "yes" } else { "no" }),
+            Value::Int(value) => value.to_string(),
+            Value::String(value) => value,
+        }
+    }
+
+    /// Parses `value` as the parameter's kind and sets it.
+    pub(crate) fn set(&self, config: &mut Config, value: &str) -> anyhow::Result<()> {
+        let value = match self.kind {
+            Kind::Bool => Value::Bool(match value.to_lowercase().as_str() {
+                "yes" => true,
+                "no" => false,
+                _ => anyhow::bail!("argument must be 'yes' or 'no'"),
+            }),
+            Kind::Int { min, max } => {
+                let value = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("argument couldn't be parsed into an integer"))?;
+                Value::Int(check_range(value, min, max)?)
+            }
+            Kind::Memory { min, max } => {
+                let value = parse_memory(value)
+                    .ok()
+                    .and_then(|value| i64::try_from(value).ok())
+                    .ok_or_else(|| anyhow::anyhow!("argument must be a memory value"))?;
+                Value::Int(check_range(value, min, max)?)
+            }
+            Kind::String | Kind::Special => Value::String(value.to_string()),
+            Kind::Enum(names) => {
+                let name = names
+                    .iter()
+                    .find(|name| name.eq_ignore_ascii_case(value))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "argument(s) must be one of the following: {}",
+                            names.join(", ")
+                        )
+                    })?;
+                Value::String(name.to_string())
+            }
+        };
+
+        (self.set)(config, value)
+    }
+
+    /// Lines CONFIG REWRITE writes for the current value.
+    pub(crate) fn lines(&self, config: &Config) -> Vec<String> {
+        let value = self.get(config);
+        let line = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+            format!("{} {}", self.name, args.join(" "))
+        };
+
+        match self.format {
+            Format::Single => vec![line(&[&value])],
+            Format::Words | Format::Lines(_) if value.is_empty() => vec![line(&[""])],
+            Format::Words => vec![line(&value.split_whitespace().collect::<Vec<_>>())],
+            Format::Lines(count) => value
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .chunks(count)
+                .map(line)
+                .collect(),
+        }
+    }
+}
+
+fn check_range(value: i64, min: i64, max: i64) -> anyhow::Result<i64> {
+    anyhow::ensure!(
+        (min..=max).contains(&value),
+        "argument must be between {} and {} inclusive",
+        min,
+        max
+    );
+    Ok(value)
+}
+
+/// Quotes `value` for redis.conf when it is empty or holds spaces, quotes
+/// or unprintable characters.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, '"' | '\'' | '\\'));
+    if plain {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for byte in value.bytes() {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => quoted.push(b as char),
+            b => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Parameter named `name`, or aliased to it.
+pub(crate) fn lookup(name: &str) -> Option<&'static Param> {
+    PARAMS.iter().find(|param| param.is_named(name))
+}
+
+fn empty_to_none(value: Value) -> Option<String> {
+    Some(value.string()).filter(|value| !value.is_empty())
+}
+
+pub(crate) static PARAMS: &[Param] = &[
+    param(
+        "dir",
+        Kind::String,
+        |config| Value::String(config.dir.clone()),
+        |config, value| {
+            let dir = value.string();
+            anyhow::ensure!(
+                dir.is_empty() || Path::new(&dir).is_dir(),
+                "No such file or directory"
+            );
+            config.dir = dir;
+            Ok(())
+        },
+    ),
+    param(
+        "dbfilename",
+        Kind::String,
+        |config| Value::String(config.db_file_name.clone()),
+        |config, value| {
+            let name = value.string();
+            anyhow::ensure!(
+                !name.contains('/'),
+                "dbfilename can't be a path, just a filename"
+            );
+            config.db_file_name = name;
+            Ok(())
+        },
+    ),
+    param(
+        "databases",
+        // Every database is allocated at startup, so the count is kept to
+        // what fits in memory
+        Kind::Int { min: 1, max: 65536 },
+        |config| Value::Int(config.databases as i64),
+        |config, value| {
+            config.databases = value.int() as usize;
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "notify-keyspace-events",
+        Kind::Special,
+        |config| Value::String(notify::to_string(config.notify_keyspace_events)),
+        |config, value| {
+            config.notify_keyspace_events = notify::parse(&value.string())?;
+            Ok(())
+        },
+    ),
+    param(
+        "proto-max-bulk-len",
+        Kind::Memory {
+            min: 1024 * 1024,
+            max: i64::MAX,
+        },
+        |config| Value::Int(config.proto_max_bulk_len as i64),
+        |config, value| {
+            config.proto_max_bulk_len = value.int() as usize;
+            Ok(())
+        },
+    ),
+    param(
+        "client-output-buffer-limit",
+        Kind::Special,
+        |config| Value::String(config.output_buffer_limits.to_string()),
+        |config, value| {
+            config.output_buffer_limits = config.output_buffer_limits.parse(&value.string())?;
+            Ok(())
+        },
+    )
+    .format(Format::Lines(4)),
+    param(
+        "requirepass",
+        Kind::String,
+        |config| Value::String(config.requirepass.clone().unwrap_or_default()),
+        |config, value| {
+            config.requirepass = empty_to_none(value);
+            Ok(())
+        },
+    )
+    .hook(|store| {
+        store.acl.set_requirepass(store.config.requirepass());
+        Ok(())
+    }),
+    param(
+        "masterauth",
+        Kind::String,
+        |config| Value::String(config.masterauth.clone().unwrap_or_default()),
+        |config, value| {
+            config.masterauth = empty_to_none(value);
+            Ok(())
+        },
+    ),
+    param(
+        "aclfile",
+        Kind::String,
+        |config| Value::String(config.aclfile.clone().unwrap_or_default()),
+        |config, value| {
+            config.aclfile = empty_to_none(value);
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "port",
+        Kind::Int { min: 0, max: 65535 },
+        |config| Value::Int(config.port.into()),
+        |config, value| {
+            config.port = value.int() as u16;
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "bind",
+        Kind::Special,
+        |config| Value::String(config.bind.join(" ")),
+        |config, value| {
+            let bind: Vec<String> = value
+                .string()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect();
+            anyhow::ensure!(!bind.is_empty(), "Too few bind addresses specified.");
+            config.bind = bind;
+            Ok(())
+        },
+    )
+    .format(Format::Words)
+    .immutable(),
+    param(
+        "protected-mode",
+        Kind::Bool,
+        |config| Value::Bool(config.protected_mode),
+        |config, value| {
+            config.protected_mode = value.bool();
+            Ok(())
+        },
+    ),
+    param(
+        "unixsocket",
+        Kind::String,
+        |config| Value::String(config.unixsocket.clone().unwrap_or_default()),
+        |config, value| {
+            config.unixsocket = empty_to_none(value);
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "unixsocketperm",
+        Kind::Special,
+        |config| Value::String(format!("{:o}", config.unixsocketperm)),
+        |config, value| {
+            config.unixsocketperm = u32::from_str_radix(&value.string(), 8)
+                .ok()
+                .filter(|perm| *perm <= 0o777)
+                .ok_or_else(|| anyhow::anyhow!("Invalid socket file permissions"))?;
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "save",
+        Kind::Special,
+        |config| {
+            let points: Vec<String> = config
+                .save
+                .iter()
+                .map(|(seconds, changes)| format!("{} {}", seconds, changes))
+                .collect();
+            Value::String(points.join(" "))
+        },
+        |config, value| {
+            let value = value.string();
+            let words: Vec<&str> = value.split_whitespace().collect();
+            anyhow::ensure!(words.len().is_multiple_of(2), "Invalid save parameters");
+            config.save = words
+                .chunks(2)
+                .map(|pair| Some((pair[0].parse().ok()?, pair[1].parse().ok()?)))
+                .collect::<Option<_>>()
+                .ok_or_else(|| anyhow::anyhow!("Invalid save parameters"))?;
+            Ok(())
+        },
+    )
+    .format(Format::Lines(2)),
+    param(
+        "replicaof",
+        Kind::Special,
+        |config| {
+            Value::String(match &config.role {
+                Role::Master => String::new(),
+                Role::Replica { master_addr } => master_addr.replacen(':', " ", 1),
+            })
+        },
+        |config, value| {
+            let value = value.string();
+            config.role = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => Role::Master,
+                [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => {
+                    Role::Master
+                }
+                [host, port] => {
+                    port.parse::<u16>()
+                        .map_err(|_| anyhow::anyhow!("Invalid master port"))?;
+                    Role::Replica {
+                        master_addr: format!("{}:{}", host, port),
+                    }
+                }
+                _ => anyhow::bail!("wrong number of arguments"),
+            };
+            Ok(())
+        },
+    )
+    .alias("slaveof")
+    .format(Format::Words)
+    .immutable(),
+    param(
+        "tls-port",
+        Kind::Int { min: 0, max: 65535 },
+        |config| Value::Int(config.tls.port.into()),
+        |config, value| {
+            config.tls.port = value.int() as u16;
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "tls-cert-file",
+        Kind::String,
+        |config| Value::String(config.tls.cert_file.clone()),
+        |config, value| {
+            config.tls.cert_file = value.string();
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "tls-key-file",
+        Kind::String,
+        |config| Value::String(config.tls.key_file.clone()),
+        |config, value| {
+            config.tls.key_file = value.string();
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "tls-ca-cert-file",
+        Kind::String,
+        |config| Value::String(config.tls.ca_cert_file.clone()),
+        |config, value| {
+            config.tls.ca_cert_file = value.string();
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "tls-auth-clients",
+        Kind::Enum(&["yes", "no", "optional"]),
+        |config| {
+            Value::String(String::from(match config.tls.auth_clients {
+                TlsAuthClients::Yes => "yes",
+                TlsAuthClients::No => "no",
+                TlsAuthClients::Optional => "optional",
+            }))
+        },
+        |config, value| {
+            config.tls.auth_clients = match value.string().as_str() {
+                "yes" => TlsAuthClients::Yes,
+                "no" => TlsAuthClients::No,
+                _ => TlsAuthClients::Optional,
+            };
+            Ok(())
+        },
+    )
+    .immutable(),
+    param(
+        "tls-auth-clients-user",
+        Kind::Enum(&["off", "CN"]),
+        |config| {
+            Value::String(String::from(if config.tls.auth_clients_user {
+                "CN"
+            } else {
+                "off"
+            }))
+        },
+        |config, value| {
+            config.tls.auth_clients_user = value.string() == "CN";
+            Ok(())
+        },
+    ),
+    param(
+        "tls-replication",
+        Kind::Bool,
+        |config| Value::Bool(config.tls.replication),
+        |config, value| {
+            config.tls.replication = value.bool();
+            Ok(())
+        },
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_checks_kind_and_bounds() {
+        let mut config = Config::default();
+        let param = |name| lookup(name).unwrap();
+
+        param("PORT").set(&mut config, "7000").unwrap();
+        assert_eq!(param("port").get(&config), "7000");
+        assert!(param("port")
+            .set(&mut config, "70000")
+            .unwrap_err()
+            .to_string()
+            .contains("between 0 and 65535"));
+        assert!(param("protected-mode").set(&mut config, "maybe").is_err());
+
+        param("proto-max-bulk-len").set(&mut config, "2mb").unwrap();
+        assert_eq!(param("proto-max-bulk-len").get(&config), "2097152");
+        param("tls-auth-clients-user")
+            .set(&mut config, "cn")
+            .unwrap();
+        assert_eq!(param("tls-auth-clients-user").get(&config), "CN");
+
+        param("slaveof").set(&mut config, "127.0.0.1 6380").unwrap();
+        assert_eq!(param("replicaof").get(&config), "127.0.0.1 6380");
+
+        assert!(param("databases")
+            .set(&mut config, "2147483647")
+            .unwrap_err()
+            .to_string()
+            .contains("between 1 and 65536"));
+
+        param("save").set(&mut config, "900 1 60 100").unwrap();
+        assert_eq!(
+            param("save").lines(&config),
+            vec!["save 900 1", "save 60 100"]
+        );
+        param("requirepass").set(&mut config, "a b").unwrap();
+        assert_eq!(
+            param("requirepass").lines(&config),
+            vec!["requirepass \"a b\""]
+        );
+    }
+}
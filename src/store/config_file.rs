@@ -0,0 +1,312 @@
+use std::{collections::HashSet, fs};
+
+use anyhow::Context;
+
+use super::config::{lookup, Config, Format, PARAMS};
+
+/// Marks the settings CONFIG REWRITE appends to the config file.
+const REWRITE_MARKER: &str = "# Generated by CONFIG REWRITE";
+
+/// How deep `include` directives may nest, so a file including itself
+/// fails instead of recursing forever.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A directive with its arguments, and where it came from for errors.
+#[derive(Debug, PartialEq)]
+struct Directive {
+    name: String,
+    args: Vec<String>,
+    origin: String,
+}
+
+/// Builds the config from the server's arguments, as redis-server takes
+/// them: an optional redis.conf path followed by `--name value...` options,
+/// which are applied after the file so they override it.
+pub(crate) fn load_config(args: &[String]) -> anyhow::Result<Config> {
+    let mut directives = vec![];
+    let mut config = Config::default();
+    let options = match args.first() {
+        Some(path) if !path.starts_with("--") => {
+            read_file(path, 0, &mut directives)?;
+            config.set_config_file(path);
+            &args[1..]
+        }
+        _ => args,
+    };
+    directives.extend(parse_options(options)?);
+
+    let mut seen = HashSet::new();
+    for directive in directives {
+        let mut args = directive.args;
+        // Repeated `save` lines add snapshot points, while the first one
+        // replaces the defaults
+        if let Some(param) = lookup(&directive.name) {
+            if matches!(param.format, Format::Lines(_)) && !seen.insert(param.name) {
+                args.insert(0, param.get(&config));
+            }
+        }
+        config.apply(&directive.name, &args).with_context(|| {
+            format!(
+                "Bad configuration directive '{}' at {}",
+                directive.name, directive.origin
+            )
+        })?;
+    }
+
+    Ok(config)
+}
+
+fn read_file(path: &str, depth: usize, directives: &mut Vec<Directive>) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        depth <= MAX_INCLUDE_DEPTH,
+        "Too many nested includes at '{}'",
+        path
+    );
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read configuration file '{}'", path))?;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let origin = format!("{}:{}", path, index + 1);
+        let mut args = split_args(line).with_context(|| format!("Invalid line at {}", origin))?;
+        let name = args.remove(0).to_lowercase();
+        if name == "include" {
+            anyhow::ensure!(args.len() == 1, "Invalid include at {}", origin);
+            read_file(&args[0], depth + 1, directives)?;
+        } else {
+            directives.push(Directive { name, args, origin });
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the current settings to the config file, as CONFIG REWRITE does.
+/// The first line of each setting is replaced and any repeats dropped,
+/// while comments and other lines are kept. Settings the file doesn't
+/// mention are appended if they differ from the default.
+pub(crate) fn rewrite_config(config: &Config) -> anyhow::Result<()> {
+    let path = config
+        .config_file()
+        .context("The server is running without a config file")?;
+    // A config file deleted since startup is written from scratch
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read config file '{}'", path)),
+    };
+
+    let mut written = HashSet::new();
+    let mut lines = vec![];
+    for line in contents.lines() {
+        let name = split_args(line.trim())
+            .ok()
+            .and_then(|args| args.into_iter().next());
+        match name.as_deref().and_then(lookup) {
+            Some(param) => {
+                if written.insert(param.name) {
+                    lines.extend(param.lines(config));
+                }
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+
+    let defaults = Config::default();
+    let changed: Vec<_> = PARAMS
+        .iter()
+        .filter(|param| !written.contains(param.name) && param.get(config) != param.get(&defaults))
+        .collect();
+    if !changed.is_empty() && !lines.iter().any(|line| line == REWRITE_MARKER) {
+        lines.push(REWRITE_MARKER.to_string());
+    }
+    for param in changed {
+        lines.extend(param.lines(config));
+    }
+
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, lines.join("\n") + "\n")
+        .and_then(|_| fs::rename(&tmp, path))
+        .with_context(|| format!("Failed to write config file '{}'", path))
+}
+
+/// Groups `--name value...` options into directives; every argument up to
+/// the next `--name` belongs to the option before it.
+fn parse_options(options: &[String]) -> anyhow::Result<Vec<Directive>> {
+    let mut directives: Vec<Directive> = vec![];
+
+    for option in options {
+        match option.strip_prefix("--") {
+            Some(name) if !name.is_empty() => directives.push(Directive {
+                name: name.to_lowercase(),
+                args: vec![],
+                origin: format!("option '{}'", option),
+            }),
+            _ => directives
+                .last_mut()
+                .with_context(|| format!("Invalid option '{}'", option))?
+                .args
+                .push(option.clone()),
+        }
+    }
+
+    Ok(directives)
+}
+
+/// Splits a config line into arguments the way redis-server does. Double
+/// quoted arguments take escapes such as `\n` and `\x41`, single quoted
+/// ones only `\'`.
+fn split_args(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut arg = vec![];
+        let mut buf = [0; 4];
+        let mut quote = None;
+        loop {
+            let Some(c) = chars.next() else {
+                anyhow::ensure!(quote.is_none(), "Unbalanced quotes in configuration line");
+                break;
+            };
+            match (quote, c) {
+                (None, c) if c.is_whitespace() => break,
+                (None, '"' | '\'') => quote = Some(c),
+                (Some('"'), '\\') => match chars.next() {
+                    Some('x') => {
+                        let hex: String = chars.clone().take(2).collect();
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(byte) if hex.len() == 2 => {
+                                arg.push(byte);
+                                chars.nth(1);
+                            }
+                            _ => arg.push(b'x'),
+                        }
+                    }
+                    Some(escaped) => {
+                        let c = match escaped {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            'b' => '\u{8}',
+                            'a' => '\u{7}',
+                            c => c,
+                        };
+                        arg.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    }
+                    None => anyhow::bail!("Unbalanced quotes in configuration line"),
+                },
+                (Some('\''), '\\') if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    arg.push(b'\'');
+                }
+                (Some(open), c) if c == open => {
+                    // A closing quote must end the argument
+                    anyhow::ensure!(
+                        chars.peek().is_none_or(|c| c.is_whitespace()),
+                        "Unbalanced quotes in configuration line"
+                    );
+                    quote = None;
+                }
+                (_, c) => arg.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+            }
+        }
+        args.push(String::from_utf8_lossy(&arg).into_owned());
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args(r#"save 900 1"#).unwrap(),
+            vec!["save", "900", "1"]
+        );
+        assert_eq!(
+            split_args(r#"requirepass "a b\"c\x41\n" 'it\'s'  """#).unwrap(),
+            vec!["requirepass", "a b\"cA\n", "it's", ""]
+        );
+        assert!(split_args(r#"dir "/tmp"x"#).is_err());
+        assert!(split_args(r#"dir '/tmp"#).is_err());
+    }
+
+    #[test]
+    fn test_load_config_with_include_and_options() {
+        let dir = std::env::temp_dir().join(format!("redis-conf-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let included = dir.join("included.conf");
+        fs::write(&included, "port 7000\nsave 60 5\n").unwrap();
+        let main = dir.join("redis.conf");
+        fs::write(
+            &main,
+            format!(
+                "# comment\ninclude {}\nsave 900 1\ndbfilename \"dump file.rdb\"\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+
+        let args: Vec<String> = [
+            main.to_str().unwrap(),
+            "--port",
+            "7001",
+            "--replicaof",
+            "127.0.0.1 6379",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let config = load_config(&args).unwrap();
+
+        assert_eq!(config.port(), 7001);
+        assert_eq!(lookup("save").unwrap().get(&config), "60 5 900 1");
+        assert_eq!(config.db_file_name(), "dump file.rdb");
+        assert_eq!(config.master_addr(), "127.0.0.1:6379");
+
+        fs::write(&main, "port seven\n").unwrap();
+        assert!(load_config(&args[..1]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_config_keeps_comments() {
+        let dir = std::env::temp_dir().join(format!("redis-rewrite-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(
+            &path,
+            "# server\nport 7000\nsave 900 1\nsave 300 10\nport 7001\n",
+        )
+        .unwrap();
+
+        let mut config = load_config(&[path.to_string_lossy().to_string()]).unwrap();
+        config.apply("save", &["60 1".to_string()]).unwrap();
+        config.apply("masterauth", &["a b".to_string()]).unwrap();
+        rewrite_config(&config).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "# server\nport 7001\nsave 60 1\n{}\nmasterauth \"a b\"\n",
+                REWRITE_MARKER
+            )
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
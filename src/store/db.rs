@@ -1,13 +1,24 @@
 use std::{
     collections::HashMap,
-    time::{Duration, SystemTime},
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
 };
 
 use glob::Pattern;
 
+use crate::RedisError;
+
+/// Keys with a TTL that active expiry looks at in one go, as in Redis.
+const EXPIRE_SAMPLE_SIZE: usize = 20;
+
 #[derive(Debug)]
 pub(crate) struct StreamValue {
     pub(crate) id: String,
+    #[allow(dead_code)]
     fields: Vec<(String, String)>,
 }
 
@@ -30,33 +41,141 @@ impl From<Vec<StreamValue>> for Value {
 }
 
 #[derive(Debug)]
-struct Entry {
+pub(crate) struct Entry {
     value: Value,
     expiry: Option<SystemTime>,
 }
 
-pub(crate) trait IntoSystemTime {
-    fn into_system_time(self) -> Option<SystemTime>;
-}
-
-impl IntoSystemTime for Option<Duration> {
-    fn into_system_time(self) -> Option<SystemTime> {
-        self.map(|duration| SystemTime::now() + duration)
-    }
-}
-
 #[derive(Debug)]
 pub(crate) struct Db {
     data: HashMap<String, Entry>,
+    /// Keys in `data` that have a TTL.
+    expires: Expires,
+    /// WATCHing sessions per key, flagged whenever the key is modified.
+    watched_keys: HashMap<String, Vec<Arc<AtomicBool>>>,
 }
 
 impl Db {
     pub(crate) fn new() -> Self {
         Self {
             data: HashMap::new(),
+            expires: Expires::default(),
+            watched_keys: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn watch(&mut self, key: &str, flag: &Arc<AtomicBool>) {
+        let watchers = self.watched_keys.entry(key.to_string()).or_default();
+        if !watchers.iter().any(|w| Arc::ptr_eq(w, flag)) {
+            watchers.push(Arc::clone(flag));
+        }
+    }
+
+    pub(crate) fn unwatch(&mut self, key: &str, flag: &Arc<AtomicBool>) {
+        if let Some(watchers) = self.watched_keys.get_mut(key) {
+            watchers.retain(|w| !Arc::ptr_eq(w, flag));
+            if watchers.is_empty() {
+                self.watched_keys.remove(key);
+            }
+        }
+    }
+
+    /// Invalidates every session WATCHing `key`.
+    fn touch(&self, key: &str) {
+        if let Some(watchers) = self.watched_keys.get(key) {
+            for flag in watchers {
+                flag.store(true, Ordering::SeqCst);
+            }
         }
     }
 
+    /// Invalidates sessions WATCHing any key that currently exists.
+    fn touch_existing(&self) {
+        for key in self.watched_keys.keys() {
+            if self.data.contains_key(key) {
+                self.touch(key);
+            }
+        }
+    }
+
+    /// Deletes `key` if its TTL has elapsed, returning whether it expired.
+    pub(crate) fn expire_if_needed(&mut self, key: &str) -> bool {
+        let expired = matches!(
+            self.data.get(key),
+            Some(Entry { expiry: Some(exp), .. }) if exp < &SystemTime::now()
+        );
+
+        if expired {
+            self.data.remove(key);
+            self.expires.remove(key);
+            self.touch(key);
+        }
+
+        expired
+    }
+
+    /// Deletes up to `limit` keys whose TTL has elapsed, returning them.
+    /// Like Redis, it samples keys with a TTL and carries on only while
+    /// more than a quarter of a sample turns out to have expired.
+    pub(crate) fn remove_expired(&mut self, limit: usize) -> Vec<String> {
+        let mut removed = vec![];
+
+        while removed.len() < limit && !self.expires.is_empty() {
+            let sample = self
+                .expires
+                .sample(EXPIRE_SAMPLE_SIZE.min(limit - removed.len()));
+            let sampled = sample.len();
+            let now = SystemTime::now();
+            let expired: Vec<String> = sample
+                .into_iter()
+                .filter(|key| {
+                    let expiry = self.data.get(*key).and_then(|entry| entry.expiry);
+                    expiry.is_some_and(|exp| exp < now)
+                })
+                .cloned()
+                .collect();
+            let done = expired.len() * 4 <= sampled;
+
+            for key in expired {
+                self.data.remove(&key);
+                self.expires.remove(&key);
+                self.touch(&key);
+                removed.push(key);
+            }
+
+            if done {
+                break;
+            }
+        }
+
+        removed
+    }
+
+    /// Empties the database, returning the old contents so the caller can
+    /// decide where to free them. WATCH registrations stay in place.
+    pub(crate) fn flush(&mut self) -> Db {
+        self.touch_existing();
+
+        Db {
+            data: mem::take(&mut self.data),
+            expires: mem::take(&mut self.expires),
+            watched_keys: HashMap::new(),
+        }
+    }
+
+    /// Exchanges the contents of two databases, leaving WATCH registrations
+    /// with their database index.
+    pub(crate) fn swap_data(&mut self, other: &mut Db) {
+        self.touch_existing();
+        other.touch_existing();
+
+        mem::swap(&mut self.data, &mut other.data);
+        mem::swap(&mut self.expires, &mut other.expires);
+
+        self.touch_existing();
+        other.touch_existing();
+    }
+
     pub(crate) fn set<V: Into<Value>>(
         &mut self,
         key: String,
@@ -64,11 +183,62 @@ impl Db {
         expiry: Option<SystemTime>,
     ) -> anyhow::Result<()> {
         let value = value.into();
+        self.touch(&key);
+        self.expires.update(&key, expiry);
         self.data.insert(key, Entry { value, expiry });
 
         Ok(())
     }
 
+    /// Deletes `key`, returning whether it existed.
+    pub(crate) fn remove(&mut self, key: &str) -> bool {
+        self.touch(key);
+        self.expires.remove(key);
+
+        self.data.remove(key).is_some()
+    }
+
+    /// Number of keys, counting expired ones not yet removed.
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// String keys that haven't expired, with their expiry.
+    pub(crate) fn strings(&self) -> impl Iterator<Item = (&String, &String, Option<SystemTime>)> {
+        let now = SystemTime::now();
+        self.data
+            .iter()
+            .filter(move |(_, entry)| entry.expiry.is_none_or(|exp| exp > now))
+            .filter_map(|(key, entry)| match &entry.value {
+                Value::String(value) => Some((key, value, entry.expiry)),
+                Value::Stream(..) => None,
+            })
+    }
+
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Removes `key` together with its expiry, skipping entries that have
+    /// already expired. Used by MOVE to carry a key across databases.
+    pub(crate) fn take_entry(&mut self, key: &str) -> Option<Entry> {
+        self.touch(key);
+        self.expires.remove(key);
+
+        match self.data.remove(key) {
+            Some(Entry {
+                expiry: Some(exp), ..
+            }) if exp < SystemTime::now() => None,
+            entry => entry,
+        }
+    }
+
+    pub(crate) fn insert_entry(&mut self, key: String, entry: Entry) {
+        self.touch(&key);
+        self.expires.update(&key, entry.expiry);
+        self.data.insert(key, entry);
+    }
+
     pub(crate) fn get(&self, key: &str) -> Option<&Value> {
         self.data.get(key).and_then(|item| match &item.expiry {
             Some(exp) if exp < &SystemTime::now() => None,
@@ -89,6 +259,25 @@ impl Db {
         }
     }
 
+    /// Number of keys, how many of them have a TTL, and the average TTL
+    /// left in milliseconds, as INFO keyspace reports them.
+    pub(crate) fn keyspace_info(&self) -> (usize, usize, u64) {
+        let now = SystemTime::now();
+        let total_ttl: u128 = self
+            .expires
+            .keys
+            .iter()
+            .filter_map(|key| self.data.get(key)?.expiry)
+            .map(|expiry| expiry.duration_since(now).unwrap_or_default().as_millis())
+            .sum();
+        let avg_ttl = match self.expires.len() {
+            0 => 0,
+            len => (total_ttl / len as u128) as u64,
+        };
+
+        (self.data.len(), self.expires.len(), avg_ttl)
+    }
+
     pub(crate) fn keys(&self, pattern: &str) -> Vec<String> {
         let ptn = match Pattern::new(pattern) {
             Ok(ptn) => ptn,
@@ -112,8 +301,11 @@ impl Db {
 
         match value {
             Some(x) => match &mut x.value {
-                Value::Stream(sv) => sv.push(StreamValue { id, fields }),
-                Value::String(..) => anyhow::bail!("Invalid Operation"),
+                Value::Stream(sv) => {
+                    sv.push(StreamValue { id, fields });
+                    self.touch(&key);
+                }
+                Value::String(..) => return Err(RedisError::WrongType.into()),
             },
             None => {
                 let fields = vec![StreamValue { id, fields }];
@@ -124,3 +316,104 @@ impl Db {
         Ok(())
     }
 }
+
+/// Keys with a TTL, kept apart from the data so active expiry can sample
+/// them without going through every key.
+#[derive(Debug, Default)]
+struct Expires {
+    keys: Vec<String>,
+    /// Index of each key in `keys`.
+    positions: HashMap<String, usize>,
+}
+
+impl Expires {
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Tracks `key` if it has an expiry, and forgets it otherwise.
+    fn update(&mut self, key: &str, expiry: Option<SystemTime>) {
+        match expiry {
+            Some(_) if !self.positions.contains_key(key) => {
+                self.positions.insert(key.to_string(), self.keys.len());
+                self.keys.push(key.to_string());
+            }
+            Some(_) => {}
+            None => self.remove(key),
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        let Some(position) = self.positions.remove(key) else {
+            return;
+        };
+
+        self.keys.swap_remove(position);
+        if let Some(moved) = self.keys.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+    }
+
+    /// Up to `count` distinct keys, running on from a random position.
+    fn sample(&self, count: usize) -> Vec<&String> {
+        let mut bytes = [0; 8];
+        getrandom::getrandom(&mut bytes).expect("Failed to read random bytes");
+        let start = u64::from_le_bytes(bytes) as usize % self.keys.len().max(1);
+
+        self.keys
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(count.min(self.keys.len()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_remove_expired_samples_keys_with_ttl() {
+        let mut db = Db::new();
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let future = SystemTime::now() + Duration::from_secs(60);
+        for i in 0..50 {
+            db.set(format!("expired:{i}"), format!("{i}"), Some(past))
+                .unwrap();
+            db.set(format!("live:{i}"), format!("{i}"), Some(future))
+                .unwrap();
+            db.set(format!("persistent:{i}"), format!("{i}"), None)
+                .unwrap();
+        }
+        // Dropping the TTL takes the key out of the sampled ones
+        db.set("expired:0".to_string(), "0".to_string(), None)
+            .unwrap();
+        db.remove("expired:1");
+        assert_eq!(db.expires.len(), 98);
+
+        let removed = db.remove_expired(10);
+        assert!(removed.len() <= 10);
+        assert!(removed.iter().all(|key| key.starts_with("expired:")));
+
+        // A sample of live keys alone stops the cycle, so it may take a few
+        while db.expires.len() > 50 {
+            db.remove_expired(200);
+        }
+        assert_eq!(db.keyspace_info().0, 101);
+        assert!(db.contains_key("expired:0"));
+        assert!(db.expires.keys.iter().all(|key| key.starts_with("live:")));
+        assert!(db
+            .expires
+            .keys
+            .iter()
+            .enumerate()
+            .all(|(i, key)| db.expires.positions[key] == i));
+    }
+}
@@ -0,0 +1,50 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::Context;
+use tokio::sync::{mpsc, oneshot};
+
+use super::Store;
+
+type Job = Box<dyn FnOnce(&mut Store) + Send>;
+
+/// Handle to the task that owns the store. Jobs run there one at a time,
+/// so the dataset needs no locking, and since jobs can't await, network I/O
+/// never holds up other clients.
+#[derive(Debug, Clone)]
+pub(crate) struct Executor {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl Executor {
+    pub(crate) fn spawn(mut store: Store) -> Self {
+        let (jobs, mut queue) = mpsc::unbounded_channel::<Job>();
+
+        tokio::spawn(async move {
+            while let Some(job) = queue.recv().await {
+                job(&mut store);
+            }
+        });
+
+        Self { jobs }
+    }
+
+    /// Runs `job` against the store once every job queued before it is done.
+    pub(crate) async fn run<F, R>(&self, job: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Store) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job: Job = Box::new(move |store| {
+            // A panicking job only fails its own caller, not the whole server
+            if let Ok(value) = panic::catch_unwind(AssertUnwindSafe(|| job(store))) {
+                let _ = reply.send(value);
+            }
+        });
+
+        self.jobs
+            .send(job)
+            .map_err(|_| anyhow::anyhow!("Executor has stopped"))?;
+        result.await.context("Job panicked on the executor")
+    }
+}
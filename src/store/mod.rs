@@ -1,59 +1,153 @@
+mod acl;
 mod config;
+mod config_file;
 mod db;
+mod executor;
+pub(crate) mod notify;
+mod pause;
+mod pubsub;
+mod replication;
+mod slot;
+mod stats;
+
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Context;
+use bytes::Bytes;
 use config::Config;
-use db::Db;
-use tokio::fs;
+use pubsub::PubSub;
+use tokio::{
+    fs,
+    sync::{mpsc, Notify},
+};
 
-use crate::{rdb_parser::RdbParser, Conn};
+use crate::{
+    handler::Session,
+    rdb_parser::{Rdb, RdbParser},
+    Resp,
+};
 
-pub(crate) use db::IntoSystemTime;
+pub(crate) use acl::{Acl, LogEntry, User, CATEGORIES};
+pub(crate) use config::{
+    lookup as lookup_param, ClientClass, OutputBufferLimit, Param, TlsAuthClients, TlsConfig,
+    PARAMS,
+};
+pub(crate) use config_file::{load_config, rewrite_config};
+pub(crate) use db::Db;
 pub(crate) use db::Value as RedisValue;
+pub(crate) use executor::Executor;
+pub(crate) use pause::{Pause, PauseMode};
+pub(crate) use pubsub::Subscriber;
+pub(crate) use replication::{Replication, BACKLOG_SIZE};
+pub(crate) use stats::{CommandStats, Stats};
+
+/// How often, and how many keys per DB, the active expire cycle reclaims.
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+const ACTIVE_EXPIRE_KEYS_PER_DB: usize = 200;
 
 #[derive(Debug)]
 pub(crate) struct ReplicaState {
-    pub(crate) conn: Conn,
-    pub(crate) ack_offset: usize,
+    /// Replication stream, written to the socket by the replica's link task.
+    sender: mpsc::UnboundedSender<Bytes>,
+    /// Bytes handed to `sender` that haven't been written yet.
+    pending: Arc<AtomicUsize>,
+    /// Offset last confirmed through REPLCONF ACK.
+    pub(crate) ack_offset: Arc<AtomicUsize>,
+    /// Unix time in seconds of the last REPLCONF ACK.
+    pub(crate) ack_time: Arc<AtomicU64>,
+    soft_limit_since: Option<Instant>,
+    pub(crate) ip: String,
+    /// Port the replica listens on, as it told REPLCONF listening-port.
+    pub(crate) port: u16,
+}
+
+/// The link task's end of a replica registered by PSYNC.
+#[derive(Debug)]
+pub(crate) struct ReplicaLink {
+    pub(crate) stream: mpsc::UnboundedReceiver<Bytes>,
+    pub(crate) pending: Arc<AtomicUsize>,
+    pub(crate) ack_offset: Arc<AtomicUsize>,
+    pub(crate) ack_time: Arc<AtomicU64>,
+    /// Woken whenever any replica acknowledges an offset.
+    pub(crate) acks: Arc<Notify>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Store {
     pub(crate) config: Config,
-    pub(crate) db: Db,
+    pub(crate) acl: Acl,
+    pub(crate) dbs: Vec<Db>,
+    pub(crate) pubsub: PubSub,
+    /// Sessions of connected clients, checked out while their commands run.
+    pub(crate) clients: HashMap<u64, Session>,
+    pub(crate) pause: Pause,
+    pub(crate) stats: Stats,
+    /// Writes since startup, reported as changes since the last save.
+    pub(crate) dirty: u64,
+    pub(crate) started: Instant,
+    /// Identifies this run of the server, as INFO's `run_id`.
+    pub(crate) run_id: String,
     pub(crate) replicas: Vec<ReplicaState>,
-    pub(crate) master_repl_offset: usize,
-    offset: usize,
+    pub(crate) replica_acks: Arc<Notify>,
+    pub(crate) replication: Replication,
+    /// Database last selected in the replication stream, if any.
+    pub(crate) repl_selected_db: Option<usize>,
 }
 
 impl Store {
-    pub(crate) async fn init(
-        dir: &str,
-        db_file_name: &str,
-        replica_of: &str,
-    ) -> anyhow::Result<Self> {
-        let config = Config::new(dir, db_file_name, replica_of);
-        let db = match Self::load_data(&config).await {
+    pub(crate) async fn init(config: Config) -> anyhow::Result<Self> {
+        let mut acl = Acl::default();
+        acl.set_requirepass(config.requirepass());
+        if let Some(path) = config.aclfile() {
+            // Loading the file replaces the default user requirepass set up
+            anyhow::ensure!(
+                config.requirepass().is_none(),
+                "requirepass can't be used together with aclfile; set the default user's password in the ACL file instead"
+            );
+            acl.load(path)?;
+        }
+        let dbs = match Self::load_data(&config).await {
             Ok(data) => data,
             Err(err) => {
                 eprintln!("Failed to load data from .rdb file, Err: {}", err);
-                Db::new()
+                Self::empty_dbs(config.databases())
             }
         };
 
         Ok(Self {
             config,
-            db,
+            acl,
+            dbs,
+            pubsub: PubSub::default(),
+            clients: HashMap::new(),
+            pause: Pause::default(),
+            stats: Stats::default(),
+            dirty: 0,
+            started: Instant::now(),
+            run_id: random_id(),
             replicas: vec![],
-            master_repl_offset: 0,
-            offset: 0,
+            replica_acks: Arc::default(),
+            replication: Replication::default(),
+            repl_selected_db: None,
         })
     }
 
-    async fn load_data(config: &Config) -> anyhow::Result<Db> {
+    fn empty_dbs(count: usize) -> Vec<Db> {
+        (0..count).map(|_| Db::new()).collect()
+    }
+
+    async fn load_data(config: &Config) -> anyhow::Result<Vec<Db>> {
         // Check if dir and db_file_name are passed
         if config.dir().is_empty() || config.db_file_name().is_empty() {
-            return Ok(Db::new());
+            return Ok(Self::empty_dbs(config.databases()));
         }
 
         let dir_path = fs::canonicalize(config.dir())
@@ -64,27 +158,233 @@ impl Store {
         let mut parser = RdbParser::new(rdb_file_path).await?;
         let rdb = parser.parse().await?;
 
-        let mut db = Db::new();
+        Self::dbs_from_rdb(rdb, config.databases())
+    }
+
+    /// Replaces the whole dataset with a snapshot received from the master,
+    /// invalidating the WATCHes on both the old and the new keys.
+    pub(crate) fn load_snapshot(&mut self, rdb: Rdb) -> anyhow::Result<()> {
+        let dbs = Self::dbs_from_rdb(rdb, self.config.databases())?;
+        for (db, mut data) in self.dbs.iter_mut().zip(dbs) {
+            db.swap_data(&mut data);
+        }
+
+        Ok(())
+    }
+
+    fn dbs_from_rdb(rdb: Rdb, count: usize) -> anyhow::Result<Vec<Db>> {
+        let mut dbs = Self::empty_dbs(count);
+
+        for (index, data) in rdb.data.into_iter() {
+            let db = dbs.get_mut(index).with_context(|| {
+                format!(
+                    "RDB file contains DB {} but only {} databases are configured",
+                    index, count
+                )
+            })?;
+
+            for (key, redis_value) in data.into_iter() {
+                db.set(key, redis_value.value, redis_value.expiry)?;
+            }
+        }
+
+        Ok(dbs)
+    }
+
+    pub(crate) fn db(&self, index: usize) -> &Db {
+        &self.dbs[index]
+    }
+
+    pub(crate) fn db_mut(&mut self, index: usize) -> &mut Db {
+        &mut self.dbs[index]
+    }
+
+    /// Publishes `__keyspace@<db>__:<key>` and `__keyevent@<db>__:<event>`
+    /// messages when `class` is enabled in notify-keyspace-events.
+    pub(crate) fn notify_keyspace_event(&self, class: u32, event: &str, key: &str, db: usize) {
+        let flags = self.config.notify_keyspace_events();
+        if flags & class == 0 {
+            return;
+        }
+
+        if flags & notify::KEYSPACE != 0 {
+            let channel = format!("__keyspace@{}__:{}", db, key);
+            self.pubsub.publish(&channel, event);
+        }
+        if flags & notify::KEYEVENT != 0 {
+            let channel = format!("__keyevent@{}__:{}", db, event);
+            self.pubsub.publish(&channel, key);
+        }
+    }
+
+    /// Deletes `key` once its TTL has elapsed. Replicas leave deletion to
+    /// their master and only hide expired keys from reads, so the master
+    /// sends them a DEL.
+    pub(crate) fn expire_if_needed(&mut self, db: usize, key: &str) -> bool {
+        if self.config.is_replica() {
+            return false;
+        }
+
+        let expired = self.dbs[db].expire_if_needed(key);
+        if expired {
+            self.expired(db, key);
+        }
+
+        expired
+    }
+
+    fn active_expire_cycle(&mut self) {
+        // Expiring keys is a write, so it waits out a write pause
+        if self.config.is_replica() || self.pause.blocks(true).is_some() {
+            return;
+        }
 
-        for (key, redis_value) in rdb.data.into_iter() {
-            db.set(key, redis_value.value, redis_value.expiry)?;
+        for db in 0..self.dbs.len() {
+            for key in self.dbs[db].remove_expired(ACTIVE_EXPIRE_KEYS_PER_DB) {
+                self.expired(db, &key);
+            }
         }
+    }
 
-        Ok(db)
+    fn expired(&mut self, db: usize, key: &str) {
+        self.stats.expired_keys += 1;
+        self.notify_keyspace_event(notify::EXPIRED, "expired", key, db);
+        self.propagate(vec![(db, vec!["DEL".to_string(), key.to_string()])]);
     }
 
-    pub(crate) fn add_replica(&mut self, conn: Conn) {
+    /// Runs `f` with client `id`'s session, or returns `None` if the
+    /// client has been removed.
+    pub(crate) fn with_client<R>(
+        &mut self,
+        id: u64,
+        f: impl FnOnce(&mut Store, &mut Session) -> R,
+    ) -> Option<R> {
+        let mut session = self.clients.remove(&id)?;
+        // The session goes back even if `f` panics, so the connection's
+        // cleanup still finds its WATCH and pub/sub registrations
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(self, &mut session)));
+        self.clients.insert(id, session);
+
+        match result {
+            Ok(result) => Some(result),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// Registers the replica at `ip` listening on `port`, which has just
+    /// been sent a snapshot.
+    pub(crate) fn add_replica(&mut self, ip: &str, port: u16) -> ReplicaLink {
+        let (sender, stream) = mpsc::unbounded_channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let ack_offset = Arc::new(AtomicUsize::new(0));
+        let ack_time = Arc::new(AtomicU64::new(unix_time()));
+
         self.replicas.push(ReplicaState {
-            conn,
-            ack_offset: 0,
+            sender,
+            pending: Arc::clone(&pending),
+            ack_offset: Arc::clone(&ack_offset),
+            ack_time: Arc::clone(&ack_time),
+            soft_limit_since: None,
+            ip: ip.to_string(),
+            port,
+        });
+        self.replication.create_backlog();
+        // Force a SELECT before the next propagated write, as the new
+        // replica has no notion of which database the stream is in.
+        self.repl_selected_db = None;
+
+        ReplicaLink {
+            stream,
+            pending,
+            ack_offset,
+            ack_time,
+            acks: Arc::clone(&self.replica_acks),
+        }
+    }
+
+    /// Appends writes to the replication stream, each paired with the DB it
+    /// ran against.
+    pub(crate) fn propagate(&mut self, entries: Vec<(usize, Vec<String>)>) {
+        if entries.is_empty() {
+            return;
+        }
+
+        // Replicas apply writes to whichever DB the stream last selected
+        let mut encoded = String::new();
+        for (db, args) in entries {
+            if self.repl_selected_db != Some(db) {
+                encoded.push_str(&Resp::array(vec!["SELECT".to_string(), db.to_string()]).encode());
+                self.repl_selected_db = Some(db);
+            }
+            encoded.push_str(&Resp::array(args).encode());
+        }
+
+        self.send_to_replicas(Bytes::from(encoded));
+    }
+
+    /// Appends `bytes` to the replication stream and queues them for every
+    /// replica, dropping replicas whose link has closed or fallen too far
+    /// behind.
+    pub(crate) fn send_to_replicas(&mut self, bytes: Bytes) {
+        // A replica's offset follows its master's stream alone, so what its
+        // own clients run, such as PUBLISH, stays out of it
+        if self.config.is_replica() {
+            return;
+        }
+        self.replication.feed(&bytes);
+
+        let limit = self.config.output_buffer_limits().get(ClientClass::Replica);
+
+        self.replicas.retain_mut(|replica| {
+            let pending = replica.pending.fetch_add(bytes.len(), Ordering::SeqCst) + bytes.len();
+            if limit.exceeded(pending, &mut replica.soft_limit_since) {
+                eprintln!("Replica closed for overcoming output buffer limits");
+                return false;
+            }
+
+            replica.sender.send(bytes.clone()).is_ok()
         });
     }
+}
 
-    pub(crate) fn increment_offset(&mut self, frame_len: usize) {
-        self.offset += frame_len;
+/// A random 40 character hex ID, like redis-server's run and
+/// replication IDs.
+pub(crate) fn random_id() -> String {
+    let mut bytes = [0; 20];
+    getrandom::getrandom(&mut bytes).expect("Failed to read random bytes");
+
+    hex::encode(bytes)
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Background task deleting expired keys that are never read again.
+pub(crate) async fn expire_cycle(executor: Executor) {
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if executor.run(Store::active_expire_cycle).await.is_err() {
+            break;
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub(crate) fn get_offset(&mut self) -> usize {
-        self.offset
+    #[tokio::test]
+    async fn test_requirepass_conflicts_with_aclfile() {
+        let args =
+            ["--requirepass", "pw", "--aclfile", "/nonexistent/users.acl"].map(str::to_string);
+        let err = Store::init(load_config(&args).unwrap()).await.unwrap_err();
+        assert!(err.to_string().contains("aclfile"), "{}", err);
     }
 }
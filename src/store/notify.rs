@@ -0,0 +1,103 @@
+//! Event classes for `notify-keyspace-events`, matching the flag characters
+//! Redis accepts in that setting.
+
+pub(crate) const KEYSPACE: u32 = 1 << 0; // K
+pub(crate) const KEYEVENT: u32 = 1 << 1; // E
+pub(crate) const GENERIC: u32 = 1 << 2; // g
+pub(crate) const STRING: u32 = 1 << 3; // $
+pub(crate) const LIST: u32 = 1 << 4; // l
+pub(crate) const SET: u32 = 1 << 5; // s
+pub(crate) const HASH: u32 = 1 << 6; // h
+pub(crate) const ZSET: u32 = 1 << 7; // z
+pub(crate) const EXPIRED: u32 = 1 << 8; // x
+pub(crate) const EVICTED: u32 = 1 << 9; // e
+pub(crate) const STREAM: u32 = 1 << 10; // t
+pub(crate) const KEY_MISS: u32 = 1 << 11; // m
+pub(crate) const NEW: u32 = 1 << 12; // n
+
+/// Every class covered by the `A` alias. Key misses and new keys are
+/// excluded, as in Redis, because they are noisy.
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+const CLASSES: [(char, u32); 11] = [
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('m', KEY_MISS),
+    ('n', NEW),
+];
+
+pub(crate) fn parse(flags: &str) -> anyhow::Result<u32> {
+    let mut result = 0;
+
+    for ch in flags.chars() {
+        result |= match ch {
+            'A' => ALL,
+            'K' => KEYSPACE,
+            'E' => KEYEVENT,
+            _ => match CLASSES.iter().find(|(c, _)| *c == ch) {
+                Some((_, class)) => *class,
+                None => anyhow::bail!("Invalid event class character '{}'", ch),
+            },
+        };
+    }
+
+    Ok(result)
+}
+
+/// Renders `flags` in the canonical form CONFIG GET reports.
+pub(crate) fn to_string(flags: u32) -> String {
+    let mut result = String::new();
+
+    if flags & ALL == ALL {
+        result.push('A');
+    } else {
+        for (ch, class) in CLASSES.iter().filter(|(_, class)| class & ALL != 0) {
+            if flags & class != 0 {
+                result.push(*ch);
+            }
+        }
+    }
+    if flags & KEYSPACE != 0 {
+        result.push('K');
+    }
+    if flags & KEYEVENT != 0 {
+        result.push('E');
+    }
+    if flags & KEY_MISS != 0 {
+        result.push('m');
+    }
+    if flags & NEW != 0 {
+        result.push('n');
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_alias() {
+        assert_eq!(parse("KEA").unwrap(), KEYSPACE | KEYEVENT | ALL);
+    }
+
+    #[test]
+    fn test_parse_invalid_class() {
+        assert!(parse("Kq").is_err());
+    }
+
+    #[test]
+    fn test_to_string_roundtrip() {
+        assert_eq!(to_string(parse("EKA").unwrap()), "AKE");
+        assert_eq!(to_string(parse("Kx$n").unwrap()), "$xKn");
+        assert_eq!(to_string(0), "");
+    }
+}
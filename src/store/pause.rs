@@ -0,0 +1,84 @@
+use tokio::{sync::watch, time::Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PauseMode {
+    /// Only commands that replicate wait.
+    Write,
+    All,
+}
+
+/// Client pause set through CLIENT PAUSE. Paused clients hold their
+/// requests until the deadline passes or CLIENT UNPAUSE is called.
+#[derive(Debug)]
+pub(crate) struct Pause {
+    state: Option<(PauseMode, Instant)>,
+    resumed: watch::Sender<()>,
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        Self {
+            state: None,
+            resumed: watch::channel(()).0,
+        }
+    }
+}
+
+impl Pause {
+    /// Overlapping pauses keep the later deadline and the stricter mode.
+    pub(crate) fn pause(&mut self, mode: PauseMode, until: Instant) {
+        self.state = match self.state {
+            Some((current, deadline)) if deadline > Instant::now() => {
+                Some((current.max(mode), deadline.max(until)))
+            }
+            _ => Some((mode, until)),
+        };
+    }
+
+    pub(crate) fn unpause(&mut self) {
+        self.state = None;
+        self.resumed.send_replace(());
+    }
+
+    /// Deadline a command has to wait for, if clients are paused for it.
+    pub(crate) fn blocks(&mut self, replicates: bool) -> Option<Instant> {
+        let (mode, until) = self.state?;
+        if until <= Instant::now() {
+            self.state = None;
+            return None;
+        }
+
+        (mode == PauseMode::All || replicates).then_some(until)
+    }
+
+    /// Fires on the next CLIENT UNPAUSE.
+    pub(crate) fn resumed(&self) -> watch::Receiver<()> {
+        self.resumed.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_overlapping_pauses() {
+        let mut pause = Pause::default();
+        let now = Instant::now();
+        assert_eq!(pause.blocks(true), None);
+
+        pause.pause(PauseMode::All, now + Duration::from_secs(1));
+        pause.pause(PauseMode::Write, now + Duration::from_secs(10));
+        // The stricter mode and the later deadline both stick
+        assert_eq!(pause.blocks(false), Some(now + Duration::from_secs(10)));
+
+        pause.unpause();
+        assert_eq!(pause.blocks(true), None);
+
+        pause.pause(PauseMode::Write, now + Duration::from_secs(10));
+        assert_eq!(pause.blocks(false), None);
+        assert!(pause.blocks(true).is_some());
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use glob::Pattern;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::slot;
+use crate::Resp;
+
+/// Channel through which published messages reach a subscribed connection.
+pub(crate) type Subscriber = UnboundedSender<Resp>;
+
+/// Registry of channel and pattern subscriptions, keyed by session id.
+#[derive(Debug, Default)]
+pub(crate) struct PubSub {
+    channels: HashMap<String, HashMap<u64, Subscriber>>,
+    patterns: HashMap<String, HashMap<u64, Subscriber>>,
+    /// Shard channels, a namespace separate from `channels`, grouped by the
+    /// hash slot that owns them.
+    shard_channels: HashMap<u16, HashMap<String, HashMap<u64, Subscriber>>>,
+}
+
+impl PubSub {
+    pub(crate) fn subscribe(&mut self, channel: &str, id: u64, subscriber: &Subscriber) {
+        self.channels
+            .entry(channel.to_string())
+            .or_default()
+            .insert(id, subscriber.clone());
+    }
+
+    pub(crate) fn unsubscribe(&mut self, channel: &str, id: u64) {
+        Self::remove(&mut self.channels, channel, id);
+    }
+
+    pub(crate) fn psubscribe(&mut self, pattern: &str, id: u64, subscriber: &Subscriber) {
+        self.patterns
+            .entry(pattern.to_string())
+            .or_default()
+            .insert(id, subscriber.clone());
+    }
+
+    pub(crate) fn punsubscribe(&mut self, pattern: &str, id: u64) {
+        Self::remove(&mut self.patterns, pattern, id);
+    }
+
+    pub(crate) fn ssubscribe(&mut self, channel: &str, id: u64, subscriber: &Subscriber) {
+        let slot = slot::key_hash_slot(channel.as_bytes());
+
+        self.shard_channels
+            .entry(slot)
+            .or_default()
+            .entry(channel.to_string())
+            .or_default()
+            .insert(id, subscriber.clone());
+    }
+
+    pub(crate) fn sunsubscribe(&mut self, channel: &str, id: u64) {
+        let slot = slot::key_hash_slot(channel.as_bytes());
+
+        if let Some(channels) = self.shard_channels.get_mut(&slot) {
+            Self::remove(channels, channel, id);
+            if channels.is_empty() {
+                self.shard_channels.remove(&slot);
+            }
+        }
+    }
+
+    fn remove(map: &mut HashMap<String, HashMap<u64, Subscriber>>, name: &str, id: u64) {
+        if let Some(subscribers) = map.get_mut(name) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                map.remove(name);
+            }
+        }
+    }
+
+    /// Delivers `message` to every subscriber of `channel`, directly or via a
+    /// matching pattern, returning how many received it.
+    pub(crate) fn publish(&self, channel: &str, message: &str) -> usize {
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.channels.get(channel) {
+            let msg = Self::message(vec![
+                "message".to_string(),
+                channel.to_string(),
+                message.to_string(),
+            ]);
+            for subscriber in subscribers.values() {
+                if subscriber.send(msg.clone()).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+
+        for (pattern, subscribers) in self.patterns.iter() {
+            if !Pattern::new(pattern).is_ok_and(|ptn| ptn.matches(channel)) {
+                continue;
+            }
+
+            let msg = Self::message(vec![
+                "pmessage".to_string(),
+                pattern.to_string(),
+                channel.to_string(),
+                message.to_string(),
+            ]);
+            for subscriber in subscribers.values() {
+                if subscriber.send(msg.clone()).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+
+    /// Delivers `message` to the subscribers of shard channel `channel`.
+    pub(crate) fn spublish(&self, channel: &str, message: &str) -> usize {
+        let slot = slot::key_hash_slot(channel.as_bytes());
+        let subscribers = match self
+            .shard_channels
+            .get(&slot)
+            .and_then(|channels| channels.get(channel))
+        {
+            Some(subscribers) => subscribers,
+            None => return 0,
+        };
+
+        let msg = Self::message(vec![
+            "smessage".to_string(),
+            channel.to_string(),
+            message.to_string(),
+        ]);
+
+        subscribers
+            .values()
+            .filter(|subscriber| subscriber.send(msg.clone()).is_ok())
+            .count()
+    }
+
+    /// Out-of-band message, sent as a push under RESP3.
+    fn message(parts: Vec<String>) -> Resp {
+        Resp::Push(parts.into_iter().map(Resp::bulk).collect())
+    }
+
+    /// Channels with at least one subscriber, optionally filtered by a glob.
+    pub(crate) fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        Self::filter_names(self.channels.keys(), pattern)
+    }
+
+    pub(crate) fn shard_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        let names = self
+            .shard_channels
+            .values()
+            .flat_map(|channels| channels.keys());
+
+        Self::filter_names(names, pattern)
+    }
+
+    fn filter_names<'a>(
+        names: impl Iterator<Item = &'a String>,
+        pattern: Option<&str>,
+    ) -> Vec<String> {
+        let ptn = match pattern.map(Pattern::new) {
+            Some(Ok(ptn)) => Some(ptn),
+            Some(Err(_)) => return vec![],
+            None => None,
+        };
+
+        names
+            .filter(|ch| ptn.as_ref().is_none_or(|ptn| ptn.matches(ch)))
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn numsub(&self, channel: &str) -> usize {
+        self.channels.get(channel).map_or(0, |subs| subs.len())
+    }
+
+    pub(crate) fn shard_numsub(&self, channel: &str) -> usize {
+        let slot = slot::key_hash_slot(channel.as_bytes());
+
+        self.shard_channels
+            .get(&slot)
+            .and_then(|channels| channels.get(channel))
+            .map_or(0, |subs| subs.len())
+    }
+
+    pub(crate) fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+
+use super::random_id;
+
+/// Bytes of the replication stream kept in the backlog, as
+/// `repl-backlog-size` defaults to.
+pub(crate) const BACKLOG_SIZE: usize = 1024 * 1024;
+
+/// Position in the replication stream, the same on both ends of it: a
+/// master advances the offset as it sends writes to its replicas, and a
+/// replica as it applies them.
+#[derive(Debug)]
+pub(crate) struct Replication {
+    /// Names the history the offset counts bytes of.
+    pub(crate) id: String,
+    /// Previous ID of the history, or all zeros if it had none.
+    pub(crate) id2: String,
+    /// Offset up to which `id2` is valid, or -1.
+    pub(crate) second_offset: i64,
+    pub(crate) offset: usize,
+    /// Most recent bytes of the stream, kept once a replica has attached.
+    backlog: Option<Backlog>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self {
+            id: random_id(),
+            id2: "0".repeat(40),
+            second_offset: -1,
+            offset: 0,
+            backlog: None,
+        }
+    }
+}
+
+impl Replication {
+    /// Appends `bytes` to the stream this server sends its replicas.
+    pub(crate) fn feed(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len();
+        if let Some(backlog) = &mut self.backlog {
+            backlog.push(bytes);
+        }
+    }
+
+    /// Starts keeping the backlog, if it isn't already.
+    pub(crate) fn create_backlog(&mut self) {
+        self.backlog.get_or_insert_with(Backlog::default);
+    }
+
+    /// Takes over the master's history after a full resync.
+    pub(crate) fn resync(&mut self, id: &str, offset: usize) {
+        *self = Self {
+            id: id.to_string(),
+            offset,
+            ..Self::default()
+        };
+    }
+
+    /// Offset of the first byte in the backlog and how many it holds, if
+    /// the backlog is active.
+    pub(crate) fn backlog(&self) -> Option<(usize, usize)> {
+        let len = self.backlog.as_ref()?.data.len();
+
+        Some((self.offset - len + 1, len))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Backlog {
+    data: VecDeque<u8>,
+}
+
+impl Backlog {
+    /// Appends `bytes`, dropping the oldest ones past `BACKLOG_SIZE`.
+    fn push(&mut self, bytes: &[u8]) {
+        let bytes = &bytes[bytes.len().saturating_sub(BACKLOG_SIZE)..];
+        let overflow = (self.data.len() + bytes.len()).saturating_sub(BACKLOG_SIZE);
+        self.data.drain(..overflow);
+        self.data.extend(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlog_keeps_latest_bytes() {
+        let mut replication = Replication::default();
+        assert_eq!(replication.id.len(), 40);
+        replication.feed(b"before");
+        assert_eq!(replication.backlog(), None);
+
+        replication.create_backlog();
+        replication.feed(b"abc");
+        assert_eq!(replication.offset, 9);
+        assert_eq!(replication.backlog(), Some((7, 3)));
+
+        replication.feed(&vec![b'x'; BACKLOG_SIZE]);
+        assert_eq!(
+            replication.backlog(),
+            Some((10, BACKLOG_SIZE)),
+            "oldest bytes are dropped"
+        );
+
+        replication.resync("a".repeat(40).as_str(), 100);
+        assert_eq!(replication.offset, 100);
+        assert_eq!(replication.backlog(), None);
+    }
+}
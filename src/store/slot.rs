@@ -0,0 +1,60 @@
+//! Redis Cluster key hashing, so keys and shard channels can be routed by
+//! slot.
+
+pub(crate) const SLOTS: u16 = 16384;
+
+/// CRC16-CCITT (XMODEM), the checksum Redis Cluster hashes keys with.
+fn crc16(buf: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for &byte in buf {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Slot owning `key`. Only the part inside the first non-empty `{...}` hash
+/// tag is hashed, so related keys can be forced into the same slot.
+pub(crate) fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = match key.iter().position(|&b| b == b'{') {
+        Some(start) => match key[start + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[start + 1..start + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+
+    crc16(hashed) & (SLOTS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn test_key_hash_slot() {
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"user1000")
+        );
+    }
+
+    #[test]
+    fn test_empty_hash_tag_hashes_whole_key() {
+        assert_eq!(key_hash_slot(b"{}foo"), crc16(b"{}foo") & (SLOTS - 1));
+    }
+}
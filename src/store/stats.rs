@@ -0,0 +1,150 @@
+use std::{collections::BTreeMap, time::Duration};
+
+/// Distinct error codes tracked before the rest are lumped together, as
+/// redis-server caps errorstats to keep bogus codes from growing it.
+const MAX_ERROR_CODES: usize = 128;
+
+/// Counters INFO reports, cleared by CONFIG RESETSTAT.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    pub(crate) connections_received: u64,
+    pub(crate) commands_processed: u64,
+    /// Connections refused, such as by protected mode.
+    pub(crate) rejected_connections: u64,
+    pub(crate) expired_keys: u64,
+    pub(crate) keyspace_hits: u64,
+    pub(crate) keyspace_misses: u64,
+    /// Replicas sent a full snapshot by PSYNC.
+    pub(crate) sync_full: u64,
+    pub(crate) error_replies: u64,
+    /// Error replies per code, such as `ERR` or `WRONGTYPE`.
+    pub(crate) errors: BTreeMap<String, u64>,
+    /// Per command, by full name such as `config|get`.
+    pub(crate) commands: BTreeMap<&'static str, CommandStats>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CommandStats {
+    pub(crate) calls: u64,
+    pub(crate) usec: u64,
+    /// Calls refused before running, like for NOAUTH or NOPERM.
+    pub(crate) rejected_calls: u64,
+    /// Calls that ran and replied with an error.
+    pub(crate) failed_calls: u64,
+    pub(crate) latency: Histogram,
+}
+
+impl Stats {
+    pub(crate) fn record_call(&mut self, name: &'static str, elapsed: Duration, failed: bool) {
+        self.commands_processed += 1;
+
+        let stats = self.commands.entry(name).or_default();
+        stats.calls += 1;
+        stats.usec += elapsed.as_micros() as u64;
+        stats.failed_calls += u64::from(failed);
+        stats.latency.record(elapsed.as_nanos() as u64);
+    }
+
+    pub(crate) fn record_rejected(&mut self, name: &'static str) {
+        self.commands.entry(name).or_default().rejected_calls += 1;
+    }
+
+    pub(crate) fn record_error(&mut self, code: &str) {
+        self.error_replies += 1;
+
+        if self.errors.len() < MAX_ERROR_CODES || self.errors.contains_key(code) {
+            *self.errors.entry(code.to_string()).or_default() += 1;
+        }
+    }
+}
+
+/// Sub-buckets per power of two; three bits of precision keep each
+/// percentile within 12.5% of the true value.
+const SUB_BUCKETS: u64 = 8;
+
+/// Call durations in nanoseconds, bucketed on a log scale like an HDR
+/// histogram, so latency percentiles take constant memory.
+#[derive(Debug, Default)]
+pub(crate) struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, value: u64) {
+        let index = Self::bucket(value);
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+        self.count += 1;
+    }
+
+    /// Smallest recorded value that `percentile` percent of the values are
+    /// at or below, rounded up to its bucket's upper bound.
+    pub(crate) fn percentile(&self, percentile: f64) -> u64 {
+        let target = ((self.count as f64 * percentile / 100.0).ceil() as u64).max(1);
+
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Self::upper_bound(index);
+            }
+        }
+
+        0
+    }
+
+    /// Values below `2 * SUB_BUCKETS` have a bucket each; above that every
+    /// power of two is split into `SUB_BUCKETS` buckets.
+    fn bucket(value: u64) -> usize {
+        if value < 2 * SUB_BUCKETS {
+            return value as usize;
+        }
+
+        let exponent = u64::from(63 - value.leading_zeros());
+        let shift = exponent - SUB_BUCKETS.trailing_zeros() as u64;
+        let sub = (value >> shift) - SUB_BUCKETS;
+
+        (2 * SUB_BUCKETS + (shift - 1) * SUB_BUCKETS + sub) as usize
+    }
+
+    fn upper_bound(index: usize) -> u64 {
+        let index = index as u64;
+        if index < 2 * SUB_BUCKETS {
+            return index;
+        }
+
+        let shift = (index - 2 * SUB_BUCKETS) / SUB_BUCKETS + 1;
+        let sub = (index - 2 * SUB_BUCKETS) % SUB_BUCKETS;
+
+        ((SUB_BUCKETS + sub + 1) << shift) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = Histogram::default();
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+
+        for (percentile, expected) in [(50.0, 500), (99.0, 990), (100.0, 1000)] {
+            let value = histogram.percentile(percentile);
+            assert!(
+                value >= expected && value <= expected + expected / 8,
+                "p{} = {}",
+                percentile,
+                value
+            );
+        }
+        assert_eq!(Histogram::default().percentile(50.0), 0);
+        assert_eq!(Histogram::upper_bound(Histogram::bucket(7)), 7);
+        assert_eq!(Histogram::upper_bound(Histogram::bucket(16)), 17);
+    }
+}